@@ -0,0 +1,65 @@
+use rocket::{get, routes, serde::json::Json, Route, State};
+
+use super::errors::MauveServeError;
+use crate::backend::{Backend, BackendState, BackendStats};
+
+pub fn routes() -> Vec<Route> {
+    routes![status, stats]
+}
+
+/// Get the full backend status, including a checksum of every tree. This is expensive.
+#[get("/status")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all))]
+pub async fn status(backend: &State<Backend>) -> Result<Json<BackendState>, MauveServeError> {
+    Ok(Json(backend.status()?))
+}
+
+/// Get cheap operational stats suitable for frequent scraping
+#[get("/stats")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all))]
+pub async fn stats(backend: &State<Backend>) -> Result<Json<BackendStats>, MauveServeError> {
+    Ok(Json(backend.stats()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{http::Status, local::asynchronous::Client};
+
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::{Backend, BackendStats},
+    };
+
+    #[rocket::async_test]
+    async fn test_stats() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        backend.get_collection("one")?;
+        backend.get_collection("two")?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client.get("/v1/backend/stats").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let stats: BackendStats = res.into_json().await.unwrap();
+        assert_eq!(stats.collections, 2);
+        assert!(!stats.recovered);
+
+        // Give the collection indexers time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let res = client.get("/v1/backend/status").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let status: serde_json::Value = res.into_json().await.unwrap();
+        let indexers = status["indexers"].as_array().unwrap();
+        assert_eq!(indexers.len(), 2);
+        for indexer in indexers {
+            assert_eq!(indexer["watched"], true);
+            assert_eq!(indexer["queued"], 0);
+            assert!(indexer["last_alive"].as_u64().is_some());
+        }
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use rocket::{
+    get,
+    http::{ContentType, Status},
+    post,
+    response::stream::{stream, ByteStream},
+    routes,
+    serde::json::Json,
+    Route, State,
+};
+use serde::Serialize;
+
+use super::errors::MauveServeError;
+use crate::{backend::Backend, config::AppConfig};
+
+pub fn routes() -> Vec<Route> {
+    routes![backup, backup_to_s3]
+}
+
+#[derive(Serialize)]
+pub struct S3Backup {
+    pub key: String,
+}
+
+/// Stream an export of the whole backend, optionally resuming after a
+/// `<tree>:<hex key>` position
+#[get("/_backup?<resume_after>")]
+pub async fn backup(
+    backend: &State<Backend>,
+    resume_after: Option<&str>,
+) -> Result<(ContentType, ByteStream![Vec<u8>]), MauveServeError> {
+    // `#[instrument]` can't wrap an `impl Trait` return, so enter the span by hand
+    #[cfg(feature = "otel")]
+    let _span = tracing::info_span!("backup", resume_after).entered();
+    let export = match resume_after {
+        Some(position) => backend.resume_export(position)?,
+        None => backend.export_stream(),
+    };
+    let stream = ByteStream(stream! {
+        for frame in export {
+            match frame {
+                Ok(frame) => yield frame,
+                Err(e) => {
+                    // The status is already sent, so the best we can do is cut the stream short
+                    log::error!("backup export failed {e}");
+                    break;
+                }
+            }
+        }
+    });
+    Ok((ContentType::Binary, stream))
+}
+
+/// Upload an export of the whole backend to the configured S3 bucket
+#[post("/_backup/s3")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all))]
+pub async fn backup_to_s3(
+    backend: &State<Backend>,
+    config: &State<AppConfig>,
+) -> Result<Json<S3Backup>, MauveServeError> {
+    let s3 = config.mauve.s3_backup.as_ref().ok_or(MauveServeError::new(
+        Status::BadRequest,
+        "BackupNotConfigured",
+        "S3 backups are not configured",
+    ))?;
+    let key = backend.backup_to_s3(s3).await?;
+    Ok(Json(S3Backup { key }))
+}
@@ -0,0 +1,657 @@
+use rocket::{
+    delete, get, head,
+    http::{ContentType, Status},
+    patch, post, put,
+    response::{
+        self,
+        stream::{stream, TextStream},
+        Responder, Response,
+    },
+    routes,
+    serde::json::Json,
+    Either, Request, Route, State,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::errors::{MauveServeError, MethodNotAllowed};
+use crate::{
+    backend::Backend,
+    changes::ChangesPage,
+    collection::{CollectionStats, ObjectsPage},
+    errors::MauveError,
+    indexer::{self, DeadLetter, ReplayReport},
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_collections,
+        list_objects,
+        head_collection,
+        collection_stats,
+        get_limits,
+        set_limits,
+        list_dead_letters,
+        replay_dead_letters,
+        copy_collection,
+        list_changes,
+        create_collection,
+        ensure_collection,
+        delete_collection,
+        patch_collection
+    ]
+}
+
+/// Object names returned per page when the request asks for `after` without a limit
+const DEFAULT_OBJECTS_LIMIT: usize = 1000;
+
+/// Changes returned per page when the request doesn't give a limit
+const DEFAULT_CHANGES_LIMIT: usize = 1000;
+
+/// Methods supported on `/<collection>`
+const COLLECTION_METHODS: &str = "GET, HEAD, POST, PUT, DELETE";
+
+#[derive(Serialize, Deserialize)]
+pub struct CollectionCopy {
+    pub collection: String,
+    pub objects: usize,
+}
+
+/// Limits on a collection's objects
+#[derive(Serialize, Deserialize)]
+pub struct CollectionLimits {
+    /// The largest object in MB, or `None` to use the configured limit
+    pub max_object_size_mb: Option<u64>,
+}
+
+/// What a collection delete removed, or would remove in a dry run
+#[derive(Serialize, Deserialize)]
+pub struct CollectionDeletion {
+    pub collection: String,
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub stats: CollectionStats,
+}
+
+/// Stream a JSON array of strings one element at a time, so memory use does not grow
+/// with the number of items.
+fn json_array(items: impl Iterator<Item = String> + Send + 'static) -> TextStream![String] {
+    TextStream(stream! {
+        yield "[".to_string();
+        for (n, item) in items.enumerate() {
+            if n > 0 {
+                yield ",".to_string();
+            }
+            yield serde_json::to_string(&item).unwrap_or_default();
+        }
+        yield "]".to_string();
+    })
+}
+
+/// List all collections
+#[get("/")]
+pub async fn list_collections(
+    backend: &State<Backend>,
+) -> Result<(ContentType, TextStream![String]), MauveServeError> {
+    // `#[instrument]` can't wrap an `impl Trait` return, so enter the span by hand
+    #[cfg(feature = "otel")]
+    let _span = tracing::info_span!("list_collections").entered();
+    let collections = backend.list_collections()?;
+    Ok((ContentType::JSON, json_array(collections)))
+}
+
+/// List the objects in a collection, optionally filtered by a key prefix
+///
+/// With `after` or `limit` the names come back a page at a time, as an `ObjectsPage` whose
+/// `cursor` is passed back as `after` for the next page. Otherwise every name is streamed as
+/// one JSON array.
+#[get("/<collection>?<prefix>&<after>&<limit>")]
+pub async fn list_objects(
+    backend: &State<Backend>,
+    collection: &str,
+    prefix: Option<&str>,
+    after: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Either<(ContentType, TextStream![String]), Json<ObjectsPage>>, MauveServeError> {
+    #[cfg(feature = "otel")]
+    let _span = tracing::info_span!("list_objects", collection, prefix).entered();
+    let collection = backend.find_collection(collection)?;
+    let prefix = prefix.unwrap_or_default();
+    if after.is_some() || limit.is_some() {
+        let limit = limit.unwrap_or(DEFAULT_OBJECTS_LIMIT);
+        let page = collection.list_objects_paginated(prefix, after, limit)?;
+        return Ok(Either::Right(Json(page)));
+    }
+    let objects = collection.list_objects(prefix)?;
+    Ok(Either::Left((ContentType::JSON, json_array(objects))))
+}
+
+/// A collection's size as response headers, with no body
+pub struct CollectionHead {
+    objects: u64,
+    bytes: u64,
+}
+
+impl<'r> Responder<'r, 'static> for CollectionHead {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .raw_header("x-mauve-object-count", self.objects.to_string())
+            .raw_header("x-mauve-total-bytes", self.bytes.to_string())
+            .ok()
+    }
+}
+
+/// Check that a collection exists and get its size, answering 404 rather than creating it
+/// if it doesn't exist. The size comes from the collection's counters, so no object is read.
+#[head("/<collection>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn head_collection(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<CollectionHead, MauveServeError> {
+    let (objects, bytes) = backend.find_collection(collection)?.counts()?;
+    Ok(CollectionHead { objects, bytes })
+}
+
+/// Count a collection's objects, their total size and its distinct labels. The objects and
+/// bytes are read from counters, and only the label index is walked.
+#[get("/<collection>/stats")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn collection_stats(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Json<CollectionStats>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(collection.stats()?))
+}
+
+/// Get the limits in effect for a collection's objects
+#[get("/<collection>/_limits")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn get_limits(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Json<CollectionLimits>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(CollectionLimits {
+        max_object_size_mb: Some(collection.max_object_size_mb()?),
+    }))
+}
+
+/// Set a collection's own limits, overriding the config. A `null` limit goes back to the
+/// configured one.
+#[put("/<collection>/_limits", data = "<limits>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn set_limits(
+    backend: &State<Backend>,
+    collection: &str,
+    limits: Json<CollectionLimits>,
+) -> Result<Json<CollectionLimits>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    collection.set_max_object_size_mb(limits.max_object_size_mb)?;
+    Ok(Json(CollectionLimits {
+        max_object_size_mb: Some(collection.max_object_size_mb()?),
+    }))
+}
+
+/// List the index events that failed for objects in a collection
+#[get("/<collection>/_dlq")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn list_dead_letters(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Json<Vec<DeadLetter>>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(collection.list_dead_letters()?))
+}
+
+/// Retry the failed index events for a collection
+#[post("/<collection>/_dlq/replay")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn replay_dead_letters(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Json<ReplayReport>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(indexer::replay_dead_letters(collection)?))
+}
+
+/// Copy a collection's objects and metadata into another collection
+#[post("/<collection>/_copy?<to>&<overwrite>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, to = %to)))]
+pub async fn copy_collection(
+    backend: &State<Backend>,
+    collection: &str,
+    to: &str,
+    overwrite: Option<bool>,
+) -> Result<Json<CollectionCopy>, MauveServeError> {
+    let objects = backend.copy_collection(collection, to, overwrite.unwrap_or_default())?;
+    Ok(Json(CollectionCopy {
+        collection: to.to_string(),
+        objects,
+    }))
+}
+
+/// List the objects written or deleted in a collection after `since` (milliseconds since the
+/// epoch), oldest first. Deleted objects are included as tombstones. Pass the returned cursor
+/// back to get the next page.
+#[get("/<collection>/_changes?<since>&<limit>&<cursor>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn list_changes(
+    backend: &State<Backend>,
+    collection: &str,
+    since: Option<u64>,
+    limit: Option<usize>,
+    cursor: Option<&str>,
+) -> Result<Json<ChangesPage>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(collection.list_changes(
+        since.unwrap_or_default(),
+        cursor,
+        limit.unwrap_or(DEFAULT_CHANGES_LIMIT),
+    )?))
+}
+
+/// Create a collection, failing with 409 Conflict if it already exists
+#[post("/<collection>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn create_collection(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Status, MauveServeError> {
+    backend.create_collection(collection)?;
+    Ok(Status::Created)
+}
+
+/// Make sure a collection exists. This is idempotent, answering 201 Created if the
+/// collection was created and 200 OK if it already existed.
+#[put("/<collection>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn ensure_collection(
+    backend: &State<Backend>,
+    collection: &str,
+) -> Result<Status, MauveServeError> {
+    match backend.ensure_collection(collection)? {
+        true => Ok(Status::Created),
+        false => Ok(Status::Ok),
+    }
+}
+
+/// Delete a collection and everything in it. This cannot be undone.
+///
+/// The collection's name must be echoed back as `?confirm=<name>`. With `?dry_run=true`
+/// nothing is deleted, and the response reports what would have been.
+#[delete("/<collection>?<dry_run>&<confirm>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn delete_collection(
+    backend: &State<Backend>,
+    collection: &str,
+    dry_run: Option<bool>,
+    confirm: Option<&str>,
+) -> Result<Json<CollectionDeletion>, MauveServeError> {
+    let dry_run = dry_run.unwrap_or_default();
+    let stats = backend.find_collection(collection)?.stats()?;
+    if !dry_run {
+        if confirm != Some(collection) {
+            return Err(MauveError::DeleteNotConfirmed(collection.to_string()).into());
+        }
+        backend.delete_collection(collection)?;
+    }
+    Ok(Json(CollectionDeletion {
+        collection: collection.to_string(),
+        dry_run,
+        stats,
+    }))
+}
+
+#[patch("/<_>")]
+pub async fn patch_collection() -> MethodNotAllowed {
+    MethodNotAllowed(COLLECTION_METHODS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{http::Status, local::asynchronous::Client};
+
+    use super::{CollectionCopy, CollectionDeletion};
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::Backend,
+        changes::ChangesPage,
+        collection::{CollectionStats, ObjectsPage},
+        indexer::{DeadLetter, IndexOp, ReplayReport},
+        labels::Label,
+        meta::Metadata,
+        objects::{ObjectRef, ObjectRefs, ToFromMauve},
+    };
+
+    #[rocket::async_test]
+    async fn test_create_and_ensure_collection() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client.post("/v1/collections/fresh").dispatch().await;
+        assert_eq!(res.status(), Status::Created);
+        let res = client.post("/v1/collections/fresh").dispatch().await;
+        assert_eq!(res.status(), Status::Conflict);
+
+        let res = client.put("/v1/collections/fresh").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.put("/v1/collections/other").dispatch().await;
+        assert_eq!(res.status(), Status::Created);
+        let res = client.put("/v1/collections/other").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_list_objects_stream() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("streamed")?;
+        for name in ["a/1", "a/2", "b/1"] {
+            collection.put_object(name, name.as_bytes().to_vec(), false)?;
+        }
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client.get("/v1/collections/streamed").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let names: Vec<String> = res.into_json().await.unwrap_or_default();
+        assert_eq!(names, vec!["a/1", "a/2", "b/1"]);
+
+        let res = client
+            .get("/v1/collections/streamed?prefix=a/")
+            .dispatch()
+            .await;
+        let names: Vec<String> = res.into_json().await.unwrap_or_default();
+        assert_eq!(names, vec!["a/1", "a/2"]);
+
+        let res = client.get("/v1/collections/").dispatch().await;
+        let collections: Vec<String> = res.into_json().await.unwrap_or_default();
+        assert!(collections.contains(&"streamed".to_string()));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_list_objects_paginated() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("paged")?;
+        for name in ["a/1", "a/2", "a/3", "b/1"] {
+            collection.put_object(name, name.as_bytes().to_vec(), false)?;
+        }
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let mut names = vec![];
+        let mut uri = "/v1/collections/paged?prefix=a/&limit=2".to_string();
+        loop {
+            let res = client.get(uri.as_str()).dispatch().await;
+            assert_eq!(res.status(), Status::Ok);
+            let page: ObjectsPage = res.into_json().await.unwrap_or_default();
+            names.extend(page.objects);
+            match page.cursor {
+                Some(cursor) => {
+                    uri = format!("/v1/collections/paged?prefix=a/&limit=2&after={cursor}")
+                }
+                None => break,
+            }
+        }
+        assert_eq!(names, vec!["a/1", "a/2", "a/3"]);
+
+        let page = collection.list_objects_paginated("", Some("a/3"), 10)?;
+        assert_eq!(page.objects, vec!["b/1"]);
+        assert_eq!(page.cursor, None);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_dead_letter_replay() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("dlq")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Metadata that can't be decoded fails to index
+        collection.meta.insert("broken", b"not cbor".to_vec())?;
+        collection.put_object("broken", b"data".to_vec(), false)?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let mut letters = vec![];
+        for _ in 0..50 {
+            let res = client.get("/v1/collections/dlq/_dlq").dispatch().await;
+            assert_eq!(res.status(), Status::Ok);
+            letters = res.into_json::<Vec<DeadLetter>>().await.unwrap_or_default();
+            if !letters.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].key, "broken");
+        assert_eq!(letters[0].op, IndexOp::Insert);
+
+        // Fix the metadata and replay
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("fixed", "yes"));
+        collection.meta.insert("broken", meta.to_object()?)?;
+        let res = client
+            .post("/v1/collections/dlq/_dlq/replay")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let report: ReplayReport = res.into_json().await.unwrap_or_default();
+        assert_eq!((report.replayed, report.failed), (1, 0));
+        assert!(collection.list_dead_letters()?.is_empty());
+        assert!(collection.index_fwd.contains_key("fixed=yes")?);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_copy_collection() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let prod = backend.get_collection("prod")?;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("env", "prod"));
+        prod.put_object_metadata("obj", meta)?;
+        prod.put_object("obj", b"data".to_vec(), false)?;
+        backend
+            .get_collection("taken")?
+            .put_object("x", vec![], false)?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .post("/v1/collections/prod/_copy?to=taken")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+
+        // Copying from a collection that doesn't exist creates neither side
+        let res = client
+            .post("/v1/collections/nothing/_copy?to=empty")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert!(!backend.collection_exists("nothing"));
+        assert!(!backend.collection_exists("empty"));
+
+        let res = client
+            .post("/v1/collections/prod/_copy?to=snapshot")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let copy: CollectionCopy = res.into_json().await.unwrap();
+        assert_eq!(copy.objects, 1);
+
+        let snapshot = backend.get_collection("snapshot")?;
+        assert_eq!(snapshot.get_object("obj")?, b"data");
+        let mut refs = None;
+        for _ in 0..50 {
+            refs = snapshot.index_fwd.get("env=prod")?;
+            if refs.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let refs = ObjectRefs::from_index(&refs.unwrap())?;
+        assert_eq!(refs.to_vec(), vec![ObjectRef::new("snapshot", "obj")]);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_list_changes() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("synced")?;
+        for name in ["a", "b", "c"] {
+            collection.put_object(name, name.as_bytes().to_vec(), false)?;
+        }
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .get("/v1/collections/synced/_changes?limit=2")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let first: ChangesPage = res.into_json().await.unwrap();
+        let keys: Vec<_> = first.changes.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, ["a", "b"]);
+        let cursor = first.cursor.unwrap();
+
+        let res = client
+            .get(format!("/v1/collections/synced/_changes?cursor={cursor}"))
+            .dispatch()
+            .await;
+        let second: ChangesPage = res.into_json().await.unwrap();
+        assert_eq!(second.changes.len(), 1);
+        assert_eq!(second.changes[0].key, "c");
+        assert!(!second.changes[0].deleted);
+
+        // A delete moves the object to the end of the feed as a tombstone
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        collection.delete_object("a")?;
+        let cursor = second.cursor.unwrap();
+        let tombstones = collection.list_changes(0, Some(&cursor), 10)?.changes;
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].key, "a");
+        assert!(tombstones[0].deleted);
+        assert_eq!(collection.list_changes(0, None, 10)?.changes.len(), 3);
+
+        // Only changes after `since` are listed
+        let since = tombstones[0].updated_at - 1;
+        let res = client
+            .get(format!("/v1/collections/synced/_changes?since={since}"))
+            .dispatch()
+            .await;
+        let recent: ChangesPage = res.into_json().await.unwrap();
+        assert_eq!(recent.changes, tombstones);
+
+        let res = client
+            .get("/v1/collections/synced/_changes?cursor=nope")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_collection() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let doomed = backend.get_collection("doomed")?;
+        doomed.put_object("a", b"one".to_vec(), false)?;
+        doomed.put_object("b", b"three".to_vec(), false)?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .delete("/v1/collections/doomed?dry_run=true")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let deletion: CollectionDeletion = res.into_json().await.unwrap();
+        assert!(deletion.dry_run);
+        assert_eq!((deletion.stats.objects, deletion.stats.bytes), (2, 8));
+        assert!(backend.list_collections()?.any(|c| c == "doomed"));
+
+        for uri in [
+            "/v1/collections/doomed",
+            "/v1/collections/doomed?confirm=other",
+        ] {
+            let res = client.delete(uri).dispatch().await;
+            assert_eq!(res.status(), Status::BadRequest);
+        }
+        assert!(backend.list_collections()?.any(|c| c == "doomed"));
+
+        let res = client
+            .delete("/v1/collections/doomed?confirm=doomed")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let deletion: CollectionDeletion = res.into_json().await.unwrap();
+        assert!(!deletion.dry_run);
+        assert_eq!(deletion.stats.objects, 2);
+        assert!(!backend.list_collections()?.any(|c| c == "doomed"));
+
+        let res = client
+            .delete("/v1/collections/doomed?confirm=doomed")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_head_collection() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let sized = backend.get_collection("sized")?;
+        sized.put_object("a", b"one".to_vec(), false)?;
+        sized.put_object("b", b"three".to_vec(), false)?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client.head("/v1/collections/sized").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("x-mauve-object-count"), Some("2"));
+        assert_eq!(res.headers().get_one("x-mauve-total-bytes"), Some("8"));
+        assert_eq!(sized.count_objects(), 2);
+
+        let res = client.get("/v1/collections/sized/stats").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let stats: CollectionStats = res.into_json().await.unwrap_or_default();
+        assert_eq!((stats.objects, stats.bytes, stats.labels), (2, 8, 0));
+
+        // The counters follow replaces and deletes, and survive reopening the collection
+        sized.put_object("a", b"seven!!".to_vec(), true)?;
+        sized.delete_object("b")?;
+        sized.delete_objects(&["b".to_string(), "c".to_string()])?;
+        assert_eq!(backend.get_collection("sized")?.counts()?, (1, 7));
+        let res = client.head("/v1/collections/sized").dispatch().await;
+        assert_eq!(res.headers().get_one("x-mauve-object-count"), Some("1"));
+        assert_eq!(res.headers().get_one("x-mauve-total-bytes"), Some("7"));
+
+        let res = client.head("/v1/collections/missing").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert!(!backend.list_collections()?.any(|c| c == "missing"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
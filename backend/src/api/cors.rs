@@ -0,0 +1,244 @@
+//! CORS
+//!
+//! With a `cors` section in the config, responses to requests from an allowed `Origin` carry
+//! the `Access-Control-*` headers browsers need to call the API from another origin, and
+//! preflight `OPTIONS` requests under `/v1` are answered with `204 No Content`.
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Method, Status},
+    options, routes, Request, Response, Route,
+};
+
+use super::objects::EXTRA_PREFIX;
+use crate::config::CorsConfig;
+
+pub fn routes() -> Vec<Route> {
+    routes![preflight]
+}
+
+/// Response headers a browser script may read besides the CORS-safelisted ones.
+/// `x-mauve-meta-<name>` headers are exposed as they are sent.
+const EXPOSED_HEADERS: &[&str] = &[
+    "Accept-Ranges",
+    "Allow",
+    "Content-Encoding",
+    "Content-Language",
+    "Content-Range",
+    "ETag",
+    "x-mauve-labels",
+    "x-mauve-offsets-inclusive",
+    "x-mauve-created-at",
+    "x-mauve-updated-at",
+    "x-mauve-size",
+    "x-mauve-object-count",
+    "x-mauve-total-bytes",
+];
+
+/// Answer a preflight request. The CORS headers are added by the `Cors` fairing.
+#[options("/<_..>")]
+pub async fn preflight() -> Status {
+    Status::NoContent
+}
+
+/// Fairing adding CORS headers to the responses to allowed origins
+pub struct Cors(pub CorsConfig);
+
+impl Cors {
+    /// The `Access-Control-Allow-Origin` to answer `origin` with, if it's allowed
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        let origins = &self.0.allowed_origins;
+        if origins.iter().any(|allowed| allowed == "*") {
+            return Some("*");
+        }
+        origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+            .then_some(origin)
+    }
+
+    /// The configured request headers, and any metadata headers the preflight asks for
+    fn allow_headers(&self, requested: &str) -> String {
+        let meta = requested
+            .split(',')
+            .map(str::trim)
+            .filter(|header| header.to_ascii_lowercase().starts_with(EXTRA_PREFIX));
+        self.0
+            .allowed_headers
+            .iter()
+            .map(String::as_str)
+            .chain(meta)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        // Unless any origin is allowed, the CORS headers depend on the request's origin, so
+        // caches must keep the responses to other origins, or to none, apart
+        if !self.0.allowed_origins.iter().any(|allowed| allowed == "*") {
+            res.adjoin_raw_header("Vary", "Origin");
+        }
+        let Some(origin) = req.headers().get_one("Origin") else {
+            return;
+        };
+        let Some(allowed) = self.allow_origin(origin) else {
+            return;
+        };
+        res.set_raw_header("Access-Control-Allow-Origin", allowed.to_string());
+
+        let preflight = req.method() == Method::Options
+            && req.headers().contains("Access-Control-Request-Method");
+        if preflight {
+            res.set_raw_header(
+                "Access-Control-Allow-Methods",
+                self.0.allowed_methods.join(", "),
+            );
+            let requested = req
+                .headers()
+                .get_one("Access-Control-Request-Headers")
+                .unwrap_or_default();
+            res.set_raw_header(
+                "Access-Control-Allow-Headers",
+                self.allow_headers(requested),
+            );
+            if let Some(max_age) = self.0.max_age_secs {
+                res.set_raw_header("Access-Control-Max-Age", max_age.to_string());
+            }
+            return;
+        }
+
+        let meta: Vec<_> = res
+            .headers()
+            .iter()
+            .map(|header| header.name().to_string())
+            .filter(|name| name.to_ascii_lowercase().starts_with(EXTRA_PREFIX))
+            .collect();
+        let exposed = EXPOSED_HEADERS
+            .iter()
+            .copied()
+            .chain(meta.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(", ");
+        res.set_raw_header("Access-Control-Expose-Headers", exposed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        http::{Header, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::Backend,
+        config::CorsConfig,
+    };
+
+    #[rocket::async_test]
+    async fn test_cors() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.cors = Some(serde_json::from_str(
+            r#"{"allowed_origins": ["https://app.example.com"], "max_age_secs": 60}"#,
+        )?);
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let origin = Header::new("Origin", "https://app.example.com");
+
+        let res = client
+            .options("/v1/objects/things/one")
+            .header(origin.clone())
+            .header(Header::new("Access-Control-Request-Method", "PUT"))
+            .header(Header::new(
+                "Access-Control-Request-Headers",
+                "x-mauve-labels, x-mauve-meta-owner",
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NoContent);
+        let headers = res.headers();
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        let methods = headers
+            .get_one("Access-Control-Allow-Methods")
+            .unwrap_or_default();
+        assert!(methods.contains("PUT") && methods.contains("DELETE"));
+        let allowed = headers
+            .get_one("Access-Control-Allow-Headers")
+            .unwrap_or_default();
+        assert!(allowed.contains("x-mauve-labels") && allowed.contains("x-mauve-meta-owner"));
+        assert_eq!(headers.get_one("Access-Control-Max-Age"), Some("60"));
+
+        client
+            .put("/v1/objects/things/one")
+            .header(Header::new("x-mauve-meta-owner", "mauve"))
+            .body("hello")
+            .dispatch()
+            .await;
+        let res = client
+            .get("/v1/objects/things/one")
+            .header(origin)
+            .dispatch()
+            .await;
+        assert_eq!(
+            res.headers().get_one("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        let exposed = res
+            .headers()
+            .get_one("Access-Control-Expose-Headers")
+            .unwrap_or_default();
+        assert!(exposed.contains("ETag") && exposed.contains("x-mauve-meta-owner"));
+
+        // Other origins, and requests without one, get no CORS headers, though the response
+        // still varies by origin
+        let res = client
+            .get("/v1/objects/things/one")
+            .header(Header::new("Origin", "https://elsewhere.example.com"))
+            .dispatch()
+            .await;
+        assert!(!res.headers().contains("Access-Control-Allow-Origin"));
+        assert!(res.headers().get("Vary").any(|vary| vary == "Origin"));
+        let res = client.get("/v1/objects/things/one").dispatch().await;
+        assert!(!res.headers().contains("Access-Control-Allow-Origin"));
+        assert!(res.headers().get("Vary").any(|vary| vary == "Origin"));
+
+        // Any origin is answered with `*`
+        let mut any = test_config();
+        any.sled.path = config.sled.path.join("any");
+        any.mauve.cors = Some(CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: vec![],
+            max_age_secs: None,
+        });
+        let backend = Backend::open(any.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, any)).await?;
+        let res = client
+            .get("/v1/collections/")
+            .header(Header::new("Origin", "https://elsewhere.example.com"))
+            .dispatch()
+            .await;
+        assert_eq!(
+            res.headers().get_one("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(!res.headers().get("Vary").any(|vary| vary == "Origin"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
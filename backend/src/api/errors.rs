@@ -0,0 +1,202 @@
+use rocket::{
+    catch,
+    http::{ContentType, Header, Status},
+    response::{self, Responder},
+    Request,
+};
+use serde::{Deserialize, Serialize};
+use sled::transaction::TransactionError;
+
+use crate::errors::{CollectionError, MauveError};
+
+/// Error response for the Mauve API, sent as an `ErrorBody`
+#[derive(Debug)]
+pub struct MauveServeError {
+    pub status: Status,
+    /// Stable machine-readable name of the error, see `MauveError::code`
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// JSON body of every API error response
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub code: String,
+    pub status: u16,
+}
+
+impl MauveServeError {
+    pub fn new(status: Status, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn body(&self) -> String {
+        let body = ErrorBody {
+            error: self.message.clone(),
+            code: self.code.to_string(),
+            status: self.status.code,
+        };
+        serde_json::to_string(&body).unwrap_or_default()
+    }
+}
+
+impl<'r> Responder<'r, 'static> for MauveServeError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        (self.status, (ContentType::JSON, self.body())).respond_to(req)
+    }
+}
+
+/// Answer requests no route handled, or whose guards failed, with an `ErrorBody` like the
+/// routes' own errors
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request<'_>) -> MauveServeError {
+    let code = match status.code {
+        400 | 422 => "InvalidRequest",
+        404 => "NotFound",
+        413 => "PayloadTooLarge",
+        _ => "HttpError",
+    };
+    MauveServeError::new(status, code, status.reason_lossy())
+}
+
+/// 405 response naming the methods a path does support in its `Allow` header
+#[derive(Debug)]
+pub struct MethodNotAllowed(pub &'static str);
+
+impl<'r> Responder<'r, 'static> for MethodNotAllowed {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let message = format!(
+            "{} is not allowed here, use one of {}",
+            req.method(),
+            self.0
+        );
+        let error = MauveServeError::new(Status::MethodNotAllowed, "MethodNotAllowed", message);
+        response::Response::build_from(error.respond_to(req)?)
+            .header(Header::new("Allow", self.0))
+            .ok()
+    }
+}
+
+impl From<MauveError> for MauveServeError {
+    fn from(value: MauveError) -> Self {
+        // No catch-all, so a new variant has to be given a status here
+        let status =
+            match &value {
+                MauveError::CollectionError(e) => match e {
+                    CollectionError::CollectionNotFound | CollectionError::ObjectNotFound => {
+                        Status::NotFound
+                    }
+                    CollectionError::PutObjectExistsNoReplace
+                    | CollectionError::CollectionExists => Status::Conflict,
+                    CollectionError::NameTooLong(_) | CollectionError::KeyTooLong(_) => {
+                        Status::BadRequest
+                    }
+                },
+                MauveError::ObjectTooLarge(_) => Status::PayloadTooLarge,
+                MauveError::SegmentNotFound(_) | MauveError::OffsetNotFound(_) => {
+                    Status::RangeNotSatisfiable
+                }
+                MauveError::ContentHashMismatch(_) => Status::UnprocessableEntity,
+                MauveError::UnsupportedEncoding(_) => Status::NotAcceptable,
+                MauveError::PreconditionFailed(_) => Status::PreconditionFailed,
+                MauveError::TooManyLabels(_)
+                | MauveError::MetadataTooLarge(_)
+                | MauveError::InvalidLabel(_)
+                | MauveError::InvalidSegments(_)
+                | MauveError::InvalidCursor(_)
+                | MauveError::DeleteNotConfirmed(_)
+                | MauveError::InvalidContentHash(_)
+                | MauveError::EncodingMismatch(_)
+                | MauveError::InvalidBatch(_)
+                | MauveError::InvalidExportPosition(_) => Status::BadRequest,
+                // Transactions abort with the `MauveError` that stopped them, which is mapped
+                // as itself, so only storage failures are left here and worth retrying later
+                MauveError::SledTxError(TransactionError::Storage(_))
+                | MauveError::SignalError(_) => Status::ServiceUnavailable,
+                MauveError::SledTxError(TransactionError::Abort(_))
+                | MauveError::ConfigError(_)
+                | MauveError::RocketError(_)
+                | MauveError::Utf8Error(_)
+                | MauveError::SledError(_)
+                | MauveError::IoError(_)
+                | MauveError::HttpError(_)
+                | MauveError::ObjectStoreError(_)
+                | MauveError::SearchError(_)
+                | MauveError::BincodeError(_)
+                | MauveError::CborError(_)
+                | MauveError::JsonError(_)
+                | MauveError::Oops(_) => Status::InternalServerError,
+            };
+        Self::new(status, value.code(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use sled::transaction::TransactionError;
+
+    use super::MauveServeError;
+    use crate::errors::{CollectionError, MauveError};
+
+    #[test]
+    fn test_error_status() {
+        let cases = [
+            (
+                MauveError::InvalidLabel("=".to_string()),
+                Status::BadRequest,
+                "InvalidLabel",
+            ),
+            (
+                MauveError::CollectionError(CollectionError::ObjectNotFound),
+                Status::NotFound,
+                "ObjectNotFound",
+            ),
+            (
+                MauveError::CborError("eof".to_string()),
+                Status::InternalServerError,
+                "CborError",
+            ),
+            (
+                MauveError::BincodeError("eof".to_string()),
+                Status::InternalServerError,
+                "BincodeError",
+            ),
+            (
+                MauveError::SledTxError(TransactionError::Abort(sled::Error::Unsupported(
+                    "conflict".to_string(),
+                ))),
+                Status::InternalServerError,
+                "TransactionAborted",
+            ),
+            (
+                MauveError::from(TransactionError::Abort(MauveError::CollectionError(
+                    CollectionError::ObjectNotFound,
+                ))),
+                Status::NotFound,
+                "ObjectNotFound",
+            ),
+            (
+                MauveError::SledTxError(TransactionError::Storage(sled::Error::Unsupported(
+                    "io".to_string(),
+                ))),
+                Status::ServiceUnavailable,
+                "TransactionFailed",
+            ),
+            (
+                MauveError::SignalError("closed".to_string()),
+                Status::ServiceUnavailable,
+                "SignalError",
+            ),
+        ];
+        for (error, status, code) in cases {
+            let error = MauveServeError::from(error);
+            assert_eq!((error.status, error.code), (status, code));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! HTTP API
+//!
+//! Rocket routes exposing a `Backend` over HTTP. Every route is built on the public
+//! `Backend`/`Collection` methods, so the API never touches sled directly.
+
+pub mod backend;
+pub mod backup;
+pub mod collections;
+pub mod cors;
+pub mod errors;
+pub mod objects;
+pub mod s3;
+pub mod search;
+
+use rocket::{
+    data::{ByteUnit, Data, ToByteUnit},
+    fairing::AdHoc,
+    request::{self, FromRequest, Request},
+    Build, Rocket,
+};
+use tokio::io::AsyncReadExt;
+
+use crate::{backend::Backend, config::AppConfig, errors::MauveError};
+
+/// Bytes read from an upload at a time
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Build the Rocket instance serving the Mauve API
+///
+/// The backend and config are placed in managed state for the route handlers.
+/// Optional API surfaces are only mounted when enabled in the config.
+pub fn mauve_rocket(backend: Backend, config: AppConfig) -> Rocket<Build> {
+    let mut rocket = rocket::build()
+        .manage(backend)
+        .manage(config.clone())
+        .mount("/v1", backup::routes())
+        .mount("/v1/backend", backend::routes())
+        .mount("/v1/collections", collections::routes())
+        .mount("/v1/objects", objects::routes())
+        .mount("/v1/search", search::routes())
+        .register("/v1", rocket::catchers![errors::default_catcher])
+        // Rocket starts a graceful shutdown on SIGINT and SIGTERM
+        .attach(AdHoc::on_shutdown("Backend shutdown", |rocket| {
+            Box::pin(async move {
+                if let Some(backend) = rocket.state::<Backend>() {
+                    if let Err(e) = backend.shutdown().await {
+                        log::error!("Backend shutdown failed {e}");
+                    }
+                }
+            })
+        }));
+    if config.mauve.s3_enabled {
+        rocket = rocket.mount("/s3", s3::routes());
+    }
+    if let Some(cors) = config.mauve.cors {
+        rocket = rocket.mount("/v1", cors::routes()).attach(cors::Cors(cors));
+    }
+    rocket
+}
+
+/// The `Content-Length` of a request, if it sent one
+pub struct ContentLength(pub Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ContentLength {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let length = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse().ok());
+        request::Outcome::Success(Self(length))
+    }
+}
+
+/// Read an object body into memory, failing once more than `limit` bytes have arrived.
+///
+/// Bytes are counted as they stream in rather than trusting `Content-Length`, so uploads
+/// using `Transfer-Encoding: chunked` are accepted and an oversized upload is rejected as
+/// soon as it crosses the limit instead of after it has been fully received. A declared
+/// length over the limit is rejected before any of the body is read.
+///
+/// Sled stores each object as a single value, so the body has to be held whole to write it.
+/// The buffer is sized from `Content-Length` up front and read into directly, so a large
+/// upload isn't copied as it grows.
+pub(crate) async fn read_payload(
+    payload: Data<'_>,
+    limit: ByteUnit,
+    length: ContentLength,
+) -> Result<Vec<u8>, MauveError> {
+    if length.0.is_some_and(|length| length > limit.as_u64()) {
+        return Err(MauveError::ObjectTooLarge(limit.to_string()));
+    }
+    let mut stream = payload.open(limit + 1.bytes());
+    let mut object = Vec::with_capacity(length.0.unwrap_or_default() as usize);
+    loop {
+        object.reserve(READ_CHUNK);
+        if stream.read_buf(&mut object).await? == 0 {
+            return Ok(object);
+        }
+        if object.len() as u64 > limit.as_u64() {
+            return Err(MauveError::ObjectTooLarge(limit.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::config::AppConfig;
+
+    /// Config for a throwaway backend stored under the system temp dir
+    pub(crate) fn test_config() -> AppConfig {
+        let mut config = AppConfig::default();
+        config.sled.path =
+            std::env::temp_dir().join(format!("mauve-test-{}", rand::random::<u64>()));
+        config
+    }
+}
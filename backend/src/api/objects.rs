@@ -0,0 +1,2057 @@
+use std::{collections::HashSet, io::Cursor};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use rocket::{
+    data::{Data, ToByteUnit},
+    delete, get, head,
+    http::{Header, Status},
+    patch, post, put,
+    request::{self, FromRequest, Request},
+    response::{self, Responder, Response},
+    routes,
+    serde::json::Json,
+    Either, Route, State,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    errors::{MauveServeError, MethodNotAllowed},
+    read_payload, ContentLength,
+};
+use crate::{
+    backend::Backend,
+    collection::{BatchObject, Collection},
+    config::{AppConfig, MauveConfig},
+    encoding,
+    errors::{CollectionError, MauveError},
+    labels::Label,
+    meta::{etag, parse_content_hash, Metadata, ObjectWithMetadata},
+    objects::ObjectRef,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        get_object,
+        head_object,
+        post_object,
+        put_object,
+        delete_object,
+        object_size,
+        patch_object,
+        batch_delete,
+        batch_put,
+        object_labels,
+        copy_object,
+        move_object
+    ]
+}
+
+/// Prefix of the headers carrying user-defined metadata fields
+pub(crate) const EXTRA_PREFIX: &str = "x-mauve-meta-";
+
+/// Methods supported on `/<collection>/<name>`
+const OBJECT_METHODS: &str = "GET, HEAD, POST, PUT, DELETE";
+
+#[derive(Serialize)]
+pub struct ObjectSize {
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchDeleted {
+    pub name: String,
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BatchPut {
+    pub name: String,
+    pub stored: bool,
+    /// Why the object wasn't stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// One line of a batch upload. Labels are `name=value` strings taken as-is, like the
+/// `x-mauve-label` header, and the body is base64.
+#[derive(Deserialize)]
+struct BatchLine {
+    name: String,
+    body: String,
+    #[serde(default)]
+    labels: Vec<String>,
+    #[serde(default)]
+    content_type: String,
+    #[serde(default)]
+    content_encoding: String,
+    #[serde(default)]
+    content_language: String,
+}
+
+/// Where to copy or move an object to
+#[derive(Serialize, Deserialize)]
+pub struct ObjectDestination {
+    pub collection: String,
+    pub name: String,
+    /// Replace an object already at the destination
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Object metadata parsed from request headers
+///
+/// Labels are sent either comma-joined as `x-mauve-labels: name=value,name=value`, with
+/// each name and value encoded by `Label::encode`, or one per repeated `x-mauve-label:
+/// name=value` header taken as-is. The segments of an object assembled from parts are sent
+/// as `x-mauve-offsets-inclusive: start-end,start-end`, along with any named ranges written
+/// `name=start-end`. An upload can be verified by sending
+/// its digest as `x-mauve-content-hash: md5:<hex>`. Any `x-mauve-meta-<name>` header is kept
+/// as a user-defined field and served back with the object.
+pub struct RequestMetadata(Metadata);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestMetadata {
+    type Error = MauveError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        let mut meta = Metadata {
+            content_type: headers
+                .get_one("Content-Type")
+                .unwrap_or_default()
+                .to_string(),
+            content_encoding: headers
+                .get_one("Content-Encoding")
+                .unwrap_or_default()
+                .to_string(),
+            content_language: headers
+                .get_one("Content-Language")
+                .unwrap_or_default()
+                .to_string(),
+            ..Default::default()
+        };
+
+        let joined = headers
+            .get("x-mauve-labels")
+            .flat_map(|labels| labels.split(','))
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(Label::decode);
+        let repeated = headers.get("x-mauve-label").map(str::parse::<Label>);
+        for label in joined.chain(repeated) {
+            match label {
+                Ok(label) => {
+                    meta.labels.insert(label);
+                }
+                Err(e) => return request::Outcome::Error((Status::BadRequest, e)),
+            }
+        }
+
+        for header in headers.iter() {
+            let name = header.name().as_str().to_ascii_lowercase();
+            if let Some(name) = name.strip_prefix(EXTRA_PREFIX) {
+                meta.extra
+                    .insert(name.to_string(), header.value().to_string());
+            }
+        }
+
+        if let Some(offsets) = headers.get_one("x-mauve-offsets-inclusive") {
+            match meta.set_offsets(offsets) {
+                Ok(()) => {}
+                Err(e) => return request::Outcome::Error((Status::BadRequest, e)),
+            }
+        }
+
+        if let Some(hash) = headers.get_one("x-mauve-content-hash") {
+            match parse_content_hash(hash) {
+                Ok(hash) => meta.content_hash = hash,
+                Err(e) => return request::Outcome::Error((Status::BadRequest, e)),
+            }
+        }
+
+        if let Some(config) = req.rocket().state::<AppConfig>() {
+            meta.normalize(&config.mauve);
+            if let Err(e) = meta.check_limits(&config.mauve) {
+                return request::Outcome::Error((Status::BadRequest, e));
+            }
+        }
+
+        request::Outcome::Success(Self(meta))
+    }
+}
+
+/// The preconditions of a write: an `If-Match` header checked against the stored object's
+/// `ETag`, or `x-mauve-expect-absent: true` for a write that may only create the object
+pub struct Precondition {
+    if_match: Option<String>,
+    expect_absent: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Precondition {
+    type Error = MauveError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        request::Outcome::Success(Self {
+            if_match: headers.get_one("If-Match").map(str::to_string),
+            expect_absent: headers
+                .get_one("x-mauve-expect-absent")
+                .is_some_and(|v| v.trim().eq_ignore_ascii_case("true")),
+        })
+    }
+}
+
+impl Precondition {
+    /// How a write has to go: swapping out the object `If-Match` named, creating it only
+    /// while absent, or replacing whatever is there when there are no preconditions. Fails
+    /// with `PreconditionFailed` if the stored object doesn't match already.
+    fn write_mode(&self, collection: &Collection, name: &str) -> Result<WriteMode, MauveError> {
+        let failed = |why: &str| Err(MauveError::PreconditionFailed(format!("{name} {why}")));
+        let Some(if_match) = &self.if_match else {
+            return Ok(match self.expect_absent {
+                true => WriteMode::Swap(None),
+                false => WriteMode::Replace,
+            });
+        };
+        if self.expect_absent {
+            return failed("can't match If-Match and be absent");
+        }
+        let object = match collection.get_object(name) {
+            Ok(object) => object,
+            Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+                return failed("does not exist");
+            }
+            Err(e) => return Err(e),
+        };
+        let meta = object_metadata(collection, name)?;
+        let etag = match meta.content_hash.is_empty() {
+            true => etag(&object),
+            false => meta.content_hash,
+        };
+        match etag_matches(if_match, &format!("\"{etag}\""), false) {
+            true => Ok(WriteMode::Swap(Some(object))),
+            false => failed(&format!("does not match If-Match {if_match}")),
+        }
+    }
+}
+
+/// How `write_object` treats an object already stored under the name
+enum WriteMode {
+    /// Replace it
+    Replace,
+    /// Replace it only if it is still this object, or create it if this is `None`
+    Swap(Option<Vec<u8>>),
+}
+
+/// Returns `true` if a list of entity tags from `If-Match` or `If-None-Match` names `etag`
+/// or is `*`. Weak tags (`W/"..."`) only match when `weak` is set, as `If-Match` needs a
+/// strong comparison.
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(str::trim).any(|tag| {
+        let tag = match tag.strip_prefix("W/") {
+            Some(tag) if weak => tag,
+            Some(_) => return false,
+            None => tag,
+        };
+        tag == "*" || tag == etag
+    })
+}
+
+/// An object body with its metadata as response headers
+///
+/// The object's stored content hash is sent as its `ETag`, and a request whose
+/// `If-None-Match` names it gets `304 Not Modified` with no body.
+///
+/// A `Range: bytes=...` header is answered with `206 Partial Content`, and a header asking
+/// for several ranges with a `multipart/byteranges` body holding each of them. If the request
+/// also sends `If-Range`, the range is only served while it matches the object's ETag, and
+/// the whole object is sent otherwise so a resumed download starts over instead of stitching
+/// together two versions.
+///
+/// With `compress_min_bytes` set, a whole object of at least that size stored without a
+/// `Content-Encoding` is compressed for a client whose `Accept-Encoding` takes gzip or
+/// deflate. Its `ETag` is then weak, as the bytes sent aren't the stored ones.
+pub struct MauveObject {
+    pub object: ObjectWithMetadata,
+    pub compress_min_bytes: Option<usize>,
+}
+
+impl<'r> Responder<'r, 'static> for MauveObject {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let ObjectWithMetadata { object, meta } = self.object;
+        let mut res = Response::build();
+        let content_type = object_content_type(&meta);
+        // The stored hash is the MD5 of the body, computed again for objects stored without one
+        let etag = match meta.content_hash.is_empty() {
+            true => format!("\"{}\"", etag(&object)),
+            false => format!("\"{}\"", meta.content_hash),
+        };
+        object_headers(&mut res, &meta, &etag);
+
+        if let Some(if_none_match) = req.headers().get_one("If-None-Match") {
+            if etag_matches(if_none_match, &etag, true) {
+                return Response::build()
+                    .status(Status::NotModified)
+                    .raw_header("ETag", etag)
+                    .ok();
+            }
+        }
+
+        let len = object.len() as u64;
+        let range = match req.headers().get_one("If-Range") {
+            // Dates never match, as objects carry no Last-Modified
+            Some(if_range) if if_range.trim() != etag => None,
+            _ => req.headers().get_one("Range"),
+        };
+        match range.map(|range| byte_range(range, len)) {
+            Some(ByteRange::Partial(start, end)) => {
+                res.status(Status::PartialContent);
+                res.raw_header("Content-Range", format!("bytes {start}-{end}/{len}"));
+                let part = object[start as usize..=end as usize].to_vec();
+                res.sized_body(part.len(), Cursor::new(part)).ok()
+            }
+            Some(ByteRange::Multi(ranges)) => {
+                let boundary = hex::encode(rand::random::<[u8; 12]>());
+                let body = byteranges(&object, &content_type, &ranges, &boundary);
+                res.status(Status::PartialContent);
+                res.raw_header(
+                    "Content-Type",
+                    format!("multipart/byteranges; boundary={boundary}"),
+                );
+                res.sized_body(body.len(), Cursor::new(body)).ok()
+            }
+            Some(ByteRange::Unsatisfiable) => Response::build()
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{len}"))
+                .ok(),
+            Some(ByteRange::Full) | None => {
+                let compressible = match self.compress_min_bytes {
+                    Some(min) => meta.content_encoding.is_empty() && object.len() >= min,
+                    None => false,
+                };
+                if !compressible {
+                    return res.sized_body(object.len(), Cursor::new(object)).ok();
+                }
+                res.raw_header("Vary", "Accept-Encoding");
+                let accept = req.headers().get_one("Accept-Encoding").unwrap_or_default();
+                let compressed = encoding::negotiate(accept).and_then(|encoding| {
+                    Some((encoding, encoding::encode(encoding, &object).ok()?))
+                });
+                match compressed {
+                    Some((encoding, body)) => {
+                        res.raw_header("Content-Encoding", encoding);
+                        res.raw_header("ETag", format!("W/{etag}"));
+                        res.sized_body(body.len(), Cursor::new(body)).ok()
+                    }
+                    None => res.sized_body(object.len(), Cursor::new(object)).ok(),
+                }
+            }
+        }
+    }
+}
+
+/// An object's metadata as the headers a GET of it would send, with no body
+///
+/// `Content-Length` is the stored size, and the `ETag` is the stored content hash, so the
+/// object itself is never read.
+pub struct MauveObjectHead(Metadata);
+
+impl<'r> Responder<'r, 'static> for MauveObjectHead {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let meta = self.0;
+        let etag = format!("\"{}\"", meta.content_hash);
+        if let Some(if_none_match) = req.headers().get_one("If-None-Match") {
+            if etag_matches(if_none_match, &etag, true) {
+                return Response::build()
+                    .status(Status::NotModified)
+                    .raw_header("ETag", etag)
+                    .ok();
+            }
+        }
+        let mut res = Response::build();
+        object_headers(&mut res, &meta, &etag);
+        // Rocket drops the body of a HEAD response but keeps its size as the length
+        res.sized_body(meta.size as usize, Cursor::new(vec![])).ok()
+    }
+}
+
+/// `Content-Type` of an object, `application/octet-stream` if it was stored without one
+fn object_content_type(meta: &Metadata) -> String {
+    match meta.content_type.is_empty() {
+        true => "application/octet-stream".to_string(),
+        false => meta.content_type.clone(),
+    }
+}
+
+/// Set the headers describing an object that GET and HEAD both send
+fn object_headers(res: &mut response::Builder<'_>, meta: &Metadata, etag: &str) {
+    res.raw_header("Content-Type", object_content_type(meta));
+    if !meta.content_encoding.is_empty() {
+        res.raw_header("Content-Encoding", meta.content_encoding.clone());
+    }
+    if !meta.content_language.is_empty() {
+        res.raw_header("Content-Language", meta.content_language.clone());
+    }
+    if !meta.labels.is_empty() {
+        res.raw_header("x-mauve-labels", meta.label_str());
+    }
+    match meta.offsets_str() {
+        Ok(offsets) if !offsets.is_empty() => {
+            res.raw_header("x-mauve-offsets-inclusive", offsets);
+        }
+        _ => {}
+    }
+    for (name, value) in &meta.extra {
+        res.raw_header(format!("{EXTRA_PREFIX}{name}"), value.clone());
+    }
+    for (header, at) in [
+        ("x-mauve-created-at", meta.created_at),
+        ("x-mauve-updated-at", meta.updated_at),
+    ] {
+        if at != 0 {
+            res.raw_header(header, at.to_string());
+        }
+    }
+    res.raw_header("ETag", etag.to_string());
+    res.raw_header("Accept-Ranges", "bytes");
+}
+
+/// Most ranges served from one request. Asking for more gets the whole object.
+const MAX_RANGES: usize = 32;
+
+/// How to answer a `Range` header
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// The header is malformed or asks for too many ranges, so send the whole object
+    Full,
+    /// Send bytes `start..=end`
+    Partial(u64, u64),
+    /// Send each `start..=end` as a part of a `multipart/byteranges` body
+    Multi(Vec<(u64, u64)>),
+    /// Every range starts past the end of the object
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header against an object of `len` bytes. Ranges past the end of the
+/// object are dropped, and the header is only unsatisfiable if none are left.
+fn byte_range(header: &str, len: u64) -> ByteRange {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let specs: Vec<_> = specs.split(',').collect();
+    if specs.len() > MAX_RANGES {
+        return ByteRange::Full;
+    }
+    let mut ranges = vec![];
+    for spec in specs {
+        match range_spec(spec, len) {
+            Some(Some(range)) => ranges.push(range),
+            Some(None) => (),
+            None => return ByteRange::Full,
+        }
+    }
+    match ranges.as_slice() {
+        [] => ByteRange::Unsatisfiable,
+        [(start, end)] => ByteRange::Partial(*start, *end),
+        _ => ByteRange::Multi(ranges),
+    }
+}
+
+/// Parse one `start-end` of a `Range` header. Returns `None` if it's malformed, and
+/// `Some(None)` if it can't be satisfied.
+fn range_spec(spec: &str, len: u64) -> Option<Option<(u64, u64)>> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // bytes=-n is the last n bytes
+        ("", suffix) => match suffix.parse::<u64>().ok()? {
+            0 => return Some(None),
+            n => (len.saturating_sub(n), len.saturating_sub(1)),
+        },
+        (start, "") => (start.parse::<u64>().ok()?, len.saturating_sub(1)),
+        (start, end) => match (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?) {
+            (start, end) if start <= end => (start, end.min(len.saturating_sub(1))),
+            _ => return None,
+        },
+    };
+    Some((start < len).then_some((start, end)))
+}
+
+/// Build a `multipart/byteranges` body holding each range of `object`
+fn byteranges(object: &[u8], content_type: &str, ranges: &[(u64, u64)], boundary: &str) -> Vec<u8> {
+    let len = object.len();
+    let mut body = vec![];
+    for (start, end) in ranges {
+        body.extend(format!("\r\n--{boundary}\r\n").as_bytes());
+        body.extend(format!("Content-Type: {content_type}\r\n").as_bytes());
+        body.extend(format!("Content-Range: bytes {start}-{end}/{len}\r\n\r\n").as_bytes());
+        body.extend(&object[*start as usize..=*end as usize]);
+    }
+    body.extend(format!("\r\n--{boundary}--\r\n").as_bytes());
+    body
+}
+
+/// Error from creating an object
+///
+/// When the object already exists the response carries the existing object's `ETag` and
+/// `x-mauve-size`, so the client can compare and decide whether to replace it with a PUT.
+pub enum CreateError {
+    Exists { etag: String, size: u64 },
+    Other(MauveServeError),
+}
+
+impl<'r> Responder<'r, 'static> for CreateError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            CreateError::Exists { etag, size } => {
+                let error: MauveServeError =
+                    MauveError::CollectionError(CollectionError::PutObjectExistsNoReplace).into();
+                Response::build_from(error.respond_to(req)?)
+                    .header(Header::new("ETag", format!("\"{etag}\"")))
+                    .header(Header::new("x-mauve-size", size.to_string()))
+                    .ok()
+            }
+            CreateError::Other(e) => e.respond_to(req),
+        }
+    }
+}
+
+impl From<MauveError> for CreateError {
+    fn from(value: MauveError) -> Self {
+        CreateError::Other(value.into())
+    }
+}
+
+/// Get an object's metadata, treating objects stored without metadata as unlabeled
+fn object_metadata(collection: &Collection, name: &str) -> Result<Metadata, MauveError> {
+    match collection.get_object_metadata(name) {
+        Ok(meta) => Ok(meta),
+        Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+            Ok(Metadata::default())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Get an object and its metadata
+///
+/// With `?segment=<i>` only the bytes of the object's `i`th segment are returned, and with
+/// `?offset=<name>` only those of its range of that name. With `?decode=true` a gzip or zstd
+/// body is decompressed and sent without `Content-Encoding`.
+#[get("/<collection>/<name>?<segment>&<offset>&<decode>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn get_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    segment: Option<usize>,
+    offset: Option<&str>,
+    decode: Option<bool>,
+) -> Result<MauveObject, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    let mut meta = object_metadata(&collection, name)?;
+    let range = match (segment, offset) {
+        (Some(i), _) => Some((
+            meta.segments()?.get(i).copied(),
+            MauveError::SegmentNotFound(i),
+        )),
+        (None, Some(offset)) => Some((
+            meta.offsets()?.0.get(offset).copied(),
+            MauveError::OffsetNotFound(offset.to_string()),
+        )),
+        (None, None) => None,
+    };
+    let mut object = match range {
+        Some((range, not_found)) => {
+            let part = match range {
+                Some((start, end)) => {
+                    let part = collection.get_object_range(name, start, end)?;
+                    // A short read means the range runs past the end of the object
+                    (part.len() as u64 == end - start + 1).then_some(part)
+                }
+                None => None,
+            };
+            part.ok_or(not_found)?
+        }
+        None => collection.get_object(name)?,
+    };
+    if decode.unwrap_or_default() {
+        object = encoding::decode(&meta.content_encoding, &object)?;
+        // The stored encoding, hash and offsets all describe the encoded bytes
+        meta.content_encoding.clear();
+        meta.content_hash.clear();
+        meta.segments.clear();
+        meta.offset_map.clear();
+    }
+    let compress_min_bytes = collection
+        .config
+        .compress_responses
+        .then_some(collection.config.compress_min_bytes);
+    Ok(MauveObject {
+        object: ObjectWithMetadata { object, meta },
+        compress_min_bytes,
+    })
+}
+
+/// Get an object's metadata as the headers a GET of it would send, without its body.
+///
+/// Only the metadata is read, unless the object was stored without a content hash, would be
+/// compressed by `compress_responses`, or the request asks for a segment, named range or
+/// decoded body, which are answered from `get_object`.
+#[head("/<collection>/<name>?<segment>&<offset>&<decode>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn head_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    segment: Option<usize>,
+    offset: Option<&str>,
+    decode: Option<bool>,
+) -> Result<Either<MauveObjectHead, MauveObject>, MauveServeError> {
+    let found = backend.find_collection(collection)?;
+    let meta = object_metadata(&found, name)?;
+    let compressed = found.config.compress_responses
+        && meta.content_encoding.is_empty()
+        && meta.size >= found.config.compress_min_bytes as u64;
+    if meta.content_hash.is_empty()
+        || compressed
+        || segment.is_some()
+        || offset.is_some()
+        || decode.unwrap_or_default()
+    {
+        let object = get_object(backend, collection, name, segment, offset, decode).await?;
+        return Ok(Either::Right(object));
+    }
+    Ok(Either::Left(MauveObjectHead(meta)))
+}
+
+/// Create an object, failing with 409 Conflict if one already exists
+#[post("/<collection>/<name>", data = "<payload>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn post_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    meta: RequestMetadata,
+    length: ContentLength,
+    payload: Data<'_>,
+) -> Result<Status, CreateError> {
+    let collection = backend.get_collection(collection)?;
+    let limit = collection.max_object_size_mb()?.mebibytes();
+    let object = read_payload(payload, limit, length).await?;
+    if collection.head_object(name)? {
+        return Err(existing(&collection, name)?);
+    }
+    // The object and its metadata are only written if the name is still free, so of two
+    // concurrent creates exactly one lands
+    write_object(&collection, name, meta.0, object, WriteMode::Swap(None)).map_err(
+        |e| match e {
+            // Lost a race with another create
+            MauveError::PreconditionFailed(_) => {
+                existing(&collection, name).unwrap_or_else(CreateError::from)
+            }
+            e => e.into(),
+        },
+    )?;
+    Ok(Status::Created)
+}
+
+/// Create or replace an object
+///
+/// With `If-Match` the object is only replaced if it still matches, and with
+/// `x-mauve-expect-absent: true` it is only created if it doesn't exist yet. Both are
+/// checked atomically with the write, failing with 412 Precondition Failed.
+///
+/// With `?return_old=true` the body of the object that was replaced is sent back, or
+/// `204 No Content` if there wasn't one.
+#[put("/<collection>/<name>?<return_old>", data = "<payload>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+#[allow(clippy::too_many_arguments)]
+pub async fn put_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    return_old: Option<bool>,
+    meta: RequestMetadata,
+    precondition: Precondition,
+    length: ContentLength,
+    payload: Data<'_>,
+) -> Result<Replaced, MauveServeError> {
+    let collection = backend.get_collection(collection)?;
+    let limit = collection.max_object_size_mb()?.mebibytes();
+    let object = read_payload(payload, limit, length).await?;
+    let mode = precondition.write_mode(&collection, name)?;
+    let old = write_object(&collection, name, meta.0, object, mode)?;
+    match return_old.unwrap_or_default() {
+        true => Ok(Replaced::Old(old)),
+        false => Ok(Replaced::Ok),
+    }
+}
+
+/// Response to a PUT, carrying the replaced object when it was asked for
+pub enum Replaced {
+    Ok,
+    Old(Option<Vec<u8>>),
+}
+
+impl<'r> Responder<'r, 'static> for Replaced {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Replaced::Ok => Status::Ok.respond_to(req),
+            Replaced::Old(Some(old)) => Response::build()
+                .raw_header("Content-Type", "application/octet-stream")
+                .sized_body(old.len(), Cursor::new(old))
+                .ok(),
+            Replaced::Old(None) => Status::NoContent.respond_to(req),
+        }
+    }
+}
+
+/// Delete an object along with its metadata and labels. With `If-Match` the object is only
+/// deleted if it still matches when it is removed.
+#[delete("/<collection>/<name>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn delete_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    precondition: Precondition,
+) -> Result<Status, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    let deleted = match precondition.write_mode(&collection, name)? {
+        WriteMode::Replace => collection.delete_object(name)?,
+        WriteMode::Swap(expected) => collection.compare_and_delete_object(name, expected)?,
+    };
+    match deleted {
+        Some(_) => Ok(Status::NoContent),
+        None => Err(MauveError::CollectionError(CollectionError::ObjectNotFound).into()),
+    }
+}
+
+/// Get just the labels of an object
+#[get("/<collection>/<name>/labels")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn object_labels(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+) -> Result<Json<HashSet<Label>>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    Ok(Json(collection.get_object_labels(name)?))
+}
+
+/// Delete every object named in a JSON array, in one transaction
+///
+/// Names that don't exist are reported with `deleted: false` rather than failing the batch.
+#[post("/<collection>/_batch_delete", data = "<names>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn batch_delete(
+    backend: &State<Backend>,
+    collection: &str,
+    names: Json<Vec<String>>,
+) -> Result<Json<Vec<BatchDeleted>>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    let names = names.into_inner();
+    let deleted = collection.delete_objects(&names)?;
+    Ok(Json(
+        names
+            .into_iter()
+            .zip(deleted)
+            .map(|(name, deleted)| BatchDeleted { name, deleted })
+            .collect(),
+    ))
+}
+
+/// Copy an object, with its metadata and labels, to the destination in a JSON body
+#[post("/<collection>/<name>/copy", data = "<destination>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn copy_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    destination: Json<ObjectDestination>,
+) -> Result<(Status, Json<ObjectRef>), MauveServeError> {
+    let to = destination.into_inner();
+    let copied = backend.copy_object(collection, name, &to.collection, &to.name, to.overwrite)?;
+    Ok((Status::Created, Json(copied)))
+}
+
+/// Move an object, with its metadata and labels, to the destination in a JSON body
+#[post("/<collection>/<name>/move", data = "<destination>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn move_object(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+    destination: Json<ObjectDestination>,
+) -> Result<(Status, Json<ObjectRef>), MauveServeError> {
+    let to = destination.into_inner();
+    let moved = backend.move_object(collection, name, &to.collection, &to.name, to.overwrite)?;
+    Ok((Status::Created, Json(moved)))
+}
+
+/// Store many objects sent as newline-delimited JSON, one object per line:
+/// `{"name": "a", "body": "<base64>", "labels": ["color=mauve"], "content_type": "..."}`
+///
+/// Existing objects are only replaced with `?replace=true`. Each line is reported as stored
+/// or not, with the reason, rather than failing the batch. The whole upload is limited to the
+/// collection's maximum object size.
+#[post("/<collection>/_batch_put?<replace>", data = "<payload>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection)))]
+pub async fn batch_put(
+    backend: &State<Backend>,
+    config: &State<AppConfig>,
+    collection: &str,
+    replace: Option<bool>,
+    length: ContentLength,
+    payload: Data<'_>,
+) -> Result<Json<Vec<BatchPut>>, MauveServeError> {
+    let collection = backend.get_collection(collection)?;
+    let limit = collection.max_object_size_mb()?.mebibytes();
+    let payload = String::from_utf8(read_payload(payload, limit, length).await?)
+        .map_err(|e| MauveError::InvalidBatch(e.to_string()))?;
+
+    let mut reports = vec![];
+    let mut items = vec![];
+    for line in payload.lines().filter(|line| !line.trim().is_empty()) {
+        let (name, item) = match serde_json::from_str::<BatchLine>(line) {
+            Ok(line) => (line.name.clone(), batch_object(line, &config.mauve)),
+            Err(e) => (String::new(), Err(MauveError::InvalidBatch(e.to_string()))),
+        };
+        reports.push(BatchPut {
+            name,
+            stored: false,
+            error: None,
+        });
+        match item {
+            Ok(item) => items.push((reports.len() - 1, item)),
+            Err(e) => reports.last_mut().expect("just pushed").error = Some(e.to_string()),
+        }
+    }
+
+    let (indexes, items): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+    let stored = collection.put_objects_batch(items, replace.unwrap_or_default())?;
+    for (i, stored) in indexes.into_iter().zip(stored) {
+        match stored {
+            Ok(_) => reports[i].stored = true,
+            Err(e) => reports[i].error = Some(e.to_string()),
+        }
+    }
+    Ok(Json(reports))
+}
+
+/// Decode and check one line of a batch upload
+fn batch_object(line: BatchLine, config: &MauveConfig) -> Result<BatchObject, MauveError> {
+    let object = BASE64_STANDARD
+        .decode(&line.body)
+        .map_err(|e| MauveError::InvalidBatch(format!("body is not base64, {e}")))?;
+    let mut meta = Metadata {
+        content_type: line.content_type,
+        content_encoding: line.content_encoding,
+        content_language: line.content_language,
+        ..Default::default()
+    };
+    for label in &line.labels {
+        meta.labels.insert(label.parse()?);
+    }
+    meta.normalize(config);
+    meta.check_limits(config)?;
+    encoding::check_encoding(&meta.content_encoding, &object)?;
+    meta.size = object.len() as u64;
+    meta.sniff_content_type(&object, config);
+    meta.content_hash = etag(&object);
+    Ok(BatchObject {
+        name: line.name,
+        object,
+        meta,
+    })
+}
+
+#[patch("/<_>/<_>")]
+pub async fn patch_object() -> MethodNotAllowed {
+    MethodNotAllowed(OBJECT_METHODS)
+}
+
+/// Get the stored size of an object without fetching its body
+#[get("/<collection>/<name>/size")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %collection, name = %name)))]
+pub async fn object_size(
+    backend: &State<Backend>,
+    collection: &str,
+    name: &str,
+) -> Result<Json<ObjectSize>, MauveServeError> {
+    let collection = backend.find_collection(collection)?;
+    let size = collection.object_size(name)?;
+    Ok(Json(ObjectSize { size }))
+}
+
+/// Store an object and its metadata, returning the object it replaced
+fn write_object(
+    collection: &Collection,
+    name: &str,
+    mut meta: Metadata,
+    object: Vec<u8>,
+    mode: WriteMode,
+) -> Result<Option<Vec<u8>>, MauveError> {
+    meta.size = object.len() as u64;
+    meta.sniff_content_type(&object, &collection.config);
+    meta.check_segments()?;
+    meta.verify_content_hash(&object)?;
+    encoding::check_encoding(&meta.content_encoding, &object)?;
+    meta.content_hash = etag(&object);
+    if let WriteMode::Swap(expected) = mode {
+        collection.compare_and_swap_object(name, expected.clone(), object, Some(meta))?;
+        return Ok(expected);
+    }
+    // Metadata goes first so the indexer sees the labels when the object lands
+    collection.put_object_metadata(name, meta)?;
+    let (_, old) = collection.put_object_returning(name, object, true)?;
+    Ok(old)
+}
+
+/// Describe the object already stored under `name` for a conflicting create
+fn existing(collection: &Collection, name: &str) -> Result<CreateError, MauveError> {
+    let object = collection.get_object(name)?;
+    let meta = object_metadata(collection, name)?;
+    Ok(CreateError::Exists {
+        etag: match meta.content_hash.is_empty() {
+            true => etag(&object),
+            false => meta.content_hash,
+        },
+        size: object.len() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Barrier},
+    };
+
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::asynchronous::{Client, LocalResponse},
+    };
+
+    use super::{byte_range, write_object, BatchDeleted, BatchPut, ByteRange, WriteMode};
+    use crate::{
+        api::{errors::ErrorBody, mauve_rocket, tests::test_config},
+        backend::Backend,
+        encoding,
+        errors::MauveError,
+        labels::Label,
+        meta::{etag, Metadata},
+        search::SearchRequest,
+    };
+
+    #[rocket::async_test]
+    async fn test_object_crud() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .post("/v1/objects/things/one")
+            .header(Header::new("Content-Type", "text/plain"))
+            .header(Header::new("x-mauve-labels", "color=mauve, shape=round"))
+            .body("first")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client.get("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("Content-Type"), Some("text/plain"));
+        let labels = res.headers().get_one("x-mauve-labels").unwrap_or_default();
+        assert!(labels.contains("color=mauve") && labels.contains("shape=round"));
+        assert_eq!(res.into_string().await.unwrap_or_default(), "first");
+
+        // HEAD sends the headers a GET would, from the metadata alone
+        let res = client.head("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("Content-Type"), Some("text/plain"));
+        let labels = res.headers().get_one("x-mauve-labels").unwrap_or_default();
+        assert!(labels.contains("color=mauve") && labels.contains("shape=round"));
+        let tag = format!("\"{}\"", etag(b"first"));
+        assert_eq!(res.headers().get_one("ETag"), Some(tag.as_str()));
+        assert_eq!(res.body().preset_size(), Some(5));
+        assert!(res.into_bytes().await.unwrap_or_default().is_empty());
+        let res = client
+            .head("/v1/objects/things/one")
+            .header(Header::new("If-None-Match", tag))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotModified);
+        let res = client.head("/v1/objects/things/missing").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Labels with commas and equals signs survive either encoding
+        let res = client
+            .put("/v1/objects/things/one")
+            .header(Header::new("x-mauve-labels", "path=a%2Cb.txt"))
+            .header(Header::new("x-mauve-label", "query=x=1,y=2"))
+            .body("first")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let meta = backend
+            .get_collection("things")?
+            .get_object_metadata("one")?;
+        assert_eq!(meta.size, 5);
+        assert!(meta.labels.contains(&Label::new("path", "a,b.txt")));
+        assert!(meta.labels.contains(&Label::new("query", "x=1,y=2")));
+        let res = client.get("/v1/objects/things/one/labels").dispatch().await;
+        let labels: HashSet<Label> = res.into_json().await.unwrap_or_default();
+        assert_eq!(labels, meta.labels);
+        let res = client.get("/v1/objects/things/one").dispatch().await;
+        let labels = res.headers().get_one("x-mauve-labels").unwrap_or_default();
+        assert!(labels.contains("path=a%2Cb.txt") && labels.contains("query=x%3D1%2Cy%3D2"));
+
+        // A conflicting create reports what is already there
+        let res = client
+            .post("/v1/objects/things/one")
+            .body("second")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+        let expected = format!("\"{}\"", etag(b"first"));
+        assert_eq!(res.headers().get_one("ETag"), Some(expected.as_str()));
+        assert_eq!(res.headers().get_one("x-mauve-size"), Some("5"));
+
+        let res = client
+            .put("/v1/objects/things/one")
+            .body("second")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/v1/objects/things/one/size").dispatch().await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), r#"{"size":6}"#);
+        let meta = backend
+            .get_collection("things")?
+            .get_object_metadata("one")?;
+        assert_eq!(meta.size, 6);
+        let res = client.get("/v1/objects/things/one/labels").dispatch().await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), "[]");
+
+        let res = client.delete("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.status(), Status::NoContent);
+        let res = client.get("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert_eq!(res.content_type(), Some(ContentType::JSON));
+        let error: ErrorBody = res.into_json().await.unwrap();
+        assert_eq!(error.error, "Object not found");
+        assert_eq!(error.code, "ObjectNotFound");
+        assert_eq!(error.status, 404);
+        // Requests no route takes get the same shape of error
+        let res = client.get("/v1/nowhere").dispatch().await;
+        let error: ErrorBody = res.into_json().await.unwrap();
+        assert_eq!((error.code.as_str(), error.status), ("NotFound", 404));
+
+        // Reads don't create the collection they ask for
+        let res = client.get("/v1/objects/nothing/one").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let error: ErrorBody = res.into_json().await.unwrap();
+        assert_eq!(error.code, "CollectionNotFound");
+        for path in ["/v1/collections/nothing", "/v1/collections/nothing/_dlq"] {
+            let res = client.get(path).dispatch().await;
+            assert_eq!(res.status(), Status::NotFound);
+        }
+        assert!(!backend.list_collections()?.any(|c| c == "nothing"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_batch_delete() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("batch")?;
+        collection.put_object("a", b"1".to_vec(), false)?;
+        collection.put_object("b", b"2".to_vec(), false)?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client
+            .post("/v1/objects/batch/_batch_delete")
+            .header(ContentType::JSON)
+            .body(r#"["a", "missing", "b"]"#)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let deleted: Vec<BatchDeleted> = res.into_json().await.unwrap_or_default();
+        let deleted: Vec<_> = deleted
+            .iter()
+            .map(|d| (d.name.as_str(), d.deleted))
+            .collect();
+        assert_eq!(deleted, [("a", true), ("missing", false), ("b", true)]);
+        assert!(collection.data.is_empty());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_batch_put() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("batch")?;
+        collection.put_object("taken", b"0".to_vec(), false)?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let body = [
+            format!(
+                r#"{{"name": "a", "body": "{}", "labels": ["color=mauve"]}}"#,
+                BASE64_STANDARD.encode("one")
+            ),
+            format!(
+                r#"{{"name": "taken", "body": "{}"}}"#,
+                BASE64_STANDARD.encode("two")
+            ),
+            r#"{"name": "bad", "body": "!"}"#.to_string(),
+            String::new(),
+            format!(
+                r#"{{"name": "b", "body": "{}", "content_type": "text/plain"}}"#,
+                BASE64_STANDARD.encode("three")
+            ),
+        ]
+        .join("\n");
+        let res = client
+            .post("/v1/objects/batch/_batch_put")
+            .body(&body)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let stored: Vec<BatchPut> = res.into_json().await.unwrap_or_default();
+        let stored: Vec<_> = stored
+            .iter()
+            .map(|s| (s.name.as_str(), s.stored, s.error.is_some()))
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                ("a", true, false),
+                ("taken", false, true),
+                ("bad", false, true),
+                ("b", true, false)
+            ]
+        );
+        assert_eq!(collection.get_object("taken")?, b"0");
+        assert_eq!(collection.get_object("b")?, b"three");
+        let meta = collection.get_object_metadata("a")?;
+        assert_eq!(meta.size, 3);
+        assert!(meta.labels.contains(&Label::new("color", "mauve")));
+        assert_eq!(
+            collection.get_object_metadata("b")?.content_type,
+            "text/plain"
+        );
+
+        let res = client
+            .post("/v1/objects/batch/_batch_put?replace=true")
+            .body(&body)
+            .dispatch()
+            .await;
+        let stored: Vec<BatchPut> = res.into_json().await.unwrap_or_default();
+        assert_eq!(stored.iter().filter(|s| s.stored).count(), 3);
+        assert_eq!(collection.get_object("taken")?, b"two");
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_replace_labels() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("envs")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let search = |label: Label| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("envs");
+                req.include(label);
+                let found = backend.perform_search(req).await?.result;
+                anyhow::Ok(found.unwrap_or_default().refs().len())
+            }
+        };
+        for env in ["prod", "dev"] {
+            let res = client
+                .put("/v1/objects/envs/a")
+                .header(Header::new(
+                    "x-mauve-labels",
+                    format!("env={env},team=core"),
+                ))
+                .body(env)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            for _ in 0..50 {
+                if search(Label::new("env", env)).await? == 1 {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        }
+        assert_eq!(search(Label::new("env", "dev")).await?, 1);
+        assert_eq!(search(Label::new("team", "core")).await?, 1);
+        // The replaced object's labels are no longer found
+        assert_eq!(search(Label::new("env", "prod")).await?, 0);
+        assert!(!collection.index_rev.contains_key("prod=env")?);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_copy_and_move() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        backend.get_collection("dst")?;
+        // Give the collection indexers time to start watching
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        let res = client
+            .post("/v1/objects/src/one")
+            .header(Header::new("x-mauve-labels", "color=mauve"))
+            .body("first")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+
+        let res = client
+            .post("/v1/objects/src/one/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"collection": "dst", "name": "two"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/v1/objects/dst/two").dispatch().await;
+        assert_eq!(res.headers().get_one("x-mauve-labels"), Some("color=mauve"));
+        assert_eq!(res.into_string().await.unwrap_or_default(), "first");
+        let src = backend.get_collection("src")?;
+        let dst = backend.get_collection("dst")?;
+        for _ in 0..50 {
+            if dst.index_fwd.contains_key("color=mauve")? {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(dst.index_fwd.contains_key("color=mauve")?);
+
+        // The destination is only replaced when asked
+        let res = client
+            .post("/v1/objects/src/one/move")
+            .header(ContentType::JSON)
+            .body(r#"{"collection": "dst", "name": "two"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+        let res = client
+            .post("/v1/objects/src/one/move")
+            .header(ContentType::JSON)
+            .body(r#"{"collection": "dst", "name": "three", "overwrite": true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Created);
+        let res = client.get("/v1/objects/src/one").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert!(!src.index_fwd.contains_key("color=mauve")?);
+        let labels = dst.get_object_metadata("three")?.labels;
+        assert_eq!(labels, HashSet::from([Label::new("color", "mauve")]));
+        let res = client.get("/v1/objects/dst/three").dispatch().await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), "first");
+
+        let res = client
+            .post("/v1/objects/src/one/copy")
+            .header(ContentType::JSON)
+            .body(r#"{"collection": "dst", "name": "four"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_method_not_allowed() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client.patch("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.status(), Status::MethodNotAllowed);
+        assert_eq!(
+            res.headers().get_one("Allow"),
+            Some("GET, HEAD, POST, PUT, DELETE")
+        );
+
+        let res = client.patch("/v1/collections/things").dispatch().await;
+        assert_eq!(res.status(), Status::MethodNotAllowed);
+        assert_eq!(
+            res.headers().get_one("Allow"),
+            Some("GET, HEAD, POST, PUT, DELETE")
+        );
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range("bytes=0-4", 10), ByteRange::Partial(0, 4));
+        assert_eq!(byte_range("bytes=5-", 10), ByteRange::Partial(5, 9));
+        assert_eq!(byte_range("bytes=-3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(byte_range("bytes=8-20", 10), ByteRange::Partial(8, 9));
+        assert_eq!(byte_range("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(
+            byte_range("bytes=0-1, 4-5, 20-", 10),
+            ByteRange::Multi(vec![(0, 1), (4, 5)])
+        );
+        assert_eq!(byte_range("bytes=10-,20-30", 10), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range("bytes=0-1,x", 10), ByteRange::Full);
+        assert_eq!(byte_range("items=0-1", 10), ByteRange::Full);
+    }
+
+    #[rocket::async_test]
+    async fn test_if_range() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        client
+            .put("/v1/objects/files/big")
+            .body("0123456789")
+            .dispatch()
+            .await;
+
+        let first = client
+            .get("/v1/objects/files/big")
+            .header(Header::new("Range", "bytes=0-4"))
+            .dispatch()
+            .await;
+        assert_eq!(first.status(), Status::PartialContent);
+        assert_eq!(
+            first.headers().get_one("Content-Range"),
+            Some("bytes 0-4/10")
+        );
+        let tag = first
+            .headers()
+            .get_one("ETag")
+            .unwrap_or_default()
+            .to_string();
+        assert_eq!(first.into_string().await.unwrap_or_default(), "01234");
+
+        let res = client
+            .get("/v1/objects/files/big")
+            .header(Header::new("Range", "bytes=5-"))
+            .header(Header::new("If-Range", tag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PartialContent);
+        assert_eq!(res.into_string().await.unwrap_or_default(), "56789");
+
+        // Once the object changes the rest of the range no longer applies
+        client
+            .put("/v1/objects/files/big")
+            .body("abcdefghij")
+            .dispatch()
+            .await;
+        let res = client
+            .get("/v1/objects/files/big")
+            .header(Header::new("Range", "bytes=5-"))
+            .header(Header::new("If-Range", tag))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().await.unwrap_or_default(), "abcdefghij");
+
+        let res = client
+            .get("/v1/objects/files/big")
+            .header(Header::new("Range", "bytes=10-"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::RangeNotSatisfiable);
+        assert_eq!(res.headers().get_one("Content-Range"), Some("bytes */10"));
+
+        let res = client
+            .get("/v1/objects/files/big")
+            .header(Header::new("Range", "bytes=0-1,-2"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PartialContent);
+        let content_type = res.headers().get_one("Content-Type").unwrap_or_default();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap_or_default()
+            .to_string();
+        assert!(!boundary.is_empty());
+        let expected = format!(
+            "\r\n--{boundary}\r\nContent-Type: application/octet-stream\r\n\
+             Content-Range: bytes 0-1/10\r\n\r\nab\
+             \r\n--{boundary}\r\nContent-Type: application/octet-stream\r\n\
+             Content-Range: bytes 8-9/10\r\n\r\nij\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(res.into_string().await.unwrap_or_default(), expected);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_object_segments() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .put("/v1/objects/parts/joined")
+            .header(Header::new("x-mauve-offsets-inclusive", "0-4, 5-10"))
+            .body("hello world")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/v1/objects/parts/joined").dispatch().await;
+        assert_eq!(
+            res.headers().get_one("x-mauve-offsets-inclusive"),
+            Some("0-4,5-10")
+        );
+        let res = client
+            .get("/v1/objects/parts/joined?segment=1")
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), " world");
+        let res = client
+            .get("/v1/objects/parts/joined?segment=2")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::RangeNotSatisfiable);
+
+        // Named ranges are kept alongside the segments and fetched by name
+        let res = client
+            .put("/v1/objects/parts/named")
+            .header(Header::new(
+                "x-mauve-offsets-inclusive",
+                "0-4, 5-10; greeting=0-4",
+            ))
+            .body("hello world")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client.get("/v1/objects/parts/named").dispatch().await;
+        assert_eq!(
+            res.headers().get_one("x-mauve-offsets-inclusive"),
+            Some("0-4,5-10,greeting=0-4")
+        );
+        let res = client
+            .get("/v1/objects/parts/named?offset=greeting")
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), "hello");
+        let res = client
+            .get("/v1/objects/parts/named?offset=1")
+            .dispatch()
+            .await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), " world");
+        let res = client
+            .get("/v1/objects/parts/named?offset=farewell")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::RangeNotSatisfiable);
+
+        // Segments must fit the object
+        let res = client
+            .put("/v1/objects/parts/short")
+            .header(Header::new("x-mauve-offsets-inclusive", "0-20"))
+            .body("hello")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_conditional_requests() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        client
+            .put("/v1/objects/cached/a")
+            .body("one")
+            .dispatch()
+            .await;
+        let meta = backend.get_collection("cached")?.get_object_metadata("a")?;
+        assert_eq!(meta.content_hash, etag(b"one"));
+
+        let res = client.get("/v1/objects/cached/a").dispatch().await;
+        let tag = res
+            .headers()
+            .get_one("ETag")
+            .unwrap_or_default()
+            .to_string();
+        assert_eq!(tag, format!("\"{}\"", etag(b"one")));
+
+        for (if_none_match, status) in [
+            (tag.clone(), Status::NotModified),
+            (format!("W/{tag}"), Status::NotModified),
+            (format!("\"other\", {tag}"), Status::NotModified),
+            ("*".to_string(), Status::NotModified),
+            ("\"other\"".to_string(), Status::Ok),
+        ] {
+            let res = client
+                .get("/v1/objects/cached/a")
+                .header(Header::new("If-None-Match", if_none_match))
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), status);
+        }
+
+        // Writes only go ahead while the object still matches
+        for (if_match, status) in [
+            ("\"other\"".to_string(), Status::PreconditionFailed),
+            (format!("W/{tag}"), Status::PreconditionFailed),
+            (tag.clone(), Status::Ok),
+            (tag.clone(), Status::PreconditionFailed),
+        ] {
+            let res = client
+                .put("/v1/objects/cached/a")
+                .header(Header::new("If-Match", if_match))
+                .body("two")
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), status);
+        }
+        let res = client.get("/v1/objects/cached/a").dispatch().await;
+        assert_eq!(res.into_string().await.unwrap_or_default(), "two");
+
+        let res = client
+            .delete("/v1/objects/cached/a")
+            .header(Header::new("If-Match", tag))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PreconditionFailed);
+        let res = client
+            .delete("/v1/objects/cached/a")
+            .header(Header::new("If-Match", "*"))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NoContent);
+        let res = client
+            .put("/v1/objects/cached/a")
+            .header(Header::new("If-Match", "*"))
+            .body("three")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PreconditionFailed);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_expect_absent() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        for status in [Status::Ok, Status::PreconditionFailed] {
+            let res = client
+                .put("/v1/objects/cas/a")
+                .header(Header::new("x-mauve-expect-absent", "true"))
+                .header(Header::new("x-mauve-labels", "color=mauve"))
+                .body("one")
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), status);
+        }
+        let res = client.get("/v1/objects/cas/a").dispatch().await;
+        assert_eq!(res.headers().get_one("x-mauve-labels"), Some("color=mauve"));
+        let tag = res
+            .headers()
+            .get_one("ETag")
+            .unwrap_or_default()
+            .to_string();
+        let res = client
+            .put("/v1/objects/cas/a")
+            .header(Header::new("x-mauve-expect-absent", "true"))
+            .header(Header::new("If-Match", tag))
+            .body("two")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PreconditionFailed);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_create() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("racing")?;
+        let barrier = Arc::new(Barrier::new(8));
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let (collection, barrier) = (collection.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    let mut meta = Metadata::default();
+                    meta.labels.insert(Label::new("writer", &i.to_string()));
+                    barrier.wait();
+                    let body = i.to_string().into_bytes();
+                    write_object(&collection, "a", meta, body, WriteMode::Swap(None))
+                })
+            })
+            .collect();
+        let mut created = 0;
+        for writer in writers {
+            match writer.join().expect("writer panicked") {
+                Ok(_) => created += 1,
+                Err(MauveError::PreconditionFailed(_)) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        assert_eq!(created, 1);
+        // The stored metadata is the winner's, not overwritten by a loser
+        let winner = String::from_utf8(collection.get_object("a")?)?;
+        let meta = collection.get_object_metadata("a")?;
+        assert!(meta.labels.contains(&Label::new("writer", &winner)));
+
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let res = client
+            .post("/v1/objects/racing/a")
+            .body("late")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Conflict);
+        assert_eq!(collection.get_object("a")?, winner.as_bytes());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_return_old() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        let res = client
+            .put("/v1/objects/kept/a?return_old=true")
+            .body("one")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NoContent);
+        let res = client
+            .put("/v1/objects/kept/a?return_old=true")
+            .body("two")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.into_string().await.unwrap_or_default(), "one");
+        let res = client
+            .put("/v1/objects/kept/a")
+            .body("three")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert!(res.into_string().await.unwrap_or_default().is_empty());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_timestamps() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        let times = |res: &LocalResponse<'_>| -> anyhow::Result<(u64, u64)> {
+            let header = |name| res.headers().get_one(name).unwrap_or_default().parse();
+            Ok((header("x-mauve-created-at")?, header("x-mauve-updated-at")?))
+        };
+
+        client
+            .put("/v1/objects/dated/a")
+            .body("one")
+            .dispatch()
+            .await;
+        let res = client.get("/v1/objects/dated/a").dispatch().await;
+        let (created, updated) = times(&res)?;
+        assert!(created > 0);
+        assert_eq!(created, updated);
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        client
+            .put("/v1/objects/dated/a")
+            .body("two")
+            .dispatch()
+            .await;
+        let res = client.head("/v1/objects/dated/a").dispatch().await;
+        let (replaced_created, replaced_updated) = times(&res)?;
+        assert_eq!(replaced_created, created);
+        assert!(replaced_updated > updated);
+
+        // A delete forgets the creation time
+        client.delete("/v1/objects/dated/a").dispatch().await;
+        client
+            .put("/v1/objects/dated/a")
+            .body("three")
+            .dispatch()
+            .await;
+        let res = client.get("/v1/objects/dated/a").dispatch().await;
+        assert!(times(&res)?.0 > created);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_extra_metadata() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        let res = client
+            .put("/v1/objects/things/one")
+            .header(Header::new("X-Mauve-Meta-Owner", "mauve"))
+            .header(Header::new("x-mauve-meta-build", "42"))
+            .body("first")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let meta = backend
+            .get_collection("things")?
+            .get_object_metadata("one")?;
+        assert_eq!(meta.extra.get("owner").map(String::as_str), Some("mauve"));
+        assert_eq!(meta.extra.len(), 2);
+
+        let res = client.head("/v1/objects/things/one").dispatch().await;
+        assert_eq!(res.headers().get_one("x-mauve-meta-owner"), Some("mauve"));
+        assert_eq!(res.headers().get_one("x-mauve-meta-build"), Some("42"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_sniff_content_type() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.sniff_content_type = true;
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+        client
+            .post("/v1/objects/things/png")
+            .body(png)
+            .dispatch()
+            .await;
+        let res = client.head("/v1/objects/things/png").dispatch().await;
+        assert_eq!(res.headers().get_one("Content-Type"), Some("image/png"));
+        // Nothing to sniff leaves the type unset, served as generic bytes
+        client
+            .post("/v1/objects/things/bytes")
+            .body(b"\x00\x01")
+            .dispatch()
+            .await;
+        let res = client.get("/v1/objects/things/bytes").dispatch().await;
+        assert_eq!(
+            res.headers().get_one("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        // A declared type is kept
+        client
+            .post("/v1/objects/things/declared")
+            .header(Header::new("Content-Type", "application/octet-stream"))
+            .body(png)
+            .dispatch()
+            .await;
+        let res = client.head("/v1/objects/things/declared").dispatch().await;
+        assert_eq!(
+            res.headers().get_one("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_compress_responses() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.compress_responses = true;
+        config.mauve.compress_min_bytes = 64;
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let text = "mauve ".repeat(100);
+        let gzipped = encoding::encode("gzip", text.as_bytes())?;
+        for (name, body, encoding) in [
+            ("text", text.as_bytes(), None),
+            ("tiny", b"mauve".as_slice(), None),
+            ("stored", gzipped.as_slice(), Some("gzip")),
+        ] {
+            let mut req = client.put(format!("/v1/objects/things/{name}")).body(body);
+            if let Some(encoding) = encoding {
+                req = req.header(Header::new("Content-Encoding", encoding));
+            }
+            assert_eq!(req.dispatch().await.status(), Status::Ok);
+        }
+
+        let res = client
+            .get("/v1/objects/things/text")
+            .header(Header::new("Accept-Encoding", "gzip, deflate"))
+            .dispatch()
+            .await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.headers().get_one("Vary"), Some("Accept-Encoding"));
+        let tag = res
+            .headers()
+            .get_one("ETag")
+            .unwrap_or_default()
+            .to_string();
+        assert!(tag.starts_with("W/"));
+        let body = res.into_bytes().await.unwrap_or_default();
+        assert!(body.len() < text.len());
+        assert_eq!(encoding::decode("gzip", &body)?, text.as_bytes());
+        // The weak tag still revalidates
+        let res = client
+            .get("/v1/objects/things/text")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .header(Header::new("If-None-Match", tag))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotModified);
+
+        let res = client
+            .get("/v1/objects/things/text")
+            .header(Header::new("Accept-Encoding", "deflate"))
+            .dispatch()
+            .await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), Some("deflate"));
+        let body = res.into_bytes().await.unwrap_or_default();
+        assert_eq!(encoding::decode("deflate", &body)?, text.as_bytes());
+
+        // Without Accept-Encoding, under the threshold or asking for a range, the stored
+        // bytes are sent
+        let res = client.get("/v1/objects/things/text").dispatch().await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), None);
+        assert_eq!(res.into_bytes().await.unwrap_or_default(), text.as_bytes());
+        for (name, range) in [("tiny", None), ("text", Some("bytes=0-4"))] {
+            let mut req = client
+                .get(format!("/v1/objects/things/{name}"))
+                .header(Header::new("Accept-Encoding", "gzip"));
+            if let Some(range) = range {
+                req = req.header(Header::new("Range", range));
+            }
+            let res = req.dispatch().await;
+            assert_eq!(res.headers().get_one("Content-Encoding"), None);
+            assert_eq!(res.into_bytes().await.unwrap_or_default(), b"mauve");
+        }
+        // Objects stored compressed pass through as they are
+        let res = client
+            .get("/v1/objects/things/stored")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch()
+            .await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.into_bytes().await.unwrap_or_default(), gzipped);
+
+        // HEAD sends the same headers
+        let res = client
+            .head("/v1/objects/things/text")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch()
+            .await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), Some("gzip"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_content_hash() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+        let digest = etag(b"hello");
+
+        let res = client
+            .put("/v1/objects/hashed/one")
+            .header(Header::new("x-mauve-content-hash", format!("md5:{digest}")))
+            .body("hellO")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::UnprocessableEntity);
+        let res = client.get("/v1/objects/hashed/one").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        let res = client
+            .put("/v1/objects/hashed/one")
+            .header(Header::new(
+                "x-mauve-content-hash",
+                format!("sha1:{digest}"),
+            ))
+            .body("hello")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .put("/v1/objects/hashed/one")
+            .header(Header::new("x-mauve-content-hash", format!("md5:{digest}")))
+            .body("hello")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let meta = backend
+            .get_collection("hashed")?
+            .get_object_metadata("one")?;
+        assert_eq!(meta.content_hash, digest);
+        let res = client.get("/v1/objects/hashed/one").dispatch().await;
+        let expected = format!("\"{digest}\"");
+        assert_eq!(res.headers().get_one("ETag"), Some(expected.as_str()));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_collection_size_limit() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.object_max_size_mb = 1;
+        config
+            .mauve
+            .collection_max_size_mb
+            .insert("ingest".to_string(), 3);
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let object = vec![b'x'; 2 * 1024 * 1024];
+
+        let res = client
+            .put("/v1/objects/things/big")
+            .body(&object)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        // A declared length over the limit is refused up front, and one under it isn't
+        // trusted
+        let res = client
+            .put("/v1/objects/things/big")
+            .header(Header::new("Content-Length", object.len().to_string()))
+            .body("x")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let res = client
+            .put("/v1/objects/things/big")
+            .header(Header::new("Content-Length", "1"))
+            .body(&object)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let res = client
+            .put("/v1/objects/ingest/big")
+            .body(&object)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        // A limit set on the collection overrides the config until it's reset
+        let res = client
+            .put("/v1/collections/ingest/_limits")
+            .json(&serde_json::json!({ "max_object_size_mb": 1 }))
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .put("/v1/objects/ingest/big")
+            .body(&object)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::PayloadTooLarge);
+        let res = client
+            .get("/v1/collections/ingest/_limits")
+            .dispatch()
+            .await;
+        assert_eq!(
+            res.into_json::<serde_json::Value>().await,
+            Some(serde_json::json!({ "max_object_size_mb": 1 }))
+        );
+        let res = client
+            .put("/v1/collections/ingest/_limits")
+            .json(&serde_json::json!({ "max_object_size_mb": null }))
+            .dispatch()
+            .await;
+        assert_eq!(
+            res.into_json::<serde_json::Value>().await,
+            Some(serde_json::json!({ "max_object_size_mb": 3 }))
+        );
+        let res = client
+            .put("/v1/objects/ingest/big")
+            .body(&object)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_content_encoding() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+        let body = zstd::encode_all(&b"hello"[..], 0)?;
+
+        let res = client
+            .put("/v1/objects/packed/one")
+            .header(Header::new("Content-Encoding", "gzip"))
+            .body(&body)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        let res = client
+            .put("/v1/objects/packed/one")
+            .header(Header::new("Content-Encoding", "zstd"))
+            .body(&body)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+
+        let res = client.get("/v1/objects/packed/one").dispatch().await;
+        assert_eq!(res.headers().get_one("Content-Encoding"), Some("zstd"));
+        assert_eq!(res.into_bytes().await.unwrap_or_default(), body);
+
+        let res = client
+            .get("/v1/objects/packed/one?decode=true")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("Content-Encoding"), None);
+        assert_eq!(res.into_string().await.unwrap_or_default(), "hello");
+
+        let res = client
+            .put("/v1/objects/packed/two")
+            .header(Header::new("Content-Encoding", "br"))
+            .body("opaque")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .get("/v1/objects/packed/two?decode=true")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::NotAcceptable);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
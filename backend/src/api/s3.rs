@@ -0,0 +1,929 @@
+//! S3 compatible API
+//!
+//! A path-style subset of the S3 REST API mounted under `/s3`. Buckets map to collections,
+//! keys map to object names and both `x-amz-meta-*` headers and object tags map to labels.
+//!
+//! Supported operations:
+//! - `GET /s3/<bucket>` ListObjects (v1) and ListObjectsV2 (`?list-type=2`)
+//! - `GET|HEAD|PUT|DELETE /s3/<bucket>/<key..>` object operations
+//! - `GET|PUT|DELETE /s3/<bucket>/<key..>?tagging` object tagging
+//!
+//! There is no request signing or authentication.
+
+use std::{collections::HashSet, io::Cursor};
+
+use rocket::{
+    data::{Data, ToByteUnit},
+    delete, get, head,
+    http::{uri::fmt::Path, uri::Segments, ContentType, RawStr, Status},
+    put,
+    request::{self, FromRequest, Request},
+    response::{self, Responder, Response},
+    routes, Either, FromForm, Route, State,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{read_payload, ContentLength};
+use crate::{
+    backend::Backend,
+    collection::Collection,
+    config::{AppConfig, MauveConfig},
+    errors::{CollectionError, MauveError},
+    labels::Label,
+    meta::{etag, Metadata, ObjectWithMetadata},
+};
+
+const S3_XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_objects,
+        get_object,
+        head_object,
+        put_object,
+        delete_object,
+        get_object_tagging,
+        put_object_tagging,
+        delete_object_tagging,
+    ]
+}
+
+/// An S3 style error, rendered as an XML `<Error>` document
+#[derive(Debug)]
+pub struct S3Error {
+    status: Status,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    fn new(status: Status, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Error", rename_all = "PascalCase")]
+struct S3ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+}
+
+impl From<MauveError> for S3Error {
+    fn from(value: MauveError) -> Self {
+        match value {
+            MauveError::CollectionError(CollectionError::CollectionNotFound) => {
+                Self::new(Status::NotFound, "NoSuchBucket", value)
+            }
+            MauveError::CollectionError(CollectionError::ObjectNotFound) => {
+                Self::new(Status::NotFound, "NoSuchKey", value)
+            }
+            MauveError::CollectionError(CollectionError::PutObjectExistsNoReplace) => {
+                Self::new(Status::Conflict, "OperationAborted", value)
+            }
+            MauveError::CollectionError(CollectionError::NameTooLong(_)) => {
+                Self::new(Status::BadRequest, "InvalidBucketName", value)
+            }
+            MauveError::CollectionError(CollectionError::KeyTooLong(_)) => {
+                Self::new(Status::BadRequest, "KeyTooLongError", value)
+            }
+            MauveError::TooManyLabels(_) => Self::new(Status::BadRequest, "InvalidTag", value),
+            MauveError::MetadataTooLarge(_) => {
+                Self::new(Status::BadRequest, "MetadataTooLarge", value)
+            }
+            MauveError::ObjectTooLarge(_) => {
+                Self::new(Status::PayloadTooLarge, "EntityTooLarge", value)
+            }
+            MauveError::InvalidLabel(_) => Self::new(Status::BadRequest, "InvalidArgument", value),
+            _ => Self::new(Status::InternalServerError, "InternalError", value),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for S3Error {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = S3ErrorBody {
+            code: self.code,
+            message: &self.message,
+        };
+        let xml = to_xml(&body).unwrap_or_default();
+        Response::build_from(xml.respond_to(req)?)
+            .status(self.status)
+            .header(ContentType::XML)
+            .ok()
+    }
+}
+
+/// Serialize an S3 response document, including the XML declaration
+fn to_xml<T: Serialize>(value: &T) -> Result<String, S3Error> {
+    let body = quick_xml::se::to_string(value)
+        .map_err(|e| S3Error::new(Status::InternalServerError, "InternalError", e))?;
+    Ok(format!(r#"<?xml version="1.0" encoding="UTF-8"?>{body}"#))
+}
+
+/// Join the path segments following the bucket back into an object key
+fn object_key(segments: Segments<'_, Path>) -> String {
+    segments.collect::<Vec<_>>().join("/")
+}
+
+/// Decode an `application/x-www-form-urlencoded` component
+fn form_decode(s: &str) -> String {
+    RawStr::new(&s.replace('+', " "))
+        .url_decode_lossy()
+        .into_owned()
+}
+
+/// Parse an `x-amz-tagging` header value (`k1=v1&k2=v2`) into labels
+fn parse_tagging(s: &str) -> Result<Vec<Label>, MauveError> {
+    s.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => Ok(Label::exact(&form_decode(name), &form_decode(value))),
+            None => Err(MauveError::InvalidLabel(pair.to_string())),
+        })
+        .collect()
+}
+
+/// Object metadata parsed from S3 request headers
+///
+/// `x-amz-meta-<name>: <value>` headers and `x-amz-tagging` pairs both become labels.
+pub struct S3Metadata(Metadata);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for S3Metadata {
+    type Error = MauveError;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = req.headers();
+        let mut meta = Metadata {
+            content_type: headers
+                .get_one("Content-Type")
+                .unwrap_or_default()
+                .to_string(),
+            content_encoding: headers
+                .get_one("Content-Encoding")
+                .unwrap_or_default()
+                .to_string(),
+            content_language: headers
+                .get_one("Content-Language")
+                .unwrap_or_default()
+                .to_string(),
+            ..Default::default()
+        };
+
+        for header in headers.iter() {
+            let name = header.name().as_str().to_ascii_lowercase();
+            if let Some(name) = name.strip_prefix("x-amz-meta-") {
+                meta.labels.insert(Label::exact(name, header.value()));
+            }
+        }
+
+        if let Some(tagging) = headers.get_one("x-amz-tagging") {
+            match parse_tagging(tagging) {
+                Ok(labels) => meta.labels.extend(labels),
+                Err(e) => return request::Outcome::Error((Status::BadRequest, e)),
+            }
+        }
+
+        if let Some(config) = req.rocket().state::<AppConfig>() {
+            meta.normalize(&config.mauve);
+            if let Err(e) = meta.check_limits(&config.mauve) {
+                return request::Outcome::Error((Status::BadRequest, e));
+            }
+        }
+
+        request::Outcome::Success(Self(meta))
+    }
+}
+
+/// An object body with its metadata mapped onto S3 response headers
+pub struct S3Object(ObjectWithMetadata);
+
+impl<'r> Responder<'r, 'static> for S3Object {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let ObjectWithMetadata { object, mut meta } = self.0;
+        if meta.content_hash.is_empty() {
+            meta.content_hash = etag(&object);
+        }
+        let mut res = Response::build();
+        object_headers(&mut res, &meta);
+        res.sized_body(object.len(), Cursor::new(object)).ok()
+    }
+}
+
+/// An object's metadata mapped onto the headers a GetObject would send, with no body
+///
+/// Rocket strips the body from the response but keeps its `Content-Length`, which is the
+/// stored size, so the object itself is never read.
+pub struct S3ObjectHead(Metadata);
+
+impl<'r> Responder<'r, 'static> for S3ObjectHead {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut res = Response::build();
+        object_headers(&mut res, &self.0);
+        res.sized_body(self.0.size as usize, Cursor::new(vec![]))
+            .ok()
+    }
+}
+
+/// Response to a PutObject, carrying the stored object's `ETag`
+pub struct S3Stored(String);
+
+impl<'r> Responder<'r, 'static> for S3Stored {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .raw_header("ETag", format!("\"{}\"", self.0))
+            .ok()
+    }
+}
+
+/// Set the headers describing an object that GetObject and HeadObject both send
+fn object_headers(res: &mut response::Builder<'_>, meta: &Metadata) {
+    res.header(ContentType::parse_flexible(&meta.content_type).unwrap_or(ContentType::Binary));
+    res.raw_header("ETag", format!("\"{}\"", meta.content_hash));
+    res.raw_header(
+        "Last-Modified",
+        UtcTime::from_millis(meta.updated_at).http_date(),
+    );
+    if !meta.content_encoding.is_empty() {
+        res.raw_header("Content-Encoding", meta.content_encoding.clone());
+    }
+    if !meta.content_language.is_empty() {
+        res.raw_header("Content-Language", meta.content_language.clone());
+    }
+    for label in &meta.labels {
+        res.raw_header_adjoin(format!("x-amz-meta-{}", label.name), label.value.clone());
+    }
+    res.raw_header("x-amz-tagging-count", meta.labels.len().to_string());
+}
+
+/// A UTC time split into the fields of the date formats S3 uses
+struct UtcTime {
+    year: u64,
+    month: u64,
+    day: u64,
+    weekday: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    millis: u64,
+}
+
+impl UtcTime {
+    /// Convert milliseconds since the epoch, such as `Metadata::updated_at`
+    fn from_millis(ms: u64) -> Self {
+        let (days, ms) = (ms / 86_400_000, ms % 86_400_000);
+        // Civil date from days since the epoch, counting eras of 400 years from 0000-03-01
+        let z = days + 719_468;
+        let (era, doe) = (z / 146_097, z % 146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            year: yoe + era * 400 + u64::from(month <= 2),
+            month,
+            day: doy - (153 * mp + 2) / 5 + 1,
+            // The epoch was a Thursday
+            weekday: (days + 4) % 7,
+            hour: ms / 3_600_000,
+            minute: ms / 60_000 % 60,
+            second: ms / 1000 % 60,
+            millis: ms % 1000,
+        }
+    }
+
+    /// ISO 8601, as ListObjects sends `LastModified`
+    fn iso8601(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+
+    /// An HTTP date, as the `Last-Modified` header is sent
+    fn http_date(&self) -> String {
+        format!(
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[self.weekday as usize],
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+/// Query parameters shared by ListObjects and ListObjectsV2
+#[derive(FromForm, Default)]
+struct ListParams {
+    #[field(name = "list-type")]
+    list_type: Option<u8>,
+    prefix: Option<String>,
+    delimiter: Option<String>,
+    #[field(name = "max-keys")]
+    max_keys: Option<usize>,
+    #[field(name = "continuation-token")]
+    continuation_token: Option<String>,
+    #[field(name = "start-after")]
+    start_after: Option<String>,
+    marker: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListEntry {
+    key: String,
+    last_modified: String,
+    size: u64,
+    storage_class: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CommonPrefix {
+    prefix: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename = "ListBucketResult", rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'static str,
+    name: String,
+    prefix: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delimiter: Option<String>,
+    max_keys: usize,
+    is_truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_marker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_after: Option<String>,
+    contents: Vec<ListEntry>,
+    common_prefixes: Vec<CommonPrefix>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename = "Tagging")]
+struct Tagging {
+    #[serde(rename = "TagSet", default)]
+    tag_set: TagSet,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct TagSet {
+    #[serde(rename = "Tag", default)]
+    tags: Vec<Tag>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Tag {
+    key: String,
+    value: String,
+}
+
+/// ListObjects / ListObjectsV2
+#[get("/<bucket>?<params..>", rank = 1)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn list_objects(
+    backend: &State<Backend>,
+    bucket: &str,
+    params: ListParams,
+) -> Result<(ContentType, String), S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    let v2 = params.list_type == Some(2);
+    let prefix = params.prefix.unwrap_or_default();
+    let delimiter = params.delimiter.filter(|d| !d.is_empty());
+    let max_keys = params.max_keys.unwrap_or(1000);
+    let after = match v2 {
+        true => params
+            .continuation_token
+            .clone()
+            .or(params.start_after.clone()),
+        false => params.marker.clone(),
+    };
+
+    let mut contents = vec![];
+    let mut common_prefixes: Vec<CommonPrefix> = vec![];
+    let mut last_key: Option<String> = None;
+    let mut is_truncated = false;
+    for key in collection.list_objects_after(&prefix, after.as_deref()) {
+        let key = key?;
+
+        // Keys sharing a prefix up to the delimiter roll up into one common prefix
+        let common = delimiter.as_ref().and_then(|d| {
+            key[prefix.len()..]
+                .find(d.as_str())
+                .map(|i| key[..prefix.len() + i + d.len()].to_string())
+        });
+        if let Some(common) = &common {
+            if common_prefixes.last().is_some_and(|p| &p.prefix == common) {
+                last_key = Some(key);
+                continue;
+            }
+        }
+
+        if contents.len() + common_prefixes.len() >= max_keys {
+            is_truncated = true;
+            break;
+        }
+
+        match common {
+            Some(prefix) => common_prefixes.push(CommonPrefix { prefix }),
+            None => contents.push(list_entry(&collection, &key)?),
+        }
+        last_key = Some(key);
+    }
+
+    let next = is_truncated.then_some(last_key).flatten();
+    let key_count = contents.len() + common_prefixes.len();
+    let result = ListBucketResult {
+        xmlns: S3_XMLNS,
+        name: bucket.to_string(),
+        prefix,
+        delimiter,
+        max_keys,
+        is_truncated,
+        marker: (!v2).then(|| params.marker.unwrap_or_default()),
+        next_marker: if v2 { None } else { next.clone() },
+        key_count: v2.then_some(key_count),
+        continuation_token: if v2 { params.continuation_token } else { None },
+        next_continuation_token: if v2 { next } else { None },
+        start_after: if v2 { params.start_after } else { None },
+        contents,
+        common_prefixes,
+    };
+    Ok((ContentType::XML, to_xml(&result)?))
+}
+
+/// GetObject
+#[get("/<bucket>/<key..>", rank = 3)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn get_object(
+    backend: &State<Backend>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+) -> Result<S3Object, S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    let key = object_key(key);
+    let object = collection.get_object(&key)?;
+    let meta = object_metadata(&collection, &key)?;
+    Ok(S3Object(ObjectWithMetadata { object, meta }))
+}
+
+/// HeadObject
+///
+/// Only the metadata is read, unless the object was stored without a content hash.
+#[head("/<bucket>/<key..>", rank = 3)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn head_object(
+    backend: &State<Backend>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+) -> Result<Either<S3ObjectHead, S3Object>, S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    let key = object_key(key);
+    let meta = object_metadata(&collection, &key)?;
+    if !meta.content_hash.is_empty() {
+        return Ok(Either::Left(S3ObjectHead(meta)));
+    }
+    let object = collection.get_object(&key)?;
+    Ok(Either::Right(S3Object(ObjectWithMetadata { object, meta })))
+}
+
+/// PutObject
+#[put("/<bucket>/<key..>", data = "<payload>", rank = 3)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn put_object(
+    backend: &State<Backend>,
+    config: &State<AppConfig>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+    meta: S3Metadata,
+    length: ContentLength,
+    payload: Data<'_>,
+) -> Result<S3Stored, S3Error> {
+    let collection = backend.get_collection(bucket)?;
+    let key = object_key(key);
+    let limit = collection.max_object_size_mb()?.mebibytes();
+    let object = read_payload(payload, limit, length).await?;
+    let mut meta = meta.0;
+    meta.size = object.len() as u64;
+    meta.sniff_content_type(&object, &config.mauve);
+    meta.content_hash = etag(&object);
+    let stored = S3Stored(meta.content_hash.clone());
+
+    // Metadata goes first so the indexer sees the labels when the object lands
+    collection.put_object_metadata(&key, meta)?;
+    collection.put_object(&key, object, true)?;
+    Ok(stored)
+}
+
+/// DeleteObject
+///
+/// The object's metadata and labels are dropped with it.
+#[delete("/<bucket>/<key..>", rank = 3)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn delete_object(
+    backend: &State<Backend>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+) -> Result<Status, S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    collection.delete_object(&object_key(key))?;
+    Ok(Status::NoContent)
+}
+
+/// GetObjectTagging
+#[get("/<bucket>/<key..>?tagging", rank = 2)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn get_object_tagging(
+    backend: &State<Backend>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+) -> Result<(ContentType, String), S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    let key = object_key(key);
+    if !collection.head_object(&key)? {
+        return Err(MauveError::CollectionError(CollectionError::ObjectNotFound).into());
+    }
+    let meta = object_metadata(&collection, &key)?;
+    let mut tags: Vec<Tag> = meta
+        .labels
+        .into_iter()
+        .map(|label| Tag {
+            key: label.name,
+            value: label.value,
+        })
+        .collect();
+    tags.sort_by(|a, b| (&a.key, &a.value).cmp(&(&b.key, &b.value)));
+    let tagging = Tagging {
+        tag_set: TagSet { tags },
+    };
+    Ok((ContentType::XML, to_xml(&tagging)?))
+}
+
+/// PutObjectTagging
+#[put("/<bucket>/<key..>?tagging", data = "<body>", rank = 2)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn put_object_tagging(
+    backend: &State<Backend>,
+    config: &State<AppConfig>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+    body: String,
+) -> Result<Status, S3Error> {
+    let tagging: Tagging = quick_xml::de::from_str(&body)
+        .map_err(|e| S3Error::new(Status::BadRequest, "MalformedXML", e))?;
+    let labels = tagging
+        .tag_set
+        .tags
+        .iter()
+        .map(|tag| Label::exact(&tag.key, &tag.value))
+        .collect();
+    let collection = backend.find_collection(bucket)?;
+    replace_labels(&collection, &object_key(key), labels, &config.mauve)?;
+    Ok(Status::Ok)
+}
+
+/// DeleteObjectTagging
+#[delete("/<bucket>/<key..>?tagging", rank = 2)]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(bucket = %bucket)))]
+async fn delete_object_tagging(
+    backend: &State<Backend>,
+    config: &State<AppConfig>,
+    bucket: &str,
+    key: Segments<'_, Path>,
+) -> Result<Status, S3Error> {
+    let collection = backend.find_collection(bucket)?;
+    replace_labels(&collection, &object_key(key), HashSet::new(), &config.mauve)?;
+    Ok(Status::NoContent)
+}
+
+/// Get an object's metadata, treating objects stored without metadata as unlabeled
+fn object_metadata(collection: &Collection, key: &str) -> Result<Metadata, MauveError> {
+    match collection.get_object_metadata(key) {
+        Ok(meta) => Ok(meta),
+        Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+            Ok(Metadata::default())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Describe an object for a listing from its metadata, reading the object only if it was
+/// stored without any
+fn list_entry(collection: &Collection, key: &str) -> Result<ListEntry, MauveError> {
+    let meta = match collection.get_object_metadata(key) {
+        Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => Metadata {
+            size: collection.get_object(key)?.len() as u64,
+            ..Default::default()
+        },
+        meta => meta?,
+    };
+    Ok(ListEntry {
+        key: key.to_string(),
+        last_modified: UtcTime::from_millis(meta.updated_at).iso8601(),
+        size: meta.size,
+        storage_class: "STANDARD",
+    })
+}
+
+/// Replace the labels on an existing object
+///
+/// The indexer only watches the data tree, so the object is written back unchanged to
+/// have the new labels picked up.
+fn replace_labels(
+    collection: &Collection,
+    key: &str,
+    labels: HashSet<Label>,
+    config: &MauveConfig,
+) -> Result<(), MauveError> {
+    let object = collection.get_object(key)?;
+    let mut meta = object_metadata(collection, key)?;
+    meta.labels = labels;
+    meta.normalize(config);
+    meta.check_limits(config)?;
+    collection.put_object_metadata(key, meta)?;
+    collection.put_object(key, object, true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{
+        fairing::AdHoc,
+        http::{Header, Status},
+        local::asynchronous::Client,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::UtcTime;
+    use crate::{
+        api::{self, mauve_rocket},
+        backend::Backend,
+        config::AppConfig,
+        meta::etag,
+    };
+
+    fn test_config() -> AppConfig {
+        let mut config = api::tests::test_config();
+        config.mauve.s3_enabled = true;
+        config
+    }
+
+    #[rocket::async_test]
+    async fn test_s3_roundtrip() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend.clone(), config.clone())).await?;
+
+        for key in ["docs/a.txt", "docs/b.txt", "top.txt"] {
+            let res = client
+                .put(format!("/s3/bucket/{key}"))
+                .header(Header::new("x-amz-meta-env", "prod"))
+                .header(Header::new("x-amz-tagging", "team=infra"))
+                .body(key)
+                .dispatch()
+                .await;
+            assert_eq!(res.status(), Status::Ok);
+            let tag = format!("\"{}\"", etag(key.as_bytes()));
+            assert_eq!(res.headers().get_one("ETag"), Some(tag.as_str()));
+        }
+
+        let tag = format!("\"{}\"", etag(b"docs/a.txt"));
+        let res = client.get("/s3/bucket/docs/a.txt").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("x-amz-meta-env"), Some("prod"));
+        assert_eq!(res.headers().get_one("ETag"), Some(tag.as_str()));
+        assert_eq!(res.into_string().await.as_deref(), Some("docs/a.txt"));
+        let meta = backend
+            .get_collection("bucket")?
+            .get_object_metadata("docs/a.txt")?;
+        assert_eq!(meta.size, 10);
+        let modified = UtcTime::from_millis(meta.updated_at);
+        assert_eq!(
+            last_modified(&client, "/s3/bucket/docs/a.txt").await,
+            Some(modified.http_date())
+        );
+
+        // HEAD is answered from the metadata alone
+        backend
+            .get_collection("bucket")?
+            .data
+            .insert("docs/a.txt", b"stale".to_vec())?;
+        let res = client.head("/s3/bucket/docs/a.txt").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        assert_eq!(res.headers().get_one("ETag"), Some(tag.as_str()));
+        assert_eq!(res.body().preset_size(), Some(10));
+        assert_eq!(res.headers().get_one("x-amz-meta-env"), Some("prod"));
+
+        let res = client
+            .get("/s3/bucket?list-type=2&delimiter=/")
+            .dispatch()
+            .await;
+        let xml = res.into_string().await.unwrap_or_default();
+        assert!(xml.contains("<CommonPrefixes><Prefix>docs/</Prefix></CommonPrefixes>"));
+        assert!(xml.contains("<Key>top.txt</Key>"));
+        assert!(xml.contains("<KeyCount>2</KeyCount>"));
+
+        let res = client
+            .get("/s3/bucket?list-type=2&prefix=docs/&max-keys=1")
+            .dispatch()
+            .await;
+        let xml = res.into_string().await.unwrap_or_default();
+        assert!(xml.contains("<IsTruncated>true</IsTruncated>"));
+        assert!(xml.contains("<NextContinuationToken>docs/a.txt</NextContinuationToken>"));
+        // Sizes and modification times come from the metadata too
+        assert!(xml.contains("<Size>10</Size>"));
+        let modified = format!("<LastModified>{}</LastModified>", modified.iso8601());
+        assert!(xml.contains(&modified));
+
+        let res = client
+            .get("/s3/bucket?list-type=2&prefix=docs/&continuation-token=docs/a.txt")
+            .dispatch()
+            .await;
+        let xml = res.into_string().await.unwrap_or_default();
+        assert!(xml.contains("<Key>docs/b.txt</Key>"));
+        assert!(!xml.contains("<Key>docs/a.txt</Key>"));
+        assert!(xml.contains("<IsTruncated>false</IsTruncated>"));
+
+        let res = client.get("/s3/bucket/top.txt?tagging").dispatch().await;
+        let xml = res.into_string().await.unwrap_or_default();
+        assert!(xml.contains("<Tag><Key>team</Key><Value>infra</Value></Tag>"));
+
+        let res = client.delete("/s3/bucket/top.txt").dispatch().await;
+        assert_eq!(res.status(), Status::NoContent);
+        let res = client.head("/s3/bucket/top.txt").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+
+        // Reading from a bucket that was never written to doesn't create it
+        let res = client.get("/s3/missing?list-type=2").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        let xml = res.into_string().await.unwrap_or_default();
+        assert!(xml.contains("<Code>NoSuchBucket</Code>"));
+        let res = client.get("/s3/missing/top.txt").dispatch().await;
+        assert_eq!(res.status(), Status::NotFound);
+        assert!(!backend.collection_exists("missing"));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    async fn last_modified(client: &Client, uri: &str) -> Option<String> {
+        let res = client.head(uri).dispatch().await;
+        res.headers().get_one("Last-Modified").map(str::to_string)
+    }
+
+    #[test]
+    fn test_utc_time() {
+        let time = UtcTime::from_millis(1_700_000_000_123);
+        assert_eq!(time.iso8601(), "2023-11-14T22:13:20.123Z");
+        assert_eq!(time.http_date(), "Tue, 14 Nov 2023 22:13:20 GMT");
+        let time = UtcTime::from_millis(951_782_400_000);
+        assert_eq!(time.iso8601(), "2000-02-29T00:00:00.000Z");
+        assert_eq!(time.http_date(), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(
+            UtcTime::from_millis(0).iso8601(),
+            "1970-01-01T00:00:00.000Z"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_chunked_put_over_limit() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.object_max_size_mb = 1;
+        let backend = Backend::open(config.clone())?;
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let rocket = mauve_rocket(backend, config.clone())
+            .configure(rocket::Config {
+                port: 0,
+                ..rocket::Config::debug_default()
+            })
+            .attach(AdHoc::on_liftoff("port", |rocket| {
+                Box::pin(async move {
+                    let _ = tx.send(rocket.config().port);
+                })
+            }));
+        let shutdown = rocket.ignite().await?;
+        let handle = shutdown.shutdown();
+        tokio::spawn(shutdown.launch());
+        let port = rx.await?;
+
+        // Send twice the limit in chunks but never the terminating chunk. The server must
+        // answer once the limit is crossed rather than waiting for the whole body.
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        stream
+            .write_all(
+                b"PUT /s3/bucket/big HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n",
+            )
+            .await?;
+        let chunk = [
+            format!("{:x}\r\n", 64 * 1024).into_bytes(),
+            vec![b'x'; 64 * 1024],
+            b"\r\n".to_vec(),
+        ]
+        .concat();
+        for _ in 0..32 {
+            // The server may answer and close before every chunk is sent
+            if stream.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+
+        let mut response = [0u8; 12];
+        tokio::time::timeout(Duration::from_secs(10), stream.read_exact(&mut response)).await??;
+        assert_eq!(&response, b"HTTP/1.1 413");
+
+        handle.notify();
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_key_too_long() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.max_key_len = 16;
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client.put("/s3/bucket/short").body("ok").dispatch().await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .put(format!("/s3/bucket/{}", "k".repeat(17)))
+            .body("too long")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .put(format!("/s3/{}/short", "b".repeat(17)))
+            .body("too long")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_label_limits() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.max_labels_per_object = 2;
+        config.mauve.max_metadata_bytes = 256;
+        let backend = Backend::open(config.clone())?;
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let res = client
+            .put("/s3/bucket/ok")
+            .header(Header::new("x-amz-tagging", "a=1&b=2"))
+            .body("ok")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let res = client
+            .put("/s3/bucket/labels")
+            .header(Header::new("x-amz-tagging", "a=1&b=2&c=3"))
+            .body("too many labels")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+        let res = client
+            .put("/s3/bucket/big")
+            .header(Header::new("x-amz-meta-big", "v".repeat(512)))
+            .body("metadata too large")
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::BadRequest);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
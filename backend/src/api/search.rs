@@ -0,0 +1,203 @@
+use rocket::{
+    http::ContentType,
+    post,
+    response::stream::{stream, TextStream},
+    routes,
+    serde::json::Json,
+    Route, State,
+};
+
+use super::errors::MauveServeError;
+use crate::{
+    backend::Backend,
+    errors::{CollectionError, MauveError},
+    meta::Metadata,
+    search::{FoundObject, SearchRequest, SearchResponse},
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![search, search_stream]
+}
+
+/// Run a search and return every result at once
+#[post("/", data = "<req>")]
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %req.collection)))]
+pub async fn search(
+    backend: &State<Backend>,
+    req: Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, MauveServeError> {
+    Ok(Json(backend.perform_search(req.into_inner()).await?))
+}
+
+/// Run a search and stream the results as newline delimited JSON, one `FoundObject` per
+/// line. Each object's metadata is only read as its line is sent, so memory use stays flat
+/// however many objects match.
+#[post("/_stream", data = "<req>")]
+pub async fn search_stream(
+    backend: &State<Backend>,
+    req: Json<SearchRequest>,
+) -> Result<(ContentType, TextStream![String]), MauveServeError> {
+    let matches = backend.search_matches(&req).await?;
+    let lines = TextStream(stream! {
+        for (collection, objects) in matches {
+            for object in objects {
+                let meta = match collection.get_object_metadata(&object.name) {
+                    Ok(meta) => meta,
+                    Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+                        Metadata::default()
+                    }
+                    Err(e) => {
+                        log::error!(object = object.name; "failed to read search result metadata {e}");
+                        continue;
+                    }
+                };
+                let mut line = serde_json::to_string(&FoundObject::new(object, meta)).unwrap_or_default();
+                line.push('\n');
+                yield line;
+            }
+        }
+    });
+    Ok((ContentType::new("application", "x-ndjson"), lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{
+        http::{ContentType, Status},
+        local::asynchronous::Client,
+    };
+
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::Backend,
+        labels::Label,
+        meta::Metadata,
+        search::{FoundObject, SearchRequest, SearchResponse},
+    };
+
+    #[rocket::async_test]
+    async fn test_search_stream() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("found")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for name in ["a", "b"] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", "mauve"));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        for _ in 0..50 {
+            if collection.index_fwd.contains_key("color=mauve")? {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let mut req = SearchRequest::new("found");
+        req.include(Label::new("color", "mauve"));
+        let res = client
+            .post("/v1/search/_stream")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&req)?)
+            .dispatch()
+            .await;
+        assert_eq!(res.status(), Status::Ok);
+        let body = res.into_string().await.unwrap_or_default();
+        let mut names = vec![];
+        for line in body.lines() {
+            let found: FoundObject = serde_json::from_str(line)?;
+            assert!(found.meta.labels.contains(&Label::new("color", "mauve")));
+            names.push(found.object.name);
+        }
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+
+        // Labels are folded the same as for a search that isn't streamed
+        let mut req = SearchRequest::new("found");
+        req.include(Label::exact("Color", "MAUVE"));
+        let res = client
+            .post("/v1/search/_stream")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&req)?)
+            .dispatch()
+            .await;
+        let body = res.into_string().await.unwrap_or_default();
+        assert_eq!(body.lines().count(), 2);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_search_collections() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let east = backend.get_collection("east")?;
+        let west = backend.get_collection("west")?;
+        // Give the collection indexers time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for (collection, name) in [(&east, "a"), (&west, "a"), (&west, "b")] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", "mauve"));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        for _ in 0..50 {
+            if east.index_fwd.contains_key("color=mauve")?
+                && west.index_fwd.contains_key("color=mauve")?
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let client = Client::tracked(mauve_rocket(backend, config.clone())).await?;
+
+        let search = |collection: serde_json::Value| {
+            let client = &client;
+            async move {
+                let res = client
+                    .post("/v1/search/")
+                    .json(&serde_json::json!({
+                        "collection": collection,
+                        "labels": [{ "Include": { "name": "color", "value": "mauve" } }],
+                    }))
+                    .dispatch()
+                    .await;
+                let res: SearchResponse = res.into_json().await.unwrap();
+                let mut found: Vec<_> = res
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.to_string())
+                    .collect();
+                found.sort();
+                found
+            }
+        };
+        // A collection listed twice is only searched once
+        assert_eq!(
+            search(serde_json::json!(["east", "west", "east"])).await,
+            ["east/a", "west/a", "west/b"]
+        );
+        assert_eq!(
+            search(serde_json::json!("west")).await,
+            ["west/a", "west/b"]
+        );
+
+        let mut req = SearchRequest::across(&["east", "west"]);
+        req.include(Label::new("color", "mauve"));
+        req.count_only(true);
+        let res = client.post("/v1/search/").json(&req).dispatch().await;
+        let res: SearchResponse = res.into_json().await.unwrap();
+        assert_eq!(res.stats.unwrap_or_default().matched, 3);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
@@ -1,31 +1,138 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::{DashMap, DashSet};
 use flume::{Receiver, Sender};
-use serde::Serialize;
+use object_store::{aws::AmazonS3Builder, path::Path};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError, TransactionalTree},
+    Transactional,
+};
 
 use crate::{
+    bloom::BloomFilter,
     collection::Collection,
-    config::AppConfig,
-    errors::MauveError,
-    indexer::{Indexer, IndexerSignal},
+    config::{AppConfig, MauveConfig, S3BackupConfig},
+    errors::{CollectionError, MauveError},
+    export::{self, Export},
+    indexer::{self, Indexer, IndexerHealth, IndexerSignal, IndexerStatus},
+    meta::Metadata,
+    mirror,
+    objects::{ObjectRef, ToFromMauve},
+    tombstones,
+    upstream::{Upstream, UpstreamCache},
 };
 
 #[derive(Clone)]
 pub struct Backend {
     db: sled::Db,
+    config: MauveConfig,
     signals: (Sender<IndexerSignal>, Receiver<IndexerSignal>),
+    blooms: Arc<DashMap<String, Arc<RwLock<BloomFilter>>>>,
+    upstream: Option<Upstream>,
+    /// Collections the indexer has been asked to watch, so each is only sent once
+    watched: Arc<DashSet<String>>,
+    indexer_health: IndexerHealth,
 }
 
+/// Key in sled's default tree recording whether the label indexes were built
+/// `case_sensitive`
+const CASE_SENSITIVE_KEY: &[u8] = b"mauve_case_sensitive";
+
+/// Time `Backend::shutdown` waits for the collection indexers to exit
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl Backend {
     /// Open the backend from a config
     pub fn open(config: AppConfig) -> Result<Self, MauveError> {
-        let config: sled::Config = config.sled.into();
-        let db = config.open()?;
+        let db = sled::Config::from(config.sled).open()?;
+        if db.was_recovered() {
+            // sled only guarantees data up to the last flush. Also reported as `recovered` by
+            // `/v1/backend/status` and `/v1/backend/stats`.
+            log::warn!("Database recovered from a previous process, writes after its last flush may be missing");
+        }
         let signals = flume::unbounded();
+        let upstream = config
+            .mauve
+            .upstream
+            .as_ref()
+            .map(Upstream::new)
+            .transpose()?;
 
         let this = Self {
             db,
+            config: config.mauve,
             signals: signals.clone(),
+            blooms: Arc::new(DashMap::new()),
+            upstream,
+            watched: Arc::new(DashSet::new()),
+            indexer_health: IndexerHealth::default(),
         };
 
+        if let Some(mirror) = this.config.mirror.clone() {
+            mirror::start(this.clone(), mirror)?;
+        }
+
+        let cached = this
+            .upstream
+            .as_ref()
+            .map(|upstream| upstream.collections.clone())
+            .unwrap_or_default();
+        if this.config.tombstones.is_some() || !cached.is_empty() {
+            tombstones::start(this.clone(), this.config.tombstones.clone(), cached);
+        }
+
+        if let Some(backup) = this.config.s3_backup.clone() {
+            if let Some(secs) = backup.interval_secs {
+                let that = this.clone();
+                tokio::task::spawn(async move {
+                    let period = Duration::from_secs(secs);
+                    let mut interval =
+                        tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    loop {
+                        interval.tick().await;
+                        match that.backup_to_s3(&backup).await {
+                            Ok(key) => log::info!("Scheduled backup uploaded to {key}"),
+                            Err(e) => log::error!("Scheduled backup failed {e}"),
+                        }
+                    }
+                });
+            }
+        }
+
+        // The index refers to objects by case-folded names unless `case_sensitive` is set
+        let case_sensitive = [this.config.case_sensitive as u8];
+        let case_changed = this
+            .db
+            .insert(CASE_SENSITIVE_KEY, &case_sensitive)?
+            .is_some_and(|old| old != case_sensitive[..]);
+        let rebuild = match case_changed {
+            true => Some("case_sensitive changed"),
+            false => {
+                (this.db.was_recovered() && this.config.reindex_on_recovery).then_some("recovery")
+            }
+        };
+        if let Some(reason) = rebuild {
+            let collections: Vec<_> = this.list_collections()?.collect();
+            log::warn!(
+                "Rebuilding the label indexes of {} collections after {reason}",
+                collections.len()
+            );
+            for name in collections {
+                // The indexer picks these up once it starts
+                let collection = this.get_collection(&name)?;
+                this.send_signal(IndexerSignal::Rebuild(collection))?;
+            }
+        } else if this.config.reindex_on_start {
+            for name in this.list_collections()?.collect::<Vec<_>>() {
+                let collection = this.get_collection(&name)?;
+                this.send_signal(IndexerSignal::Reconcile(collection))?;
+            }
+        }
+
         let that = this.clone();
         tokio::task::spawn(async move {
             let indexer = Indexer::initialize(that)?;
@@ -43,23 +150,122 @@ impl Backend {
 
     /// Get a Collection by name
     pub fn get_collection(&self, name: &str) -> Result<Collection, MauveError> {
+        if name.len() > self.config.max_key_len {
+            return Err(MauveError::CollectionError(CollectionError::NameTooLong(
+                self.config.max_key_len,
+            )));
+        }
         let data = self.db.open_tree(format!("mauve_data::{name}"))?;
         let meta = self.db.open_tree(format!("mauve_meta::{name}"))?;
         let index_fwd = self.db.open_tree(format!("mauve_fwd::{name}"))?;
         let index_rev = self.db.open_tree(format!("mauve_rev::{name}"))?;
+        let dlq = self.db.open_tree(format!("mauve_index_dlq::{name}"))?;
+        let index_num = match self.config.numeric_labels.contains_key(name) {
+            true => Some(self.db.open_tree(format!("mauve_num::{name}"))?),
+            false => None,
+        };
+        let changes = self.db.open_tree(format!("mauve_changes::{name}"))?;
+        let changed_at = self.db.open_tree(format!("mauve_changed_at::{name}"))?;
+        let settings = self.db.open_tree(format!("mauve_settings::{name}"))?;
+        let tombstones = match &self.config.tombstones {
+            Some(config) if config.collections.iter().any(|c| c == name) => {
+                Some(self.db.open_tree(format!("mauve_tombstones::{name}"))?)
+            }
+            _ => None,
+        };
+        let bloom = match self.config.bloom_collections.iter().any(|c| c == name) {
+            true => Some(
+                self.blooms
+                    .entry(name.to_string())
+                    .or_try_insert_with(|| {
+                        Ok::<_, MauveError>(Arc::new(RwLock::new(BloomFilter::build(&data)?)))
+                    })?
+                    .clone(),
+            ),
+            false => None,
+        };
+        let upstream = match &self.upstream {
+            Some(upstream) if upstream.collections.iter().any(|c| c == name) => {
+                Some(UpstreamCache {
+                    upstream: upstream.clone(),
+                    expiry: self.db.open_tree(format!("mauve_cache_ttl::{name}"))?,
+                })
+            }
+            _ => None,
+        };
         let this = Collection {
             name: name.to_string(),
             data,
             meta,
             index_fwd,
             index_rev,
+            dlq,
+            index_num,
+            changes,
+            changed_at,
+            settings,
+            tombstones,
+            config: self.config.clone(),
+            bloom,
+            upstream,
         };
-        self.send_signal(IndexerSignal::Watch(this.clone()))?;
+        this.init_counts()?;
+        // Reads and writes work without the indexer, only labels go stale
+        if self.watched.insert(name.to_string()) {
+            if let Err(e) = self.send_signal(IndexerSignal::Watch(this.clone())) {
+                log::warn!(collection = name; "indexer unavailable, collection will not be indexed: {e}");
+                self.watched.remove(name);
+            }
+        }
         Ok(this)
     }
 
+    /// Get an existing Collection by name, without creating it.
+    ///
+    /// Collections read through from an upstream always exist, since their objects may
+    /// only be stored there so far.
+    pub fn find_collection(&self, name: &str) -> Result<Collection, MauveError> {
+        let federated = self
+            .upstream
+            .as_ref()
+            .is_some_and(|upstream| upstream.collections.iter().any(|c| c == name));
+        if !federated && !self.collection_exists(name) {
+            return Err(MauveError::CollectionError(
+                CollectionError::CollectionNotFound,
+            ));
+        }
+        self.get_collection(name)
+    }
+
+    /// Create a new, empty Collection, failing if one already exists by that name
+    pub fn create_collection(&self, name: &str) -> Result<Collection, MauveError> {
+        if self.collection_exists(name) {
+            return Err(MauveError::CollectionError(
+                CollectionError::CollectionExists,
+            ));
+        }
+        self.get_collection(name)
+    }
+
+    /// Make sure a Collection exists, creating it if needed. Returns `true` if it was created.
+    pub fn ensure_collection(&self, name: &str) -> Result<bool, MauveError> {
+        let created = !self.collection_exists(name);
+        self.get_collection(name)?;
+        Ok(created)
+    }
+
+    /// Returns `true` if a collection by this name has been opened before. This only reads
+    /// the tree names, so it doesn't create the collection or start indexing it.
+    pub fn collection_exists(&self, name: &str) -> bool {
+        let tree = format!("mauve_meta::{name}");
+        self.db
+            .tree_names()
+            .iter()
+            .any(|name| name == tree.as_bytes())
+    }
+
     /// Get a list of all the collections stored on this Backend
-    pub fn list_collections(&self) -> Result<impl IntoIterator<Item = String>, MauveError> {
+    pub fn list_collections(&self) -> Result<impl Iterator<Item = String> + Send, MauveError> {
         let mut collections = vec![];
         for name in self.db.tree_names() {
             let s = match String::from_utf8(name.to_vec()) {
@@ -73,22 +279,367 @@ impl Backend {
                 collections.push(s.strip_prefix("mauve_meta::").unwrap().to_string());
             }
         }
-        Ok(collections)
+        Ok(collections.into_iter())
+    }
+
+    /// Copy every object and its metadata from `src` into `dst`, returning the number of
+    /// objects copied.
+    ///
+    /// If `dst` already holds objects it is deleted and recreated when `overwrite` is set,
+    /// otherwise the copy is refused. The label indexes are not copied: their object refs
+    /// name the source collection, so the destination's index is rebuilt by its indexer.
+    pub fn copy_collection(
+        &self,
+        src: &str,
+        dst: &str,
+        overwrite: bool,
+    ) -> Result<usize, MauveError> {
+        if src == dst {
+            return Err(MauveError::CollectionError(
+                CollectionError::CollectionExists,
+            ));
+        }
+        let source = self.find_collection(src)?;
+        let mut dest = self.get_collection(dst)?;
+        if !dest.data.is_empty() || !dest.meta.is_empty() {
+            if !overwrite {
+                return Err(MauveError::CollectionError(
+                    CollectionError::CollectionExists,
+                ));
+            }
+            self.delete_collection(dst)?;
+            dest = self.get_collection(dst)?;
+        }
+
+        // Metadata goes first so the indexer finds it for each object that lands
+        let mut meta = sled::Batch::default();
+        for entry in source.meta.iter() {
+            let (key, value) = entry?;
+            meta.insert(key, value);
+        }
+        dest.meta.apply_batch(meta)?;
+        let mut data = sled::Batch::default();
+        let mut keys = vec![];
+        for entry in source.data.iter() {
+            let (key, value) = entry?;
+            keys.push(String::from_utf8(key.to_vec())?);
+            data.insert(key, value);
+        }
+        dest.data.apply_batch(data)?;
+        dest.recount()?;
+        for key in &keys {
+            dest.record_change(key, false)?;
+        }
+        let copied = keys.len();
+
+        dest.rebuild_bloom()?;
+        // The objects are copied either way, only their labels go unindexed
+        if let Err(e) = self.send_signal(IndexerSignal::Rebuild(dest)) {
+            log::warn!(collection = dst; "indexer unavailable, copied collection will not be indexed: {e}");
+        }
+        Ok(copied)
+    }
+
+    /// Copy an object and its metadata to `dst_name` in `dst`, which may be the same
+    /// collection. The copy keeps the source's labels, which the destination's indexer picks
+    /// up. An existing destination object is only replaced if `overwrite` is set.
+    pub fn copy_object(
+        &self,
+        src: &str,
+        src_name: &str,
+        dst: &str,
+        dst_name: &str,
+        overwrite: bool,
+    ) -> Result<ObjectRef, MauveError> {
+        self.transfer_object(src, src_name, dst, dst_name, overwrite, false)
+    }
+
+    /// Move an object and its metadata to `dst_name` in `dst`, like `copy_object` but
+    /// removing the source. The source is removed in the same transaction as the copy is
+    /// written, so the object is never missing from both.
+    pub fn move_object(
+        &self,
+        src: &str,
+        src_name: &str,
+        dst: &str,
+        dst_name: &str,
+        overwrite: bool,
+    ) -> Result<ObjectRef, MauveError> {
+        self.transfer_object(src, src_name, dst, dst_name, overwrite, true)
+    }
+
+    fn transfer_object(
+        &self,
+        src: &str,
+        src_name: &str,
+        dst: &str,
+        dst_name: &str,
+        overwrite: bool,
+        remove: bool,
+    ) -> Result<ObjectRef, MauveError> {
+        let source = self.find_collection(src)?;
+        let dest = self.get_collection(dst)?;
+        dest.check_key(dst_name)?;
+        // Reads through from an upstream, if the object is only there so far
+        if !source.head_object(src_name)? {
+            return Err(MauveError::CollectionError(CollectionError::ObjectNotFound));
+        }
+        if src == dst && src_name == dst_name {
+            return Ok(dest.object_ref(dst_name));
+        }
+        dest.bloom_insert(dst_name)?;
+
+        let transfer = |src_data: &TransactionalTree,
+                        src_meta: &TransactionalTree,
+                        dst_data: &TransactionalTree,
+                        dst_meta: &TransactionalTree| {
+            let not_found = MauveError::CollectionError(CollectionError::ObjectNotFound);
+            let object = src_data
+                .get(src_name)?
+                .ok_or(ConflictableTransactionError::Abort(not_found))?;
+            if !overwrite && dst_data.get(dst_name)?.is_some() {
+                return Err(ConflictableTransactionError::Abort(
+                    MauveError::CollectionError(CollectionError::PutObjectExistsNoReplace),
+                ));
+            }
+            let meta = src_meta.get(src_name)?;
+            if remove {
+                src_data.remove(src_name)?;
+                src_meta.remove(src_name)?;
+            }
+            // Metadata goes first so the indexer finds it when the object lands
+            let replaced = match &meta {
+                Some(meta) => dst_meta.insert(dst_name, meta)?,
+                None => dst_meta.remove(dst_name)?,
+            };
+            let len = object.len();
+            let old = dst_data.insert(dst_name, object)?;
+            Ok((meta, replaced, len, old.map(|old| old.len())))
+        };
+        let transferred = match src == dst {
+            true => (&source.data, &source.meta)
+                .transaction(|(data, meta)| transfer(data, meta, data, meta)),
+            false => (&source.data, &source.meta, &dest.data, &dest.meta).transaction(
+                |(src_data, src_meta, dst_data, dst_meta)| {
+                    transfer(src_data, src_meta, dst_data, dst_meta)
+                },
+            ),
+        };
+        let (meta, replaced, len, old) = match transferred {
+            Ok(metas) => metas,
+            Err(TransactionError::Abort(e)) => return Err(e),
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        };
+        let meta = meta
+            .map(|meta| Metadata::from_object(meta.to_vec()))
+            .transpose()?;
+        dest.count_write(old, Some(len))?;
+        if remove {
+            source.count_write(Some(len), None)?;
+        }
+
+        // The indexer only adds the copy's labels, so drop any the replaced object had
+        if let Some(replaced) = replaced {
+            let mut replaced = Metadata::from_object(replaced.to_vec())?;
+            if let Some(meta) = &meta {
+                replaced.labels.retain(|label| !meta.labels.contains(label));
+            }
+            indexer::unindex(&dest, dst_name, replaced)?;
+        }
+        if let Some(upstream) = &dest.upstream {
+            upstream.forget(dst_name)?;
+        }
+        dest.unbury(dst_name)?;
+        dest.record_change(dst_name, false)?;
+        if remove {
+            if let Some(upstream) = &source.upstream {
+                upstream.deleted(src_name)?;
+            }
+            if let Some(meta) = meta {
+                indexer::unindex(&source, src_name, meta)?;
+            }
+            source.bury(src_name)?;
+            source.record_change(src_name, true)?;
+        }
+        Ok(dest.object_ref(dst_name))
     }
 
     /// Delete a named collection. This cannot be undone.
+    ///
+    /// Fails with `CollectionNotFound` rather than creating a collection just to delete it.
     pub fn delete_collection(&self, name: &str) -> Result<String, MauveError> {
-        self.send_signal(IndexerSignal::Unwatch(self.get_collection(name)?))?;
+        if let Err(e) = self.send_signal(IndexerSignal::Unwatch(self.find_collection(name)?)) {
+            log::warn!(collection = name; "indexer unavailable, deleting collection anyway: {e}");
+        }
         self.db.drop_tree(format!("mauve_data::{name}"))?;
         self.db.drop_tree(format!("mauve_meta::{name}"))?;
         self.db.drop_tree(format!("mauve_fwd::{name}"))?;
         self.db.drop_tree(format!("mauve_rev::{name}"))?;
+        self.db.drop_tree(format!("mauve_index_dlq::{name}"))?;
+        self.db.drop_tree(format!("mauve_num::{name}"))?;
+        self.db.drop_tree(format!("mauve_changes::{name}"))?;
+        self.db.drop_tree(format!("mauve_changed_at::{name}"))?;
+        self.db.drop_tree(format!("mauve_settings::{name}"))?;
+        self.db.drop_tree(format!("mauve_tombstones::{name}"))?;
+        self.db.drop_tree(format!("mauve_cache_ttl::{name}"))?;
+        self.blooms.remove(name);
+        self.watched.remove(name);
         Ok(name.to_string())
     }
 
+    /// Stream every tree in the backend in the export format described in `export`
+    pub fn export_stream(&self) -> Export {
+        Export::new(self.db.clone())
+    }
+
+    /// Resume an interrupted export after a `<tree>:<hex key>` position. See `export` for the
+    /// ordering this relies on.
+    pub fn resume_export(&self, position: &str) -> Result<Export, MauveError> {
+        Export::resume_after(self.db.clone(), position)
+    }
+
+    /// Export every collection's objects and metadata as one serializable value.
+    ///
+    /// Unlike `export_stream` this holds the whole backend in memory and leaves out the
+    /// label indexes, which `import` rebuilds.
+    pub fn export(&self) -> Result<BackendExport, MauveError> {
+        let mut collections = vec![];
+        for name in self.list_collections()? {
+            let collection = self.get_collection(&name)?;
+            let mut objects = vec![];
+            for entry in collection.data.iter() {
+                let (key, object) = entry?;
+                let key = String::from_utf8(key.to_vec())?;
+                let meta = match collection.meta.get(&key)? {
+                    Some(meta) => Some(Metadata::from_object(meta.to_vec())?),
+                    None => None,
+                };
+                objects.push(ExportedObject {
+                    key,
+                    object: object.to_vec(),
+                    meta,
+                });
+            }
+            collections.push(CollectionExport { name, objects });
+        }
+        Ok(BackendExport { collections })
+    }
+
+    /// Replace the contents of the backend with an export.
+    ///
+    /// Every existing collection is deleted first, so afterwards the backend holds exactly
+    /// what was exported. Label indexes are rebuilt by the indexer.
+    pub fn import(&self, export: BackendExport) -> Result<(), MauveError> {
+        for name in self.list_collections()?.collect::<Vec<_>>() {
+            self.delete_collection(&name)?;
+        }
+        for CollectionExport { name, objects } in export.collections {
+            let collection = self.get_collection(&name)?;
+            // Metadata goes first so the indexer finds it for each object that lands
+            let mut meta = sled::Batch::default();
+            let mut data = sled::Batch::default();
+            let mut keys = vec![];
+            for ExportedObject {
+                key,
+                object,
+                meta: object_meta,
+            } in objects
+            {
+                if let Some(object_meta) = object_meta {
+                    meta.insert(key.as_bytes(), object_meta.to_object()?);
+                }
+                data.insert(key.as_bytes(), object);
+                keys.push(key);
+            }
+            collection.meta.apply_batch(meta)?;
+            collection.data.apply_batch(data)?;
+            collection.recount()?;
+            for key in &keys {
+                collection.record_change(key, false)?;
+            }
+
+            collection.rebuild_bloom()?;
+            if let Err(e) = self.send_signal(IndexerSignal::Rebuild(collection)) {
+                log::warn!(collection = name; "indexer unavailable, imported collection will not be indexed: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload an export of the backend to an S3 bucket under a timestamped key.
+    ///
+    /// Returns the key the backup was written to.
+    pub async fn backup_to_s3(&self, config: &S3BackupConfig) -> Result<String, MauveError> {
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(&config.bucket);
+        if let Some(region) = &config.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_endpoint(endpoint);
+        }
+        let store = Arc::new(builder.build()?);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| MauveError::Oops(e.to_string()))?
+            .as_secs();
+        let key = format!("{}mauve-{timestamp}.backup", config.prefix);
+        export::upload(self.export_stream(), store, Path::from(key.as_str())).await?;
+        Ok(key)
+    }
+
     /// Get backend status
     pub fn status(&self) -> Result<BackendState, MauveError> {
-        Ok(self.clone().try_into()?)
+        self.clone().try_into()
+    }
+
+    /// Get the cheap backend stats. Unlike `status` this computes no checksums and doesn't
+    /// count tree entries, so it's fine to scrape often.
+    pub fn stats(&self) -> Result<BackendStats, MauveError> {
+        Ok(BackendStats {
+            size_on_disk: self.db.size_on_disk()?,
+            recovered: self.db.was_recovered(),
+            collections: self.list_collections()?.count(),
+        })
+    }
+
+    /// Get a ref to the backend config
+    pub(crate) fn config(&self) -> &MauveConfig {
+        &self.config
+    }
+
+    /// Stop the indexer and flush the database.
+    ///
+    /// Collection indexers index the writes already made before they exit, and are given
+    /// `SHUTDOWN_TIMEOUT` to do so. Writes made after this is called may not be indexed.
+    pub async fn shutdown(&self) -> Result<(), MauveError> {
+        log::info!("Shutting down the backend");
+        self.send_signal(IndexerSignal::Shutdown)?;
+        // Collection indexers clear their alive stamp as they exit
+        let drained = async {
+            while !self.indexer_health.alive.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, drained)
+            .await
+            .is_err()
+        {
+            log::warn!(
+                "Collection indexers still running after {}s, flushing without them",
+                SHUTDOWN_TIMEOUT.as_secs()
+            );
+        }
+        let flushed = self.db.flush_async().await?;
+        log::info!("Flushed {flushed} bytes to disk");
+        Ok(())
+    }
+
+    /// Get a ref to the indexer state shared with the indexer
+    pub(crate) fn indexer_health(&self) -> &IndexerHealth {
+        &self.indexer_health
     }
 
     /// Get a ref to the backend sled Db
@@ -104,6 +655,26 @@ impl Backend {
     }
 }
 
+/// Every collection in a backend, as returned by `Backend::export`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BackendExport {
+    pub collections: Vec<CollectionExport>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CollectionExport {
+    pub name: String,
+    pub objects: Vec<ExportedObject>,
+}
+
+/// An object with its metadata, if it was stored with any
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedObject {
+    pub key: String,
+    pub object: Vec<u8>,
+    pub meta: Option<Metadata>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct TreeState {
     pub checksum: u32,
@@ -126,6 +697,13 @@ impl TryInto<TreeState> for sled::Tree {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackendStats {
+    pub size_on_disk: u64,
+    pub recovered: bool,
+    pub collections: usize,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct BackendState {
     pub checksum: u32,
@@ -133,6 +711,8 @@ pub struct BackendState {
     pub size: u64,
     pub trees: Vec<TreeState>,
     pub recovered: bool,
+    /// The indexer of every collection
+    pub indexers: Vec<IndexerStatus>,
 }
 
 impl TryInto<BackendState> for Backend {
@@ -147,12 +727,143 @@ impl TryInto<BackendState> for Backend {
         for tree_name in self.db.tree_names() {
             trees.push(self.db.open_tree(tree_name)?.try_into()?);
         }
+        let indexers = self
+            .list_collections()?
+            .map(|collection| self.indexer_health.status(&collection))
+            .collect();
         Ok(BackendState {
             checksum,
             name,
             size,
             trees,
             recovered,
+            indexers,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        api::tests::test_config,
+        backend::Backend,
+        errors::{CollectionError, MauveError},
+        labels::Label,
+        meta::Metadata,
+        objects::{ObjectRef, ObjectRefs},
+    };
+
+    #[rocket::async_test]
+    async fn test_export_import() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("snapshot")?;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("labeled", meta)?;
+        collection.put_object("labeled", b"one".to_vec(), false)?;
+        collection.put_object("plain", b"two".to_vec(), false)?;
+
+        let export = backend.export()?;
+        let json = serde_json::to_vec(&export)?;
+
+        let restored_config = test_config();
+        let restored = Backend::open(restored_config.clone())?;
+        restored
+            .get_collection("stale")?
+            .put_object("x", vec![], false)?;
+        restored.import(serde_json::from_slice(&json)?)?;
+
+        assert_eq!(
+            restored.list_collections()?.collect::<Vec<_>>(),
+            ["snapshot"]
+        );
+        let collection = restored.get_collection("snapshot")?;
+        assert_eq!(collection.get_object("labeled")?, b"one");
+        assert_eq!(collection.get_object("plain")?, b"two");
+        let label = Label::new("color", "mauve");
+        assert!(collection
+            .get_object_metadata("labeled")?
+            .labels
+            .contains(&label));
+
+        let mut refs = None;
+        for _ in 0..50 {
+            refs = collection.index_fwd.get(label.to_fwd())?;
+            if refs.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let refs = ObjectRefs::from_index(&refs.unwrap())?;
+        assert_eq!(refs.to_vec(), vec![ObjectRef::new("snapshot", "labeled")]);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        std::fs::remove_dir_all(restored_config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_watch_once() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        for _ in 0..3 {
+            backend.get_collection("watched")?;
+        }
+        assert_eq!(backend.watched.len(), 1);
+
+        // A collection created again after a delete is watched again
+        backend.delete_collection("watched")?;
+        assert!(backend.watched.is_empty());
+
+        // Deleting a collection that doesn't exist doesn't create it
+        assert!(matches!(
+            backend.delete_collection("watched"),
+            Err(MauveError::CollectionError(
+                CollectionError::CollectionNotFound
+            ))
+        ));
+        assert!(!backend.collection_exists("watched"));
+        let collection = backend.get_collection("watched")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("a", meta)?;
+        collection.put_object("a", vec![], false)?;
+        for _ in 0..50 {
+            if collection.index_fwd.contains_key("color=mauve")? {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(collection.index_fwd.contains_key("color=mauve")?);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_shutdown() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("stopping")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!backend.indexer_health.alive.is_empty());
+
+        // Writes made before the shutdown are indexed before it returns
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("a", meta)?;
+        collection.put_object("a", vec![], false)?;
+        backend.shutdown().await?;
+        assert!(backend.indexer_health.alive.is_empty());
+        assert!(collection.index_fwd.contains_key("color=mauve")?);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+//! Bloom filter
+//!
+//! An in-memory Bloom filter over the object keys of a collection. It answers "definitely
+//! not present" without reading the data tree, which makes `head_object` and misses in
+//! `get_object` cheap for collections used as existence sets.
+//!
+//! Bloom filters cannot forget keys, so deleted objects keep testing as "maybe present"
+//! until the filter is rebuilt from the data tree.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    f64::consts::LN_2,
+    hash::{Hash, Hasher},
+};
+
+use crate::errors::MauveError;
+
+/// Target false positive rate when sizing a filter
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// Smallest number of keys a filter is sized for
+const MIN_CAPACITY: usize = 1024;
+
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u64,
+    capacity: usize,
+    items: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized to hold `capacity` keys at the target false positive rate
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let num_bits =
+            (-(capacity as f64) * FALSE_POSITIVE_RATE.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let num_hashes = ((num_bits as f64 / capacity as f64) * LN_2)
+            .round()
+            .max(1.0) as u64;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            capacity,
+            items: 0,
+        }
+    }
+
+    /// Build a filter from every key in a data tree, leaving room for it to double in size
+    pub fn build(data: &sled::Tree) -> Result<Self, MauveError> {
+        let mut this = Self::with_capacity(data.len() * 2);
+        for key in data.iter().keys() {
+            this.insert(&key?);
+        }
+        Ok(this)
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_indexes(key) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// Returns `false` if the key was definitely never inserted
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.bit_indexes(key)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns `true` once more keys have been inserted than the filter was sized for
+    pub fn is_full(&self) -> bool {
+        self.items >= self.capacity
+    }
+
+    /// Derive the bit positions for a key by double hashing
+    fn bit_indexes(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        h1.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, FALSE_POSITIVE_RATE};
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::with_capacity(10_000);
+        for n in 0..10_000 {
+            filter.insert(format!("present-{n}").as_bytes());
+        }
+        for n in 0..10_000 {
+            assert!(filter.contains(format!("present-{n}").as_bytes()));
+        }
+        assert!(filter.is_full());
+
+        let false_positives = (0..10_000)
+            .filter(|n| filter.contains(format!("absent-{n}").as_bytes()))
+            .count();
+        // A full filter stays within twice its target rate
+        let limit = (2.0 * FALSE_POSITIVE_RATE * 10_000.0) as usize;
+        assert!(
+            false_positives < limit,
+            "{false_positives} false positives in 10000 lookups"
+        );
+    }
+}
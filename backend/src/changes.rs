@@ -0,0 +1,155 @@
+//! Changes
+//!
+//! A time-ordered feed of the objects written and deleted in a collection, for clients that
+//! sync incrementally. Changes are recorded as each write lands by `Collection::put_object`
+//! and the deletes, and for every object a copy or import writes.
+//!
+//! The feed lives in the collection's `mauve_changes::<name>` tree, keyed by the change time
+//! in milliseconds since the epoch (u64 BE) followed by the object name. Each object has one
+//! entry, for its latest change; `mauve_changed_at::<name>` maps the object name to that
+//! entry's time so the previous entry can be dropped. A delete leaves a tombstone entry,
+//! which is kept so a client that syncs late still learns the object is gone. In
+//! collections that keep `tombstones`, the entry is dropped when its tombstone is pruned.
+
+use std::{
+    ops::Bound,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use macros::MauveObject;
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionalTree},
+    Transactional,
+};
+
+use crate::{collection::Collection, errors::MauveError, objects::ToFromMauve};
+
+/// The latest change to one object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MauveObject)]
+pub struct Change {
+    pub key: String,
+    /// Milliseconds since the epoch
+    pub updated_at: u64,
+    /// `true` if the object was deleted
+    pub deleted: bool,
+}
+
+/// One page of a collection's changes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChangesPage {
+    pub changes: Vec<Change>,
+    /// Position after the last change in this page. Pass it back to continue from there.
+    /// This is `None` when the page is empty and no cursor was given.
+    pub cursor: Option<String>,
+}
+
+impl Collection {
+    /// Record `ident` as changed now, replacing its previous entry in the feed
+    pub(crate) fn record_change(&self, ident: &str, deleted: bool) -> Result<(), MauveError> {
+        let updated_at = now_millis()?;
+        (&self.changes, &self.changed_at).transaction(|(changes, changed_at)| {
+            record_change_tx(changes, changed_at, ident, deleted, updated_at)
+        })?;
+        Ok(())
+    }
+
+    /// Drop `ident` from the feed if its latest change is a delete
+    pub(crate) fn forget_delete(&self, ident: &str) -> Result<(), MauveError> {
+        (&self.changes, &self.changed_at).transaction(|(changes, changed_at)| {
+            let Some(at) = changed_at.get(ident.as_bytes())? else {
+                return Ok(());
+            };
+            let key = change_key(
+                u64::from_be_bytes(at.as_ref().try_into().unwrap_or_default()),
+                ident,
+            );
+            let deleted = match changes.get(&key)? {
+                Some(change) => {
+                    Change::from_object(change.to_vec())
+                        .map_err(ConflictableTransactionError::Abort)?
+                        .deleted
+                }
+                None => false,
+            };
+            if deleted {
+                changes.remove(key)?;
+                changed_at.remove(ident.as_bytes())?;
+            }
+            Ok::<_, ConflictableTransactionError<MauveError>>(())
+        })?;
+        Ok(())
+    }
+
+    /// List up to `limit` changes made after `since` (milliseconds since the epoch), oldest
+    /// first. A `cursor` from a previous page takes the place of `since`.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn list_changes(
+        &self,
+        since: u64,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<ChangesPage, MauveError> {
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(
+                hex::decode(cursor).map_err(|_| MauveError::InvalidCursor(cursor.to_string()))?,
+            ),
+            None => Bound::Included(since.saturating_add(1).to_be_bytes().to_vec()),
+        };
+
+        let mut changes = vec![];
+        let mut last = None;
+        for entry in self
+            .changes
+            .range::<Vec<u8>, _>((start, Bound::Unbounded))
+            .take(limit)
+        {
+            let (key, change) = entry?;
+            changes.push(Change::from_object(change.to_vec())?);
+            last = Some(key);
+        }
+        let cursor = match last {
+            Some(key) => Some(hex::encode(key)),
+            None => cursor.map(str::to_string),
+        };
+        Ok(ChangesPage { changes, cursor })
+    }
+}
+
+/// Record `ident` as changed at `updated_at` as part of a transaction over the collection's
+/// `changes` and `changed_at` trees, replacing its previous entry in the feed
+pub(crate) fn record_change_tx(
+    changes: &TransactionalTree,
+    changed_at: &TransactionalTree,
+    ident: &str,
+    deleted: bool,
+    updated_at: u64,
+) -> Result<(), ConflictableTransactionError<MauveError>> {
+    let change = Change {
+        key: ident.to_string(),
+        updated_at,
+        deleted,
+    }
+    .to_object()
+    .map_err(ConflictableTransactionError::Abort)?;
+    if let Some(old) = changed_at.insert(ident.as_bytes(), &updated_at.to_be_bytes())? {
+        let old = u64::from_be_bytes(old.as_ref().try_into().unwrap_or_default());
+        changes.remove(change_key(old, ident))?;
+    }
+    changes.insert(change_key(updated_at, ident), change)?;
+    Ok(())
+}
+
+pub(crate) fn now_millis() -> Result<u64, MauveError> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| MauveError::Oops(e.to_string()))?
+        .as_millis() as u64)
+}
+
+fn change_key(updated_at: u64, ident: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(8 + ident.len());
+    key.extend(updated_at.to_be_bytes());
+    key.extend(ident.as_bytes());
+    key
+}
@@ -1,13 +1,65 @@
+use std::{
+    collections::HashSet,
+    ops::Bound,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Transactional,
+};
 
 use crate::{
-    errors::{CollectionError::ObjectNotFound, MauveError},
+    bloom::BloomFilter,
+    changes::{now_millis, record_change_tx},
+    config::MauveConfig,
+    errors::{
+        CollectionError::{KeyTooLong, ObjectNotFound},
+        MauveError,
+    },
+    indexer::{self, DeadLetter},
     labels::Label,
     meta::Metadata,
     objects::{ObjectRef, ToFromMauve},
+    tombstones::Tombstone,
+    upstream::UpstreamCache,
 };
 
+/// Key of the object size limit in a collection's settings, a u64 BE of MB
+const MAX_OBJECT_SIZE_MB: &[u8] = b"max_object_size_mb";
+
+/// Key of the object count and total object bytes in a collection's settings, two u64 BE
+const COUNTS: &[u8] = b"counts";
+
+/// Size of a collection's contents
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub objects: usize,
+    /// Total size of the objects, not counting metadata or indexes
+    pub bytes: u64,
+    /// Distinct labels in the label index
+    pub labels: usize,
+}
+
+/// One page of a collection's object names
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ObjectsPage {
+    pub objects: Vec<String>,
+    /// The last name in this page, to pass back as `after` for the next page. This is
+    /// `None` once the listing is done.
+    pub cursor: Option<String>,
+}
+
+/// One object of a batch write, see `Collection::put_objects_batch`
+#[derive(Clone, Debug)]
+pub struct BatchObject {
+    pub name: String,
+    pub object: Vec<u8>,
+    pub meta: Metadata,
+}
+
 #[derive(Clone)]
 pub struct Collection {
     pub name: String,
@@ -15,9 +67,28 @@ pub struct Collection {
     pub(crate) meta: sled::Tree,
     pub(crate) index_fwd: sled::Tree,
     pub(crate) index_rev: sled::Tree,
+    pub(crate) dlq: sled::Tree,
+    pub(crate) index_num: Option<sled::Tree>,
+    pub(crate) changes: sled::Tree,
+    pub(crate) changed_at: sled::Tree,
+    /// Settings of this collection that override the config, see `max_object_size_mb`
+    pub(crate) settings: sled::Tree,
+    pub(crate) tombstones: Option<sled::Tree>,
+    pub(crate) config: MauveConfig,
+    pub(crate) bloom: Option<Arc<RwLock<BloomFilter>>>,
+    pub(crate) upstream: Option<UpstreamCache>,
 }
 
 impl Collection {
+    /// A ref to the object `ident` in this collection, lowercased unless the backend is
+    /// `case_sensitive`
+    pub fn object_ref(&self, ident: &str) -> ObjectRef {
+        match self.config.case_sensitive {
+            true => ObjectRef::exact(&self.name, ident),
+            false => ObjectRef::new(&self.name, ident),
+        }
+    }
+
     pub(crate) fn data_tree(&self) -> sled::Tree {
         self.data.clone()
     }
@@ -34,13 +105,113 @@ impl Collection {
         self.index_rev.clone()
     }
 
+    pub(crate) fn dlq_tree(&self) -> sled::Tree {
+        self.dlq.clone()
+    }
+
+    pub(crate) fn index_num(&self) -> Option<sled::Tree> {
+        self.index_num.clone()
+    }
+
+    /// Returns `true` if labels named `name` are also indexed by numeric value
+    pub(crate) fn is_numeric_label(&self, name: &str) -> bool {
+        self.config
+            .numeric_labels
+            .get(&self.name)
+            .is_some_and(|names| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+    }
+
+    /// Reject object keys longer than the configured maximum before they reach sled.
+    pub(crate) fn check_key(&self, ident: &str) -> Result<(), MauveError> {
+        if ident.len() > self.config.max_key_len {
+            return Err(MauveError::CollectionError(KeyTooLong(
+                self.config.max_key_len,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if the collection's Bloom filter says the key is definitely absent.
+    /// Collections without a filter always return `true`.
+    fn bloom_may_contain(&self, ident: &str) -> bool {
+        match &self.bloom {
+            Some(bloom) => bloom
+                .read()
+                .map(|bloom| bloom.contains(ident.as_bytes()))
+                .unwrap_or(true),
+            None => true,
+        }
+    }
+
+    /// Record a key in the collection's Bloom filter, if it has one. The filter is rebuilt
+    /// from the data tree once it has outgrown its size.
+    pub(crate) fn bloom_insert(&self, ident: &str) -> Result<(), MauveError> {
+        if let Some(bloom) = &self.bloom {
+            let mut bloom = bloom.write().map_err(|e| MauveError::Oops(e.to_string()))?;
+            if bloom.is_full() {
+                *bloom = BloomFilter::build(&self.data)?;
+            }
+            bloom.insert(ident.as_bytes());
+        }
+        Ok(())
+    }
+
+    /// Fetch an object from the upstream if it is missing here or its cached copy has
+    /// expired. Returns `None` if the object should be read locally as usual.
+    ///
+    /// Upstream failures are logged and fall back to the local copy, if any.
+    fn read_through(&self, ident: &str) -> Result<Option<Vec<u8>>, MauveError> {
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream,
+            None => return Ok(None),
+        };
+        match upstream.is_expired(ident)? {
+            // A fresh cached copy, or a fresh miss or local delete without one
+            Some(false) => return Ok(None),
+            // Written locally
+            None if self.data.contains_key(ident)? => return Ok(None),
+            _ => (),
+        }
+        match upstream.fetch(&self.name, ident) {
+            Ok(Some((object, mut meta))) => {
+                meta.normalize(&self.config);
+                self.put_object_metadata(ident, meta)?;
+                self.put_object(ident, object.clone(), true)?;
+                upstream.cached(ident)?;
+                Ok(Some(object))
+            }
+            Ok(None) => {
+                // Gone upstream, so drop any expired copy and remember the miss
+                self.delete_object(ident)?;
+                upstream.missed(ident)?;
+                Ok(None)
+            }
+            Err(e) => {
+                log::warn!(collection = self.name, ident = ident; "upstream read failed {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Rebuild the collection's Bloom filter from the data tree, sized from the current
+    /// object count. This drops deleted keys from the filter.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn rebuild_bloom(&self) -> Result<(), MauveError> {
+        if let Some(bloom) = &self.bloom {
+            let mut bloom = bloom.write().map_err(|e| MauveError::Oops(e.to_string()))?;
+            *bloom = BloomFilter::build(&self.data)?;
+        }
+        Ok(())
+    }
+
     /// Get a list of object keys being stored in the collection matching a given prefix.
     /// This iterates over every object stored. This can be very expensive and time consuming
     /// if there are a huge number of objects stored. Use with caution
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, prefix = %prefix)))]
     pub fn list_objects(
         &self,
         prefix: &str,
-    ) -> Result<impl IntoIterator<Item = String>, MauveError> {
+    ) -> Result<impl Iterator<Item = String> + Send, MauveError> {
         Ok(self.data.scan_prefix(prefix)
             .filter_map(|result| {
                 let k = match result {
@@ -60,25 +231,93 @@ impl Collection {
             }))
     }
 
+    /// List up to `limit` object names starting with `prefix`, in key order, after the name
+    /// `after` if given. Only the page is read, so this is cheap however large the
+    /// collection is.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, prefix = %prefix)))]
+    pub fn list_objects_paginated(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<ObjectsPage, MauveError> {
+        let mut page = ObjectsPage::default();
+        for key in self.list_objects_after(prefix, after).take(limit) {
+            page.objects.push(key?);
+        }
+        if page.objects.len() == limit {
+            page.cursor = page.objects.last().cloned();
+        }
+        Ok(page)
+    }
+
+    /// Walk the object keys matching `prefix` in order, seeking past `after` rather than
+    /// reading the keys before it
+    pub fn list_objects_after<'a>(
+        &self,
+        prefix: &'a str,
+        after: Option<&str>,
+    ) -> impl Iterator<Item = Result<String, MauveError>> + Send + 'a {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.as_bytes().to_vec()),
+            _ => Bound::Included(prefix.as_bytes().to_vec()),
+        };
+        self.data
+            .range::<Vec<u8>, _>((start, Bound::Unbounded))
+            .keys()
+            .take_while(move |key| {
+                key.as_ref()
+                    .map_or(true, |key| key.starts_with(prefix.as_bytes()))
+            })
+            .map(|key| Ok(String::from_utf8(key?.to_vec())?))
+    }
+
+    /// Walk every object in key order along with its metadata, which is defaulted for
+    /// objects stored without any. Each object is read as the iterator reaches it.
+    pub fn iter_with_metadata(
+        &self,
+    ) -> impl Iterator<Item = Result<(String, Vec<u8>, Metadata), MauveError>> + Send {
+        let meta = self.meta.clone();
+        self.data.iter().map(move |entry| {
+            let (key, object) = entry?;
+            let meta = match meta.get(&key)? {
+                Some(bytes) => Metadata::from_object(bytes.to_vec())?,
+                None => Metadata::default(),
+            };
+            Ok((String::from_utf8(key.to_vec())?, object.to_vec(), meta))
+        })
+    }
+
     /// Check if an object exists in the collection.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn head_object(&self, ident: &str) -> Result<bool, MauveError> {
+        if self.read_through(ident)?.is_some() {
+            return Ok(true);
+        }
+        if !self.bloom_may_contain(ident) {
+            return Ok(false);
+        }
         Ok(self.data.contains_key(ident)?)
     }
 
     /// Get a `T: ToFromMauve` from the collection
-    pub fn get_object_t<T: ToFromMauve>(&self, ident: &str) -> Result<T, MauveError>
-    where
-        T: Serialize + for<'de> Deserialize<'de>,
-    {
+    pub fn get_object_t<T: ToFromMauve>(&self, ident: &str) -> Result<T, MauveError> {
         let bytes = self.get_object(ident)?;
-        Ok(T::from_object(bytes)?)
+        T::from_object(bytes)
     }
 
     /// Get an object as bytes by its name.
     ///
     /// **Note:** `get_object_t` should be used in almost all cases.
     ///
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn get_object(&self, ident: &str) -> Result<Vec<u8>, MauveError> {
+        if let Some(object) = self.read_through(ident)? {
+            return Ok(object);
+        }
+        if !self.bloom_may_contain(ident) {
+            return Err(MauveError::CollectionError(ObjectNotFound));
+        }
         match self.data.get(ident) {
             Ok(Some(bytes)) => Ok(bytes.to_vec()),
             Ok(None) => Err(MauveError::CollectionError(ObjectNotFound)),
@@ -89,7 +328,36 @@ impl Collection {
         }
     }
 
+    /// Get bytes `start..=end` of an object, copying only that slice out of the stored value.
+    /// The range is clipped to the object, so it may come back short or empty.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn get_object_range(
+        &self,
+        ident: &str,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<u8>, MauveError> {
+        let slice = |object: &[u8]| {
+            let len = object.len() as u64;
+            match start < len && start <= end {
+                true => object[start as usize..=end.min(len - 1) as usize].to_vec(),
+                false => vec![],
+            }
+        };
+        if let Some(object) = self.read_through(ident)? {
+            return Ok(slice(&object));
+        }
+        if !self.bloom_may_contain(ident) {
+            return Err(MauveError::CollectionError(ObjectNotFound));
+        }
+        match self.data.get(ident)? {
+            Some(bytes) => Ok(slice(&bytes)),
+            None => Err(MauveError::CollectionError(ObjectNotFound)),
+        }
+    }
+
     /// Get all metadata for a given object in this collection.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn get_object_metadata(&self, ident: &str) -> Result<Metadata, MauveError> {
         match self.meta.get(ident) {
             Ok(Some(bytes)) => {
@@ -104,35 +372,159 @@ impl Collection {
         }
     }
 
+    /// Get only the labels of an object. The rest of its metadata is skipped over rather
+    /// than decoded.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn get_object_labels(&self, ident: &str) -> Result<HashSet<Label>, MauveError> {
+        #[derive(Deserialize)]
+        struct Labels {
+            labels: HashSet<Label>,
+        }
+
+        match self.meta.get(ident)? {
+            Some(bytes) => Ok(ciborium::from_reader::<Labels, _>(bytes.as_ref())?.labels),
+            None => Err(MauveError::CollectionError(ObjectNotFound)),
+        }
+    }
+
+    /// Get the stored size of an object in bytes without reading the object itself.
+    ///
+    /// This reads only the metadata tree.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn object_size(&self, ident: &str) -> Result<u64, MauveError> {
+        Ok(self.get_object_metadata(ident)?.size)
+    }
+
     /// Put an object into the collection with the given identity.
     ///
     /// **Note:** `put_object_t` should be used in almost all cases.
     ///
     /// If an object already exists with that identity and the replace flag is true, the old object will
-    /// be replaced with the new. The old object will *not* be returned.
+    /// be replaced with the new. The old object will *not* be returned, see `put_object_returning`.
     ///
     /// If an object already exists with that identity and the replace flag is false, an error is returned.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn put_object(
         &self,
         ident: &str,
         object: Vec<u8>,
         replace: bool,
     ) -> Result<ObjectRef, MauveError> {
-        let old = self.data.get(ident)?;
-        match old {
-            Some(_) => {
-                log::debug!(ident = ident, replace = replace; "object already exists with ident");
-                if !replace {
+        Ok(self.put_object_returning(ident, object, replace)?.0)
+    }
+
+    /// Put an object into the collection like `put_object`, also returning the object it
+    /// replaced, if any.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn put_object_returning(
+        &self,
+        ident: &str,
+        object: Vec<u8>,
+        replace: bool,
+    ) -> Result<(ObjectRef, Option<Vec<u8>>), MauveError> {
+        self.check_key(ident)?;
+        // A false positive in the filter is harmless, so record the key before it lands
+        self.bloom_insert(ident)?;
+        let len = object.len();
+        let old = match replace {
+            true => self.data.insert(ident, object)?,
+            // Only create the object if nothing is there, checked and written in one step
+            false => match self
+                .data
+                .compare_and_swap(ident, None as Option<&[u8]>, Some(object))?
+            {
+                Ok(()) => None,
+                Err(_) => {
+                    log::debug!(ident = ident; "object already exists with ident");
                     return Err(MauveError::CollectionError(
                         crate::errors::CollectionError::PutObjectExistsNoReplace,
                     ));
                 }
+            },
+        };
+        // A local write replaces any cached upstream copy for good
+        if let Some(upstream) = &self.upstream {
+            upstream.forget(ident)?;
+        }
+        self.count_write(old.as_ref().map(|old| old.len()), Some(len))?;
+        self.unbury(ident)?;
+        self.record_change(ident, false)?;
+        Ok((self.object_ref(ident), old.map(|old| old.to_vec())))
+    }
+
+    /// Store many objects and their metadata at once, replacing existing objects if `replace`
+    /// is set. Each object gets its own result, so one that can't be written, like an
+    /// existing object without `replace`, is reported while the rest are still stored.
+    ///
+    /// The metadata of every accepted object is applied in one `sled::Batch`, then the
+    /// objects in one transaction.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn put_objects_batch(
+        &self,
+        items: Vec<BatchObject>,
+        replace: bool,
+    ) -> Result<Vec<Result<ObjectRef, MauveError>>, MauveError> {
+        let now = now_millis()?;
+        let mut data = vec![];
+        let mut metas = sled::Batch::default();
+        let mut seen = HashSet::new();
+        let mut replaced_meta = vec![];
+        let mut results = Vec::with_capacity(items.len());
+        for BatchObject {
+            name,
+            object,
+            mut meta,
+        } in items
+        {
+            if let Err(e) = self.check_key(&name) {
+                results.push(Err(e));
+                continue;
             }
-            None => (),
+            if !replace && (seen.contains(&name) || self.data.contains_key(&name)?) {
+                results.push(Err(MauveError::CollectionError(
+                    crate::errors::CollectionError::PutObjectExistsNoReplace,
+                )));
+                continue;
+            }
+            let old = self.meta.get(&name)?;
+            stamp(&mut meta, old.as_deref(), now);
+            metas.insert(name.as_bytes(), meta.to_object()?);
+            if let Some(old) = old {
+                replaced_meta.push((name.clone(), old, meta));
+            }
+            data.push((name.clone(), object));
+            results.push(Ok(self.object_ref(&name)));
+            seen.insert(name);
         }
 
-        self.data.insert(ident, object)?;
-        Ok(ObjectRef::new(&self.name, ident))
+        for name in &seen {
+            // A local write replaces any cached upstream copy for good
+            if let Some(upstream) = &self.upstream {
+                upstream.forget(name)?;
+            }
+            self.bloom_insert(name)?;
+        }
+        // Metadata goes first so the indexer sees the labels when the objects land
+        self.meta.apply_batch(metas)?;
+        for (name, old, meta) in &replaced_meta {
+            self.unindex_dropped(name, old, meta)?;
+        }
+        let replaced = self.data.transaction(|tree| {
+            let mut replaced = Vec::with_capacity(data.len());
+            for (name, object) in &data {
+                let old = tree.insert(name.as_bytes(), object.as_slice())?;
+                replaced.push((old.map(|old| old.len()), object.len()));
+            }
+            Ok::<_, ConflictableTransactionError<MauveError>>(replaced)
+        })?;
+        for (old, len) in replaced {
+            self.count_write(old, Some(len))?;
+        }
+        for name in &seen {
+            self.unbury(name)?;
+            self.record_change(name, false)?;
+        }
+        Ok(results)
     }
 
     /// Put a `T: ToFromMauve` into the collection with the given identity.
@@ -149,15 +541,24 @@ impl Collection {
     ) -> Result<ObjectRef, MauveError> {
         let bytes = object.to_object()?;
         self.put_object(ident, bytes, replace)?;
-        Ok(ObjectRef::new(&self.name, ident))
+        Ok(self.object_ref(ident))
     }
 
-    /// Insert metadata about an object, replacing the existing.
-    pub fn put_object_metadata(&self, ident: &str, meta: Metadata) -> Result<String, MauveError> {
+    /// Insert metadata about an object, replacing the existing. The existing `created_at` is
+    /// kept, and `updated_at` is set to now.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn put_object_metadata(
+        &self,
+        ident: &str,
+        mut meta: Metadata,
+    ) -> Result<String, MauveError> {
+        self.check_key(ident)?;
+        stamp(&mut meta, self.meta.get(ident)?.as_deref(), now_millis()?);
         let meta_bytes = meta.to_object()?;
         match self.meta.insert(ident, meta_bytes) {
-            Ok(Some(_old)) => {
-                log::debug!(ident = ident; "Replaced existing object metadata with {meta:?}")
+            Ok(Some(old)) => {
+                log::debug!(ident = ident; "Replaced existing object metadata with {meta:?}");
+                self.unindex_dropped(ident, &old, &meta)?;
             }
             Ok(None) => (),
             Err(e) => {
@@ -168,8 +569,81 @@ impl Collection {
         Ok(ident.to_string())
     }
 
-    /// Delete an object by its name. This returns the object if one existed.
-    /// Deleting an object that does not exist is a no-op.
+    /// Take the labels of the replaced metadata `old` that `new` doesn't have out of the label
+    /// indexes. The indexer only adds the labels an object is written with.
+    ///
+    /// Metadata that can't be decoded has no labels to drop, and replacing it is how it gets
+    /// repaired, so it is skipped with a warning.
+    fn unindex_dropped(&self, ident: &str, old: &[u8], new: &Metadata) -> Result<(), MauveError> {
+        let mut old = match Metadata::from_object(old.to_vec()) {
+            Ok(old) => old,
+            Err(e) => {
+                log::warn!(collection = self.name, ident = ident; "replaced undecodable metadata {e}");
+                return Ok(());
+            }
+        };
+        old.labels.retain(|label| !new.labels.contains(label));
+        if !old.labels.is_empty() {
+            indexer::unindex(self, ident, old)?;
+        }
+        Ok(())
+    }
+
+    /// Atomically replace an object, but only if it is still `expected`. An `expected` of
+    /// `None` means the object must not exist yet. Fails with `PreconditionFailed` if the
+    /// stored object has changed.
+    ///
+    /// `meta`, if given, is written in the same transaction as the object, so a failed swap
+    /// leaves the metadata alone too.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn compare_and_swap_object(
+        &self,
+        ident: &str,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+        meta: Option<Metadata>,
+    ) -> Result<ObjectRef, MauveError> {
+        self.check_key(ident)?;
+        let now = now_millis()?;
+        // A false positive in the filter is harmless, so record the key before it lands
+        self.bloom_insert(ident)?;
+        let swapped = (&self.data, &self.meta).transaction(|(data, meta_tree)| {
+            if data.get(ident)?.as_deref() != expected.as_deref() {
+                return Err(ConflictableTransactionError::Abort(
+                    MauveError::PreconditionFailed(format!("{ident} has changed")),
+                ));
+            }
+            let mut replaced_meta = None;
+            if let Some(meta) = &meta {
+                let mut meta = meta.clone();
+                stamp(&mut meta, meta_tree.get(ident)?.as_deref(), now);
+                let meta = meta
+                    .to_object()
+                    .map_err(ConflictableTransactionError::Abort)?;
+                replaced_meta = meta_tree.insert(ident, meta)?;
+            }
+            Ok((data.insert(ident, new.as_slice())?, replaced_meta))
+        });
+        let (old, replaced_meta) = match swapped {
+            Ok(swapped) => swapped,
+            Err(TransactionError::Abort(e)) => return Err(e),
+            Err(TransactionError::Storage(e)) => return Err(e.into()),
+        };
+        self.count_write(old.map(|old| old.len()), Some(new.len()))?;
+        if let (Some(replaced), Some(meta)) = (replaced_meta, &meta) {
+            self.unindex_dropped(ident, &replaced, meta)?;
+        }
+
+        if let Some(upstream) = &self.upstream {
+            upstream.forget(ident)?;
+        }
+        self.unbury(ident)?;
+        self.record_change(ident, false)?;
+        Ok(self.object_ref(ident))
+    }
+
+    /// Delete an object by its name, along with its metadata and labels. This returns the
+    /// object if one existed. Deleting an object that does not exist is a no-op.
     pub fn delete_object_t<T: ToFromMauve>(&self, ident: &str) -> Result<Option<T>, MauveError> {
         match self.delete_object(ident)? {
             Some(bytes) => Ok(Some(T::from_object(bytes)?)),
@@ -177,19 +651,125 @@ impl Collection {
         }
     }
 
-    /// Delete an object by its name. This returns the object if one existed.
-    /// Deleting an object that does not exist is a no-op.
+    /// Delete an object by its name, along with its metadata and labels. This returns the
+    /// object if one existed. Deleting an object that does not exist is a no-op.
     ///
     /// **Note:** `delete_object_t` should be used in almost all cases.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn delete_object(&self, ident: &str) -> Result<Option<Vec<u8>>, MauveError> {
+        if let Some(upstream) = &self.upstream {
+            upstream.deleted(ident)?;
+        }
         let old = self.data.remove(ident)?;
         match old {
-            Some(old) => Ok(Some(old.to_vec())),
+            Some(old) => {
+                self.count_write(Some(old.len()), None)?;
+                self.drop_metadata(ident)?;
+                self.bury(ident)?;
+                self.record_change(ident, true)?;
+                Ok(Some(old.to_vec()))
+            }
             None => Ok(None),
         }
     }
 
+    /// Atomically delete an object and its metadata, but only if it is still `expected`. An
+    /// `expected` of `None` means the object must not exist. Fails with `PreconditionFailed`
+    /// if the stored object has changed, and returns the object if one was deleted.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
+    pub fn compare_and_delete_object(
+        &self,
+        ident: &str,
+        expected: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, MauveError> {
+        let removed = (&self.data, &self.meta).transaction(|(data, meta)| {
+            if data.get(ident)?.as_deref() != expected.as_deref() {
+                return Err(ConflictableTransactionError::Abort(
+                    MauveError::PreconditionFailed(format!("{ident} has changed")),
+                ));
+            }
+            if expected.is_none() {
+                return Ok(None);
+            }
+            data.remove(ident)?;
+            Ok(Some(meta.remove(ident)?))
+        })?;
+        let Some(old_meta) = removed else {
+            return Ok(None);
+        };
+        self.count_write(expected.as_ref().map(Vec::len), None)?;
+
+        if let Some(upstream) = &self.upstream {
+            upstream.deleted(ident)?;
+        }
+        self.bury(ident)?;
+        self.record_change(ident, true)?;
+        if let Some(old_meta) = old_meta {
+            indexer::unindex(self, ident, Metadata::from_object(old_meta.to_vec())?)?;
+        }
+        Ok(expected)
+    }
+
+    /// Delete many objects at once, returning whether each one existed.
+    ///
+    /// The objects, their metadata, their tombstones and their entries in the changes feed
+    /// are all written in one transaction, so either every object is deleted or none are.
+    /// Names that don't exist are skipped. As with `delete_object`, the labels of each
+    /// deleted object are taken out of the index afterwards.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, count = idents.len())))]
+    pub fn delete_objects(&self, idents: &[String]) -> Result<Vec<bool>, MauveError> {
+        for ident in idents {
+            self.check_key(ident)?;
+        }
+        let now = now_millis()?;
+        let mut trees = vec![&self.data, &self.meta, &self.changes, &self.changed_at];
+        trees.extend(&self.tombstones);
+        let removed = trees[..].transaction(|trees| {
+            let (data, meta, changes, changed_at) = (&trees[0], &trees[1], &trees[2], &trees[3]);
+            let mut removed = Vec::with_capacity(idents.len());
+            for ident in idents {
+                let Some(object) = data.remove(ident.as_bytes())? else {
+                    removed.push(None);
+                    continue;
+                };
+                let old = match meta.remove(ident.as_bytes())? {
+                    Some(old) => Metadata::from_object(old.to_vec())
+                        .map_err(ConflictableTransactionError::Abort)?,
+                    None => Metadata::default(),
+                };
+                if let Some(tombstones) = trees.get(4) {
+                    let tombstone = Tombstone {
+                        name: ident.clone(),
+                        deleted_at: now,
+                    }
+                    .to_object()
+                    .map_err(ConflictableTransactionError::Abort)?;
+                    tombstones.insert(ident.as_bytes(), tombstone)?;
+                }
+                record_change_tx(changes, changed_at, ident, true, now)?;
+                removed.push(Some((old, object.len())));
+            }
+            Ok(removed)
+        })?;
+
+        if let Some(upstream) = &self.upstream {
+            for ident in idents {
+                upstream.deleted(ident)?;
+            }
+        }
+        let mut deleted = Vec::with_capacity(removed.len());
+        for (ident, removed) in idents.iter().zip(removed) {
+            deleted.push(removed.is_some());
+            if let Some((meta, len)) = removed {
+                self.count_write(Some(len), None)?;
+                indexer::unindex(self, ident, meta)?;
+            }
+        }
+        Ok(deleted)
+    }
+
     /// Delete metadata about an object.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name, ident = %ident)))]
     pub fn delete_metadata(&self, ident: &str) -> Result<Option<Metadata>, MauveError> {
         let old = self.meta.remove(ident)?;
         match old {
@@ -201,7 +781,122 @@ impl Collection {
         }
     }
 
+    /// Delete a deleted object's metadata and take it out of the label indexes
+    fn drop_metadata(&self, ident: &str) -> Result<(), MauveError> {
+        if let Some(meta) = self.delete_metadata(ident)? {
+            indexer::unindex(self, ident, meta)?;
+        }
+        Ok(())
+    }
+
+    /// Count the objects and bytes in the collection once, for a collection stored before it
+    /// kept counters. Afterwards every write keeps them up to date.
+    pub(crate) fn init_counts(&self) -> Result<(), MauveError> {
+        if self.settings.contains_key(COUNTS)? {
+            return Ok(());
+        }
+        self.recount()
+    }
+
+    /// Count the objects and bytes in the collection again, replacing its counters. This
+    /// reads every object, so it is only for bulk writes that read them all anyway.
+    pub(crate) fn recount(&self) -> Result<(), MauveError> {
+        let (mut objects, mut bytes) = (0u64, 0u64);
+        for object in self.data.iter().values() {
+            objects += 1;
+            bytes += object?.len() as u64;
+        }
+        self.settings.insert(
+            COUNTS,
+            [objects.to_be_bytes(), bytes.to_be_bytes()].concat(),
+        )?;
+        Ok(())
+    }
+
+    /// Update the counters for an object of `old` bytes replaced by one of `new` bytes,
+    /// either of which is `None` when there is no object
+    pub(crate) fn count_write(
+        &self,
+        old: Option<usize>,
+        new: Option<usize>,
+    ) -> Result<(), MauveError> {
+        let objects = i64::from(new.is_some()) - i64::from(old.is_some());
+        let bytes = new.unwrap_or_default() as i64 - old.unwrap_or_default() as i64;
+        // Counters that haven't been initialized yet are left for `init_counts`
+        self.settings.update_and_fetch(COUNTS, |counts| {
+            let (count, total) = decode_counts(counts?);
+            Some(
+                [
+                    count.saturating_add_signed(objects).to_be_bytes(),
+                    total.saturating_add_signed(bytes).to_be_bytes(),
+                ]
+                .concat(),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// The number of objects in the collection and their total size in bytes, read from
+    /// counters kept by every write rather than by walking the collection
+    pub fn counts(&self) -> Result<(u64, u64), MauveError> {
+        match self.settings.get(COUNTS)? {
+            Some(counts) => Ok(decode_counts(&counts)),
+            None => {
+                self.init_counts()?;
+                self.counts()
+            }
+        }
+    }
+
+    /// The largest object in MB that can be written to the collection: the limit set with
+    /// `set_max_object_size_mb` if there is one, and `MauveConfig::object_limit_mb` otherwise.
+    /// A stored limit that can't be decoded is ignored in favour of the configured one.
+    pub fn max_object_size_mb(&self) -> Result<u64, MauveError> {
+        if let Some(limit) = self.settings.get(MAX_OBJECT_SIZE_MB)? {
+            match limit.as_ref().try_into() {
+                Ok(limit) => return Ok(u64::from_be_bytes(limit)),
+                Err(_) => {
+                    log::warn!(collection = self.name; "ignoring undecodable object size limit {limit:?}");
+                }
+            }
+        }
+        Ok(self.config.object_limit_mb(&self.name))
+    }
+
+    /// Set the collection's own object size limit in MB, or go back to the configured limit
+    /// with `None`
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn set_max_object_size_mb(&self, limit: Option<u64>) -> Result<(), MauveError> {
+        match limit {
+            Some(limit) => self
+                .settings
+                .insert(MAX_OBJECT_SIZE_MB, &limit.to_be_bytes())?,
+            None => self.settings.remove(MAX_OBJECT_SIZE_MB)?,
+        };
+        Ok(())
+    }
+
+    /// Count the objects in the collection. Sled counts a tree by walking its keys, so this
+    /// is linear in the number of objects, where `counts` and `stats` read a counter.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn count_objects(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Count the collection's objects, bytes and labels. The objects and bytes come from the
+    /// counters, while the labels are counted by walking the label index.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn stats(&self) -> Result<CollectionStats, MauveError> {
+        let (objects, bytes) = self.counts()?;
+        Ok(CollectionStats {
+            objects: objects as usize,
+            bytes,
+            labels: self.index_fwd.len(),
+        })
+    }
+
     /// List all labels known to this collection.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
     pub fn list_labels(&self) -> Result<impl IntoIterator<Item = Label>, MauveError> {
         let mut labels = vec![];
         for label in self.index_fwd.into_iter() {
@@ -211,4 +906,280 @@ impl Collection {
         }
         Ok(labels)
     }
+
+    /// List the index events that failed for objects in this collection.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn list_dead_letters(&self) -> Result<Vec<DeadLetter>, MauveError> {
+        let mut letters = vec![];
+        for letter in self.dlq.iter().values() {
+            letters.push(DeadLetter::from_object(letter?.to_vec())?);
+        }
+        Ok(letters)
+    }
+}
+
+/// Split the `COUNTS` setting into the object count and total bytes
+fn decode_counts(counts: &[u8]) -> (u64, u64) {
+    let half = |range: std::ops::Range<usize>| {
+        u64::from_be_bytes(
+            counts
+                .get(range)
+                .and_then(|b| b.try_into().ok())
+                .unwrap_or_default(),
+        )
+    };
+    (half(0..8), half(8..16))
+}
+
+/// Set the times on metadata about to be written over `old`. A replace keeps the original
+/// creation time, and so does a copy of an object that was created elsewhere.
+fn stamp(meta: &mut Metadata, old: Option<&[u8]>, now: u64) {
+    let created_at = old
+        .and_then(|old| Metadata::from_object(old.to_vec()).ok())
+        .map(|old| old.created_at)
+        .filter(|at| *at != 0);
+    meta.created_at = created_at
+        .or((meta.created_at != 0).then_some(meta.created_at))
+        .unwrap_or(now);
+    meta.updated_at = now;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BatchObject;
+    use crate::{
+        api::tests::test_config, backend::Backend, config::TombstoneConfig, errors::MauveError,
+        labels::Label, meta::Metadata,
+    };
+
+    #[rocket::async_test]
+    async fn test_iter_with_metadata() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("walked")?;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("b", meta.clone())?;
+        collection.put_object("b", b"two".to_vec(), false)?;
+        collection.put_object("a", b"one".to_vec(), false)?;
+
+        let objects = collection
+            .iter_with_metadata()
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(objects.len(), 2);
+        assert_eq!(
+            (objects[0].0.as_str(), objects[0].1.as_slice()),
+            ("a", &b"one"[..])
+        );
+        assert!(objects[0].2.labels.is_empty());
+        assert_eq!(
+            (objects[1].0.as_str(), objects[1].1.as_slice()),
+            ("b", &b"two"[..])
+        );
+        assert_eq!(objects[1].2.labels, meta.labels);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_compare_and_swap() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("swapped")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let labeled = |color| {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", color));
+            Some(meta)
+        };
+
+        collection.compare_and_swap_object("a", None, b"one".to_vec(), labeled("red"))?;
+        assert!(collection
+            .compare_and_swap_object("a", None, b"two".to_vec(), labeled("blue"))
+            .is_err());
+        assert!(collection
+            .compare_and_swap_object("a", Some(b"two".to_vec()), b"three".to_vec(), None)
+            .is_err());
+        // A failed swap leaves the object and its metadata as they were
+        assert_eq!(collection.get_object("a")?, b"one");
+        let meta = collection.get_object_metadata("a")?;
+        assert!(meta.labels.contains(&Label::new("color", "red")));
+        for _ in 0..50 {
+            if collection.index_fwd.contains_key("color=red")? {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(collection.index_fwd.contains_key("color=red")?);
+
+        collection.compare_and_swap_object(
+            "a",
+            Some(b"one".to_vec()),
+            b"two".to_vec(),
+            labeled("blue"),
+        )?;
+        assert_eq!(collection.get_object("a")?, b"two");
+        let swapped = collection.get_object_metadata("a")?;
+        assert!(swapped.labels.contains(&Label::new("color", "blue")));
+        assert_eq!(swapped.created_at, meta.created_at);
+        // The labels the swap dropped are no longer indexed
+        assert!(!collection.index_fwd.contains_key("color=red")?);
+
+        // Deletes are compared the same way
+        assert!(matches!(
+            collection.compare_and_delete_object("a", Some(b"one".to_vec())),
+            Err(MauveError::PreconditionFailed(_))
+        ));
+        assert!(collection.compare_and_delete_object("a", None).is_err());
+        assert_eq!(collection.get_object("a")?, b"two");
+        assert!(collection.get_object_metadata("a").is_ok());
+        let deleted = collection.compare_and_delete_object("a", Some(b"two".to_vec()))?;
+        assert_eq!(deleted.as_deref(), Some(b"two".as_slice()));
+        assert!(!collection.head_object("a")?);
+        assert!(collection.get_object_metadata("a").is_err());
+        assert_eq!(collection.compare_and_delete_object("a", None)?, None);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_counts() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let counted = backend.get_collection("counted")?;
+        let batch = ["a", "b", "c"].map(|name| BatchObject {
+            name: name.to_string(),
+            object: name.repeat(2).into_bytes(),
+            meta: Metadata::default(),
+        });
+        counted.put_objects_batch(batch.to_vec(), false)?;
+        assert_eq!(counted.counts()?, (3, 6));
+        counted.compare_and_swap_object("a", Some(b"aa".to_vec()), b"aaaa".to_vec(), None)?;
+        counted.compare_and_delete_object("b", Some(b"bb".to_vec()))?;
+        assert_eq!(counted.counts()?, (2, 6));
+
+        backend.move_object("counted", "c", "other", "c", false)?;
+        backend.copy_object("counted", "a", "other", "a", false)?;
+        assert_eq!(counted.counts()?, (1, 4));
+        assert_eq!(backend.get_collection("other")?.counts()?, (2, 6));
+        backend.copy_collection("other", "copied", false)?;
+        assert_eq!(backend.get_collection("copied")?.counts()?, (2, 6));
+
+        // A collection stored before there were counters is counted when it is opened
+        counted.settings.remove(super::COUNTS)?;
+        counted.put_object("d", b"d".to_vec(), false)?;
+        assert_eq!(backend.get_collection("counted")?.counts()?, (2, 5));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_max_object_size_mb() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config
+            .mauve
+            .collection_max_size_mb
+            .insert("limited".to_string(), 7);
+        let backend = Backend::open(config.clone())?;
+        let limited = backend.get_collection("limited")?;
+        let other = backend.get_collection("other")?;
+        let configured = config.mauve.object_limit_mb("limited");
+        assert_eq!(configured, 7);
+        assert_eq!(limited.max_object_size_mb()?, configured);
+        assert_eq!(other.max_object_size_mb()?, config.mauve.object_max_size_mb);
+
+        // The collection's own limit wins over the config, and clearing it goes back to the
+        // collection's configured limit
+        limited.set_max_object_size_mb(Some(configured + 1))?;
+        assert_eq!(limited.max_object_size_mb()?, configured + 1);
+        limited.set_max_object_size_mb(None)?;
+        assert_eq!(limited.max_object_size_mb()?, configured);
+        limited.set_max_object_size_mb(Some(configured + 1))?;
+
+        // A corrupt limit falls back to the configured one rather than zero
+        limited.settings.insert(super::MAX_OBJECT_SIZE_MB, b"bad")?;
+        assert_eq!(limited.max_object_size_mb()?, configured);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_delete_objects_atomic() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.tombstones = Some(TombstoneConfig {
+            collections: vec!["batched".to_string()],
+            retention_secs: 60,
+            interval_secs: 3600,
+        });
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("batched")?;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        for name in ["a", "b"] {
+            collection.put_object_metadata(name, meta.clone())?;
+            collection.put_object(name, name.as_bytes().to_vec(), false)?;
+        }
+        let feed = collection.list_changes(0, None, 10)?.changes;
+
+        // Metadata that can't be decoded fails the whole batch, leaving every tree as it was
+        collection.meta.insert("b", b"\xff".as_slice())?;
+        let names = ["a".to_string(), "b".to_string()];
+        assert!(collection.delete_objects(&names).is_err());
+        assert_eq!(collection.get_object("a")?, b"a");
+        assert_eq!(collection.get_object("b")?, b"b");
+        assert_eq!(collection.get_object_metadata("a")?.labels, meta.labels);
+        assert!(collection.meta.contains_key("b")?);
+        assert!(collection.list_tombstones()?.is_empty());
+        assert_eq!(collection.list_changes(0, None, 10)?.changes, feed);
+
+        collection.put_object_metadata("b", meta.clone())?;
+        let names = ["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(collection.delete_objects(&names)?, [true, true, false]);
+        assert!(collection.data.is_empty() && collection.meta.is_empty());
+        assert_eq!(collection.list_tombstones()?.len(), 2);
+        let feed = collection.list_changes(0, None, 10)?.changes;
+        assert!(feed.len() == 2 && feed.iter().all(|change| change.deleted));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_put_object_returning() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("returned")?;
+        let (_, old) = collection.put_object_returning("a", b"one".to_vec(), false)?;
+        assert!(old.is_none());
+        let (_, old) = collection.put_object_returning("a", b"two".to_vec(), true)?;
+        assert_eq!(old.as_deref(), Some(&b"one"[..]));
+        assert_eq!(collection.get_object("a")?, b"two");
+
+        // Only one of many racing creates of the same object wins
+        let created = std::thread::scope(|scope| {
+            let racers: Vec<_> = (0..8u8)
+                .map(|n| {
+                    let collection = &collection;
+                    scope.spawn(move || collection.put_object_returning("b", vec![n], false))
+                })
+                .collect();
+            racers
+                .into_iter()
+                .filter_map(|racer| racer.join().ok())
+                .filter(Result::is_ok)
+                .count()
+        });
+        assert_eq!(created, 1);
+        // The losers aren't counted
+        let stats = collection.stats()?;
+        assert_eq!((stats.objects, stats.bytes), (2, 4));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
 }
@@ -1,7 +1,7 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
 
 use figment::{
-    providers::{Format, Serialized, Yaml},
+    providers::{Env, Format, Serialized, Yaml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
@@ -15,44 +15,267 @@ pub struct AppConfig {
 }
 
 impl AppConfig {
+    /// Load the config from a YAML file over the defaults. Environment variables prefixed
+    /// `MAUVE_` override both, with `__` between nested keys, e.g. `MAUVE_SLED__PATH`.
     pub fn load(file: PathBuf) -> Result<Self, MauveError> {
         Ok(Figment::from(Serialized::defaults(Self::default()))
             .merge(Yaml::file(file))
+            .merge(Env::prefixed("MAUVE_").split("__"))
             .extract()?)
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MauveConfig {
+    /// The largest object in MB, unless the collection has a limit of its own
     pub object_max_size_mb: u64,
+    /// Per-collection overrides of `object_max_size_mb`, keyed by collection
+    ///
+    /// A limit set on a collection through `PUT /v1/collections/<name>/_limits` wins over
+    /// both, and clearing it goes back to the collection's entry here, then to
+    /// `object_max_size_mb`. See `Collection::max_object_size_mb`.
+    pub collection_max_size_mb: HashMap<String, u64>,
+    pub max_key_len: usize,
+    pub max_labels_per_object: usize,
+    pub max_metadata_bytes: usize,
+    /// Guess the `Content-Type` of objects uploaded without one from their first bytes
+    pub sniff_content_type: bool,
+    /// Compress object GETs with gzip or deflate for clients that accept it. Objects stored
+    /// with a `Content-Encoding` are sent as they are.
+    pub compress_responses: bool,
+    /// Smallest object in bytes that `compress_responses` compresses
+    pub compress_min_bytes: usize,
+    pub compress_index: bool,
+    pub bloom_collections: Vec<String>,
+    /// Label names indexed by numeric value, keyed by collection
+    pub numeric_labels: HashMap<String, Vec<String>>,
+    /// Rebuild every collection's label index at startup if the database was recovered
+    pub reindex_on_recovery: bool,
+    /// Add any labels missing from each collection's index at startup, such as those of
+    /// objects written before the indexer caught up
+    pub reindex_on_start: bool,
+    /// Keep the case of labels and of object names in the label index. By default labels
+    /// are lowercased as they are written, and so are the object names the index refers to.
+    /// Changing this rebuilds every label index at the next start; labels already stored
+    /// keep the case they were written with.
+    pub case_sensitive: bool,
+    /// Most objects one search returns, however many match
+    pub max_search_results: usize,
+    /// Label lookups of one search that run at once
+    pub query_concurrency: usize,
+    /// Seconds between indexer heartbeat logs, or `0` for none
+    pub indexer_heartbeat_secs: u64,
+    pub otlp_endpoint: Option<String>,
+    pub mirror: Option<MirrorConfig>,
+    pub upstream: Option<UpstreamConfig>,
+    pub tombstones: Option<TombstoneConfig>,
+    pub s3_backup: Option<S3BackupConfig>,
+    pub s3_enabled: bool,
+    pub cors: Option<CorsConfig>,
 }
 
 impl Default for MauveConfig {
     fn default() -> Self {
         Self {
             object_max_size_mb: 30,
+            collection_max_size_mb: HashMap::new(),
+            max_key_len: 1024,
+            max_labels_per_object: 100,
+            max_metadata_bytes: 64 * 1024,
+            sniff_content_type: false,
+            compress_responses: false,
+            compress_min_bytes: 1024,
+            compress_index: false,
+            bloom_collections: vec![],
+            numeric_labels: HashMap::new(),
+            reindex_on_recovery: false,
+            reindex_on_start: true,
+            case_sensitive: false,
+            max_search_results: 10_000,
+            query_concurrency: 16,
+            indexer_heartbeat_secs: 120,
+            otlp_endpoint: None,
+            mirror: None,
+            upstream: None,
+            tombstones: None,
+            s3_backup: None,
+            s3_enabled: false,
+            cors: None,
         }
     }
 }
 
+impl MauveConfig {
+    /// The largest object in MB that can be written to `collection`, which is its entry in
+    /// `collection_max_size_mb` if it has one and `object_max_size_mb` otherwise
+    pub fn object_limit_mb(&self, collection: &str) -> u64 {
+        self.collection_max_size_mb
+            .get(collection)
+            .copied()
+            .unwrap_or(self.object_max_size_mb)
+    }
+}
+
+/// Secondary Mauve instance that writes are mirrored to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MirrorConfig {
+    /// Base URL of the remote, which must have `s3_enabled`
+    pub url: String,
+    /// Collections to mirror
+    pub collections: Vec<String>,
+}
+
+/// Mauve instance that missing objects are read through from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpstreamConfig {
+    /// Base URL of the upstream, which must have `s3_enabled`
+    pub url: String,
+    /// Collections to read through
+    pub collections: Vec<String>,
+    /// Seconds a fetched object is cached before it is refreshed from the upstream
+    pub cache_ttl: u64,
+    /// Seconds an upstream read may take in all before it fails over to the local copy
+    #[serde(default = "default_upstream_timeout")]
+    pub timeout_secs: u64,
+    /// Seconds to wait for a connection to the upstream
+    #[serde(default = "default_upstream_connect_timeout")]
+    pub connect_timeout_secs: u64,
+}
+
+fn default_upstream_timeout() -> u64 {
+    30
+}
+
+fn default_upstream_connect_timeout() -> u64 {
+    5
+}
+
+/// Collections that keep a tombstone for every deleted object
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TombstoneConfig {
+    /// Collections to keep tombstones for
+    pub collections: Vec<String>,
+    /// Seconds a tombstone is kept before the reaper prunes it
+    pub retention_secs: u64,
+    /// Seconds between reaper runs
+    #[serde(default = "default_reap_interval")]
+    pub interval_secs: u64,
+}
+
+pub(crate) fn default_reap_interval() -> u64 {
+    3600
+}
+
+/// S3 compatible bucket that backups are uploaded to
+///
+/// Credentials are read from the standard `AWS_*` environment variables.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct S3BackupConfig {
+    pub bucket: String,
+    /// Key prefix for backups, which are named `<prefix>mauve-<unix seconds>.backup`
+    #[serde(default)]
+    pub prefix: String,
+    pub region: Option<String>,
+    /// Endpoint for non-AWS stores such as MinIO
+    pub endpoint: Option<String>,
+    /// Take a backup every this many seconds
+    pub interval_secs: Option<u64>,
+}
+
+/// Cross-origin access to the API for browser clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://app.example.com`, or `*` for any
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers a browser may send. `x-mauve-meta-<name>` headers are always allowed.
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+    /// Seconds a browser may cache the answer to a preflight request
+    pub max_age_secs: Option<u64>,
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "DELETE"]
+        .map(str::to_string)
+        .to_vec()
+}
+
+fn default_cors_headers() -> Vec<String> {
+    [
+        "Content-Type",
+        "Content-Encoding",
+        "Content-Language",
+        "If-Match",
+        "If-None-Match",
+        "If-Range",
+        "Range",
+        "x-mauve-labels",
+        "x-mauve-label",
+        "x-mauve-offsets-inclusive",
+        "x-mauve-content-hash",
+        "x-mauve-expect-absent",
+    ]
+    .map(str::to_string)
+    .to_vec()
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SledConfig {
     pub cache_capacity: u64,
     pub flush_every_ms: Option<u64>,
     pub path: PathBuf,
-    pub mode: String,
+    pub mode: SledMode,
     pub use_compression: bool,
     pub compression_factor: i32,
     pub idgen_persist_interval: u64,
 }
 
+/// sled's storage mode, written `HighThroughput` or `LowSpace` in any case
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum SledMode {
+    #[default]
+    HighThroughput,
+    LowSpace,
+}
+
+impl FromStr for SledMode {
+    type Err = MauveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "highthroughput" => Ok(Self::HighThroughput),
+            "lowspace" => Ok(Self::LowSpace),
+            _ => Err(MauveError::ConfigError(format!(
+                "unknown sled mode {s}, expected HighThroughput or LowSpace"
+            ))),
+        }
+    }
+}
+
+impl TryFrom<String> for SledMode {
+    type Error = MauveError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<SledMode> for String {
+    fn from(value: SledMode) -> Self {
+        format!("{value:?}")
+    }
+}
+
 impl Default for SledConfig {
     fn default() -> Self {
         Self {
             cache_capacity: 1024 * 1024 * 1024,
             flush_every_ms: Some(500),
             path: PathBuf::from_str("data/").unwrap(),
-            mode: "HighThroughput".to_string(),
+            mode: SledMode::HighThroughput,
             use_compression: false,
             compression_factor: 5,
             idgen_persist_interval: 1_000_000,
@@ -60,19 +283,72 @@ impl Default for SledConfig {
     }
 }
 
-impl Into<sled::Config> for SledConfig {
-    fn into(self) -> sled::Config {
+impl From<SledConfig> for sled::Config {
+    fn from(value: SledConfig) -> Self {
         sled::Config::new()
-            .cache_capacity(self.cache_capacity)
-            .flush_every_ms(self.flush_every_ms)
-            .path(self.path)
-            .mode(match self.mode.as_str() {
-                "HighThroughput" => sled::Mode::HighThroughput,
-                "LowSpace" => sled::Mode::LowSpace,
-                _ => sled::Mode::HighThroughput,
+            .cache_capacity(value.cache_capacity)
+            .flush_every_ms(value.flush_every_ms)
+            .path(value.path)
+            .mode(match value.mode {
+                SledMode::HighThroughput => sled::Mode::HighThroughput,
+                SledMode::LowSpace => sled::Mode::LowSpace,
             })
-            .use_compression(self.use_compression)
-            .compression_factor(self.compression_factor)
-            .idgen_persist_interval(self.idgen_persist_interval)
+            .use_compression(value.use_compression)
+            .compression_factor(value.compression_factor)
+            .idgen_persist_interval(value.idgen_persist_interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Serialized, Yaml},
+        Figment,
+    };
+
+    use super::{AppConfig, SledMode};
+    use crate::errors::MauveError;
+
+    fn load(yaml: &str) -> Result<AppConfig, MauveError> {
+        Ok(Figment::from(Serialized::defaults(AppConfig::default()))
+            .merge(Yaml::string(yaml))
+            .extract()?)
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)] // `Jail` fixes the closure's error type
+    fn test_env_overrides() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file(
+                "mauve.yaml",
+                "sled:\n  path: /tmp/from-file\nmauve:\n  object_max_size_mb: 5\n  max_key_len: 64\n",
+            )?;
+            jail.set_env("MAUVE_MAUVE__OBJECT_MAX_SIZE_MB", "7");
+            jail.set_env("MAUVE_SLED__PATH", "/tmp/from-env");
+            let config = AppConfig::load("mauve.yaml".into())
+                .map_err(|e| figment::Error::from(e.to_string()))?;
+            assert_eq!(config.mauve.object_max_size_mb, 7);
+            assert_eq!(config.sled.path, std::path::PathBuf::from("/tmp/from-env"));
+            assert_eq!(config.mauve.max_key_len, 64);
+            assert_eq!(
+                config.mauve.max_labels_per_object,
+                AppConfig::default().mauve.max_labels_per_object
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_sled_mode() -> anyhow::Result<()> {
+        assert_eq!(
+            load("sled:\n  mode: lowspace")?.sled.mode,
+            SledMode::LowSpace
+        );
+        assert_eq!(
+            load("sled:\n  mode: HighThroughput")?.sled.mode,
+            SledMode::HighThroughput
+        );
+        assert!(load("sled:\n  mode: fast").is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,187 @@
+//! Content encoding
+//!
+//! Objects are stored exactly as uploaded, so a body sent with `Content-Encoding: gzip` or
+//! `zstd` is kept compressed and served back with the same header. The declared encoding is
+//! checked against the body on upload, and a read can ask for the decoded bytes instead for
+//! clients that can't decompress them.
+//!
+//! Concatenated gzip members and zstd frames decode as one body, so the segments of an
+//! object assembled from separately compressed parts can each be decoded on their own.
+//!
+//! With `compress_responses` set, objects stored without an encoding are compressed on the
+//! way out for clients whose `Accept-Encoding` takes gzip or deflate.
+
+use std::io::{Read, Write};
+
+use flate2::{
+    read::{MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+
+use crate::errors::MauveError;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// An encoding Mauve can check and decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Identity,
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`
+    Deflate,
+    Zstd,
+}
+
+impl Encoding {
+    /// Parse a `Content-Encoding` value, or `None` for encodings Mauve doesn't know
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(Self::Identity),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            "deflate" => Some(Self::Deflate),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Check that a body starts like its declared encoding. Empty bodies, deflate bodies, which
+/// have no magic number, and encodings Mauve doesn't know are accepted as-is.
+pub fn check_encoding(encoding: &str, body: &[u8]) -> Result<(), MauveError> {
+    let magic = match Encoding::parse(encoding) {
+        Some(Encoding::Gzip) => GZIP_MAGIC,
+        Some(Encoding::Zstd) => ZSTD_MAGIC,
+        Some(Encoding::Identity | Encoding::Deflate) | None => return Ok(()),
+    };
+    if !body.is_empty() && !body.starts_with(magic) {
+        return Err(MauveError::EncodingMismatch(encoding.trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Decode a body stored with `encoding`
+pub fn decode(encoding: &str, body: &[u8]) -> Result<Vec<u8>, MauveError> {
+    match Encoding::parse(encoding) {
+        Some(Encoding::Identity) => Ok(body.to_vec()),
+        Some(Encoding::Gzip) => {
+            let mut decoded = vec![];
+            MultiGzDecoder::new(body).read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        Some(Encoding::Deflate) => {
+            let mut decoded = vec![];
+            ZlibDecoder::new(body).read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        Some(Encoding::Zstd) => Ok(zstd::decode_all(body)?),
+        None => Err(MauveError::UnsupportedEncoding(encoding.trim().to_string())),
+    }
+}
+
+/// Compress a body with `encoding`
+pub fn encode(encoding: &str, body: &[u8]) -> Result<Vec<u8>, MauveError> {
+    match Encoding::parse(encoding) {
+        Some(Encoding::Identity) => Ok(body.to_vec()),
+        Some(Encoding::Gzip) => {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(body)?;
+            Ok(encoder.finish()?)
+        }
+        Some(Encoding::Deflate) => {
+            let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+            encoder.write_all(body)?;
+            Ok(encoder.finish()?)
+        }
+        Some(Encoding::Zstd) => Ok(zstd::encode_all(body, 0)?),
+        None => Err(MauveError::UnsupportedEncoding(encoding.trim().to_string())),
+    }
+}
+
+/// Pick the encoding to compress a response with from the request's `Accept-Encoding`:
+/// whichever of gzip and deflate it gives the higher quality, preferring gzip on a tie, or
+/// `None` if it refuses both
+pub fn negotiate(accept_encoding: &str) -> Option<&'static str> {
+    let gzip = quality(accept_encoding, "gzip");
+    let deflate = quality(accept_encoding, "deflate");
+    match (gzip, deflate) {
+        (gzip, deflate) if gzip > 0.0 && gzip >= deflate => Some("gzip"),
+        (_, deflate) if deflate > 0.0 => Some("deflate"),
+        _ => None,
+    }
+}
+
+/// The quality an `Accept-Encoding` header gives `encoding`, by name or through `*`, and
+/// `0` if it names neither
+fn quality(accept_encoding: &str, encoding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.trim().parse().ok())
+            .unwrap_or_default();
+        if Encoding::parse(name) == Encoding::parse(encoding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = q;
+        }
+    }
+    wildcard
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::{check_encoding, decode, encode, negotiate};
+
+    fn gzip(body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(body)?;
+        Ok(encoder.finish()?)
+    }
+
+    #[test]
+    fn test_encoding() -> anyhow::Result<()> {
+        let gz = gzip(b"hello")?;
+        let zst = zstd::encode_all(&b"hello"[..], 0)?;
+        check_encoding("gzip", &gz)?;
+        check_encoding("ZSTD", &zst)?;
+        check_encoding("br", b"anything")?;
+        assert!(check_encoding("gzip", &zst).is_err());
+        assert!(check_encoding("zstd", b"hello").is_err());
+
+        assert_eq!(decode("gzip", &gz)?, b"hello");
+        assert_eq!(decode("zstd", &zst)?, b"hello");
+        assert_eq!(decode("", b"hello")?, b"hello");
+        assert!(decode("br", b"hello").is_err());
+
+        // Separately compressed parts decode as one body
+        let parts = [gzip(b"hel")?, gzip(b"lo")?].concat();
+        assert_eq!(decode("gzip", &parts)?, b"hello");
+
+        for encoding in ["gzip", "deflate", "zstd"] {
+            assert_eq!(decode(encoding, &encode(encoding, b"hello")?)?, b"hello");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some("gzip"));
+        assert_eq!(negotiate("deflate"), Some("deflate"));
+        assert_eq!(negotiate("gzip;q=0.5, deflate"), Some("deflate"));
+        assert_eq!(negotiate("x-gzip"), Some("gzip"));
+        assert_eq!(negotiate("*"), Some("gzip"));
+        assert_eq!(negotiate("*, gzip;q=0"), Some("deflate"));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("br, gzip;q=0"), None);
+        assert_eq!(negotiate(""), None);
+    }
+}
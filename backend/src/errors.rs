@@ -1,14 +1,19 @@
 use std::fmt::{Debug, Display};
 
-use sled::transaction::ConflictableTransactionError;
+use sled::transaction::TransactionError;
 use thiserror::Error;
 
-use crate::indexer::IndexerSignal;
+use crate::{indexer::IndexerSignal, search::SearchError};
 
+/// Errors from every part of Mauve
+///
+/// This is the only error enum in the workspace; the HTTP status each variant maps to is
+/// decided in `api::errors`. Serialization failures have their own variants rather than
+/// going through `Oops`.
 #[derive(Clone, Debug, Error)]
 pub enum MauveError {
     #[error("Config error {0}")]
-    ConfigError(#[from] figment::Error),
+    ConfigError(String),
 
     #[error("Rocket exploded {0}")]
     RocketError(String),
@@ -20,36 +25,160 @@ pub enum MauveError {
     SledError(#[from] sled::Error),
 
     #[error("Sled tx error {0}")]
-    SledTxError(#[from] sled::transaction::TransactionError),
+    SledTxError(#[from] TransactionError),
 
     #[error("IO error {0}")]
     IoError(String),
 
+    #[error("HTTP client error {0}")]
+    HttpError(String),
+
+    #[error("Invalid export position {0}")]
+    InvalidExportPosition(String),
+
+    #[error("Invalid changes cursor {0}")]
+    InvalidCursor(String),
+
+    #[error("Deleting collection {0} needs ?confirm={0}")]
+    DeleteNotConfirmed(String),
+
+    #[error("Object store error {0}")]
+    ObjectStoreError(String),
+
     #[error("Signaling error {0}")]
-    SignalError(#[from] flume::SendError<IndexerSignal>),
+    SignalError(String),
+
+    #[error("Object exceeds the maximum size of {0}")]
+    ObjectTooLarge(String),
+
+    #[error("Object has more than {0} labels")]
+    TooManyLabels(usize),
+
+    #[error("Object metadata exceeds {0} bytes")]
+    MetadataTooLarge(usize),
 
     #[error("Invalid label string {0}")]
     InvalidLabel(String),
 
+    #[error("Invalid object segments {0}")]
+    InvalidSegments(String),
+
+    #[error("Object has no segment {0}")]
+    SegmentNotFound(usize),
+
+    #[error("Object has no offset named {0}")]
+    OffsetNotFound(String),
+
+    #[error("Invalid content hash {0}")]
+    InvalidContentHash(String),
+
+    #[error("Content hash mismatch, {0}")]
+    ContentHashMismatch(String),
+
+    #[error("Body is not valid for content encoding {0}")]
+    EncodingMismatch(String),
+
+    #[error("Can't decode content encoding {0}")]
+    UnsupportedEncoding(String),
+
+    #[error("Invalid batch, {0}")]
+    InvalidBatch(String),
+
+    #[error("Precondition failed, {0}")]
+    PreconditionFailed(String),
+
     #[error("{0}")]
     CollectionError(CollectionError),
 
+    #[error("{0}")]
+    SearchError(#[from] SearchError),
+
     #[error("bincode failed {0}")]
     BincodeError(String),
 
     #[error("cbor serde {0}")]
     CborError(String),
 
+    #[error("json serde {0}")]
+    JsonError(String),
+
     #[error("Oopsie {0}")]
     Oops(String),
 }
 
+impl MauveError {
+    /// Stable machine-readable name of the error, sent as `code` in API error bodies.
+    /// Errors wrapping a more specific one use its code.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MauveError::ConfigError(_) => "ConfigError",
+            MauveError::RocketError(_) => "RocketError",
+            MauveError::Utf8Error(_) => "Utf8Error",
+            MauveError::SledError(_) => "SledError",
+            MauveError::SledTxError(TransactionError::Abort(_)) => "TransactionAborted",
+            MauveError::SledTxError(TransactionError::Storage(_)) => "TransactionFailed",
+            MauveError::IoError(_) => "IoError",
+            MauveError::HttpError(_) => "HttpError",
+            MauveError::InvalidExportPosition(_) => "InvalidExportPosition",
+            MauveError::InvalidCursor(_) => "InvalidCursor",
+            MauveError::DeleteNotConfirmed(_) => "DeleteNotConfirmed",
+            MauveError::ObjectStoreError(_) => "ObjectStoreError",
+            MauveError::SignalError(_) => "SignalError",
+            MauveError::ObjectTooLarge(_) => "ObjectTooLarge",
+            MauveError::TooManyLabels(_) => "TooManyLabels",
+            MauveError::MetadataTooLarge(_) => "MetadataTooLarge",
+            MauveError::InvalidLabel(_) => "InvalidLabel",
+            MauveError::InvalidSegments(_) => "InvalidSegments",
+            MauveError::SegmentNotFound(_) => "SegmentNotFound",
+            MauveError::OffsetNotFound(_) => "OffsetNotFound",
+            MauveError::InvalidContentHash(_) => "InvalidContentHash",
+            MauveError::ContentHashMismatch(_) => "ContentHashMismatch",
+            MauveError::EncodingMismatch(_) => "EncodingMismatch",
+            MauveError::UnsupportedEncoding(_) => "UnsupportedEncoding",
+            MauveError::InvalidBatch(_) => "InvalidBatch",
+            MauveError::PreconditionFailed(_) => "PreconditionFailed",
+            MauveError::CollectionError(e) => e.code(),
+            MauveError::SearchError(SearchError::NotYetExecuted) => "SearchNotYetExecuted",
+            MauveError::SearchError(SearchError::LookupFailed { .. }) => "SearchLookupFailed",
+            MauveError::SearchError(SearchError::Cancelled) => "SearchCancelled",
+            MauveError::BincodeError(_) => "BincodeError",
+            MauveError::CborError(_) => "CborError",
+            MauveError::JsonError(_) => "JsonError",
+            MauveError::Oops(_) => "Oops",
+        }
+    }
+}
+
+impl From<figment::Error> for MauveError {
+    fn from(value: figment::Error) -> Self {
+        MauveError::ConfigError(value.to_string())
+    }
+}
+
+impl From<flume::SendError<IndexerSignal>> for MauveError {
+    fn from(value: flume::SendError<IndexerSignal>) -> Self {
+        MauveError::SignalError(value.to_string())
+    }
+}
+
 impl From<std::io::Error> for MauveError {
     fn from(value: std::io::Error) -> Self {
         MauveError::IoError(value.to_string())
     }
 }
 
+impl From<reqwest::Error> for MauveError {
+    fn from(value: reqwest::Error) -> Self {
+        MauveError::HttpError(value.to_string())
+    }
+}
+
+impl From<object_store::Error> for MauveError {
+    fn from(value: object_store::Error) -> Self {
+        MauveError::ObjectStoreError(value.to_string())
+    }
+}
+
 impl From<ciborium::de::Error<std::io::Error>> for MauveError {
     fn from(value: ciborium::de::Error<std::io::Error>) -> Self {
         Self::CborError(value.to_string())
@@ -62,16 +191,38 @@ impl From<ciborium::ser::Error<std::io::Error>> for MauveError {
     }
 }
 
-impl Into<ConflictableTransactionError> for MauveError {
-    fn into(self) -> ConflictableTransactionError {
-        ConflictableTransactionError::Abort(sled::Error::ReportableBug(self.to_string()))
+/// The error a transaction aborted with, or the storage error that failed it
+impl From<TransactionError<MauveError>> for MauveError {
+    fn from(value: TransactionError<MauveError>) -> Self {
+        match value {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => MauveError::SledError(e),
+        }
     }
 }
 
 #[derive(Clone)]
 pub enum CollectionError {
     PutObjectExistsNoReplace,
+    CollectionExists,
+    CollectionNotFound,
     ObjectNotFound,
+    NameTooLong(usize),
+    KeyTooLong(usize),
+}
+
+impl CollectionError {
+    /// Stable machine-readable name of the error, see `MauveError::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CollectionError::PutObjectExistsNoReplace => "ObjectExists",
+            CollectionError::CollectionExists => "CollectionExists",
+            CollectionError::CollectionNotFound => "CollectionNotFound",
+            CollectionError::ObjectNotFound => "ObjectNotFound",
+            CollectionError::NameTooLong(_) => "NameTooLong",
+            CollectionError::KeyTooLong(_) => "KeyTooLong",
+        }
+    }
 }
 
 impl Debug for CollectionError {
@@ -86,7 +237,13 @@ impl Display for CollectionError {
             CollectionError::PutObjectExistsNoReplace => {
                 write!(f, "Object exists with ident, replace=false")
             }
+            CollectionError::CollectionExists => write!(f, "Collection already exists"),
+            CollectionError::CollectionNotFound => write!(f, "Collection not found"),
             CollectionError::ObjectNotFound => write!(f, "Object not found"),
+            CollectionError::NameTooLong(max) => {
+                write!(f, "Collection name is longer than {max} bytes")
+            }
+            CollectionError::KeyTooLong(max) => write!(f, "Object key is longer than {max} bytes"),
         }
     }
 }
@@ -0,0 +1,235 @@
+//! Export
+//!
+//! The framed byte stream a backend is backed up as, shared by the HTTP and S3 backups.
+//!
+//! An export is a sequence of frames. Each tree starts with a tree frame giving its name,
+//! followed by an entry frame for every key in the tree:
+//!
+//! - tree: `0x01`, name length (u32 BE), name
+//! - entry: `0x02`, key length (u32 BE), key, value length (u32 BE), value
+//!
+//! Every tree in the database is exported except sled's default tree, including the label
+//! indexes, so a restore needs no reindexing.
+//!
+//! # Ordering
+//!
+//! Trees are exported in ascending byte order of their names, and the entries of a tree in
+//! ascending byte order of their keys, which is sled's iteration order. Two exports of the
+//! same data are therefore byte-identical, and any entry names a fixed position in the
+//! stream. An interrupted export can be resumed after the last complete entry received
+//! with `Export::resume_after`, given as `<tree>:<hex key>`; the resumed stream starts with
+//! the next entry frame, so it can be appended to the partial one as-is.
+
+use std::{ops::Bound, sync::Arc};
+
+use object_store::{buffered::BufWriter, path::Path, ObjectStore};
+use tokio::io::AsyncWriteExt;
+
+use crate::errors::MauveError;
+
+const FRAME_TREE: u8 = 0x01;
+const FRAME_ENTRY: u8 = 0x02;
+
+/// Name of sled's default tree, which Mauve only uses for settings of the local database
+const DEFAULT_TREE: &[u8] = b"__sled__default";
+
+/// Iterator over the frames of a backend export, one frame per item
+pub struct Export {
+    db: sled::Db,
+    trees: std::vec::IntoIter<sled::IVec>,
+    current: Option<sled::Iter>,
+}
+
+impl Export {
+    pub(crate) fn new(db: sled::Db) -> Self {
+        Self {
+            trees: sorted_trees(&db).into_iter(),
+            db,
+            current: None,
+        }
+    }
+
+    /// Continue an export after the entry at `key` in `tree`
+    ///
+    /// The position is written `<tree>:<key>` with the key in hex, as keys such as those of
+    /// the changes feed are binary. Tree names contain colons themselves, but hex doesn't, so
+    /// the tree is everything before the last `:`.
+    pub(crate) fn resume_after(db: sled::Db, position: &str) -> Result<Self, MauveError> {
+        let invalid = || MauveError::InvalidExportPosition(position.to_string());
+        let trees = sorted_trees(&db);
+        let (tree, key) = position.rsplit_once(':').ok_or_else(invalid)?;
+        if !trees.iter().any(|name| name == tree.as_bytes()) {
+            return Err(invalid());
+        }
+        let key = hex::decode(key).map_err(|_| invalid())?;
+
+        let entries = db
+            .open_tree(tree)?
+            .range::<&[u8], _>((Bound::Excluded(key.as_slice()), Bound::Unbounded));
+        let remaining: Vec<_> = trees
+            .into_iter()
+            .filter(|name| name.as_ref() > tree.as_bytes())
+            .collect();
+        Ok(Self {
+            db,
+            trees: remaining.into_iter(),
+            current: Some(entries),
+        })
+    }
+}
+
+/// Every exported tree name in export order
+fn sorted_trees(db: &sled::Db) -> Vec<sled::IVec> {
+    let mut trees: Vec<_> = db
+        .tree_names()
+        .into_iter()
+        .filter(|name| name != DEFAULT_TREE)
+        .collect();
+    trees.sort();
+    trees
+}
+
+impl Iterator for Export {
+    type Item = Result<Vec<u8>, MauveError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entries) = &mut self.current {
+            match entries.next() {
+                Some(Ok((key, value))) => return Some(Ok(entry_frame(&key, &value))),
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => self.current = None,
+            }
+        }
+        let name = self.trees.next()?;
+        match self.db.open_tree(&name) {
+            Ok(tree) => {
+                self.current = Some(tree.iter());
+                Some(Ok(tree_frame(&name)))
+            }
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+fn tree_frame(name: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + name.len());
+    frame.push(FRAME_TREE);
+    push_field(&mut frame, name);
+    frame
+}
+
+fn entry_frame(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + key.len() + value.len());
+    frame.push(FRAME_ENTRY);
+    push_field(&mut frame, key);
+    push_field(&mut frame, value);
+    frame
+}
+
+fn push_field(frame: &mut Vec<u8>, field: &[u8]) {
+    frame.extend((field.len() as u32).to_be_bytes());
+    frame.extend(field);
+}
+
+/// Write an export to an object store, as a multipart upload once it outgrows one part.
+///
+/// The upload is aborted if the export fails part way, so a partial backup is never left
+/// behind under `path`.
+pub(crate) async fn upload(
+    export: Export,
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+) -> Result<(), MauveError> {
+    let mut writer = BufWriter::new(store, path);
+    for frame in export {
+        let written = match frame {
+            Ok(frame) => writer.write_all(&frame).await.map_err(MauveError::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            if let Err(abort) = writer.abort().await {
+                log::error!("failed to abort backup upload {abort}");
+            }
+            return Err(e);
+        }
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+
+    use super::upload;
+    use crate::{api::tests::test_config, backend::Backend};
+
+    #[rocket::async_test]
+    async fn test_upload_export() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("exported")?;
+        collection.put_object("b", b"two".to_vec(), false)?;
+        collection.put_object("a", b"one".to_vec(), false)?;
+
+        let expected: Vec<u8> = backend
+            .export_stream()
+            .collect::<Result<Vec<_>, _>>()?
+            .concat();
+        // Keys come out in order within their tree
+        let data = b"\x01\x00\x00\x00\x14mauve_data::exported\
+            \x02\x00\x00\x00\x01a\x00\x00\x00\x03one\
+            \x02\x00\x00\x00\x01b\x00\x00\x00\x03two";
+        assert!(expected.windows(data.len()).any(|w| w == data));
+
+        let store = Arc::new(InMemory::new());
+        let path = Path::from("backups/test.mauve");
+        upload(backend.export_stream(), store.clone(), path.clone()).await?;
+        let uploaded = store.get(&path).await?.bytes().await?;
+        assert_eq!(uploaded.as_ref(), expected.as_slice());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_resume_export() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("res:umed")?;
+        for name in ["a", "b:1", "c"] {
+            collection.put_object(name, name.as_bytes().to_vec(), false)?;
+        }
+
+        let full = backend.export_stream().collect::<Result<Vec<_>, _>>()?;
+        let resumed = backend
+            .resume_export(&format!("mauve_data::res:umed:{}", hex::encode("b:1")))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let entry = b"\x02\x00\x00\x00\x03b:1\x00\x00\x00\x03b:1".to_vec();
+        let at = full.iter().position(|frame| frame == &entry).unwrap();
+        assert_eq!(full[at + 1..], resumed[..]);
+
+        // The changes feed is keyed by a big endian timestamp, so stop inside it
+        let tree = b"mauve_changes::res:umed";
+        let start = full
+            .iter()
+            .position(|frame| frame[0] == 0x01 && frame.ends_with(tree))
+            .unwrap();
+        let frame = &full[start + 2];
+        let len = u32::from_be_bytes(frame[1..5].try_into()?) as usize;
+        let key = &frame[5..5 + len];
+        assert!(std::str::from_utf8(key).is_err());
+        let resumed = backend
+            .resume_export(&format!("mauve_changes::res:umed:{}", hex::encode(key)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(full[start + 3..], resumed[..]);
+
+        assert!(backend.resume_export("mauve_nope::x:61").is_err());
+        assert!(backend.resume_export("mauve_data::res:umed:b:1").is_err());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
@@ -8,50 +8,169 @@
 //! The job of the indexer is to manage indexer threads for each known collection. The indexer
 //! thread watches their collection metadata for labels. The indexer thread maintains a
 //! forward and reverse index of `Label => [ObjectRef, ...]`.
+//!
+//! Events that fail to index are recorded in the collection's dead letter tree
+//! (`mauve_index_dlq::<name>`) so they can be inspected and replayed later.
+//!
+//! Labels whose names are listed under `numeric_labels` for a collection are additionally
+//! kept in `mauve_num::<name>`, ordered by value, so searches can select a value range.
+//!
+//! Each collection indexer stamps the time in the backend's `IndexerHealth` every
+//! `ALIVE_INTERVAL` and after every event, and clears it when it exits, so
+//! `/v1/backend/status` can show a stuck or dead indexer.
 
 use crate::{
     backend::Backend,
+    changes::now_millis,
     collection::Collection,
     errors::MauveError,
+    labels::{numeric_key, Label},
     meta::Metadata,
     objects::{ObjectRef, ObjectRefs, ToFromMauve},
 };
 use dashmap::DashMap;
 use flume::{Receiver, Sender};
 use futures::{stream::FuturesUnordered, StreamExt};
-use sled::{transaction::ConflictableTransactionError, Event};
-use std::{fmt::Display, sync::Arc, time::Duration};
+use macros::MauveObject;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionalTree},
+    Event, Transactional,
+};
+use std::{collections::HashSet, fmt::Display, sync::Arc, time::Duration};
+
+/// Attempts made at indexing an event before it goes to the dead letter tree
+const INDEX_ATTEMPTS: u32 = 4;
+
+/// Delay before the first retry of an index update, doubled on each further retry
+const INDEX_BACKOFF: Duration = Duration::from_millis(2);
+
+/// Time between a collection indexer's alive stamps while it's idle
+const ALIVE_INTERVAL: Duration = Duration::from_secs(10);
 
 type CollectionName = String;
+type IndexerChannel = (Sender<IndexerSignal>, Receiver<IndexerSignal>);
 
 #[derive(Clone)]
 pub enum IndexerSignal {
     Watch(Collection),
     Unwatch(Collection),
     Rebuild(Collection),
+    /// Index any labels missing from the collection's index
+    Reconcile(Collection),
     Shutdown,
 }
 
+/// The kind of index update a dead letter failed to apply
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexOp {
+    Insert,
+    Remove,
+}
+
+/// An index event that failed, stored in the collection's dead letter tree by object key
+#[derive(Clone, Debug, Serialize, Deserialize, MauveObject)]
+pub struct DeadLetter {
+    pub key: String,
+    pub op: IndexOp,
+    pub error: String,
+    pub attempts: u32,
+    /// Metadata the object was indexed with. Removals need it to find the labels to
+    /// unindex, since the metadata itself is already gone.
+    pub meta: Option<Metadata>,
+}
+
+/// Outcome of replaying a collection's dead letters
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub failed: usize,
+}
+
+/// Retry every event in a collection's dead letter tree.
+///
+/// Events that index successfully are removed from the tree; the rest stay with their
+/// attempt count bumped and the latest error.
+pub fn replay_dead_letters(collection: Collection) -> Result<ReplayReport, MauveError> {
+    CollectionIndexer::new(collection, flume::unbounded()).replay()
+}
+
+/// Drop a deleted object from the label indexes under every label in its metadata.
+///
+/// Deletes call this with the metadata they removed, since it's gone by the time the
+/// indexer sees the object's remove event. Failures go to the dead letter tree.
+pub(crate) fn unindex(
+    collection: &Collection,
+    object: &str,
+    meta: Metadata,
+) -> Result<(), MauveError> {
+    let indexer = CollectionIndexer::new(collection.clone(), flume::unbounded());
+    if let Err(e) = indexer.index_remove(object, &meta) {
+        indexer.dead_letter(object, IndexOp::Remove, Some(meta), e)?;
+    }
+    Ok(())
+}
+
+/// Indexer state shared with the backend, so it can report it in `Backend::status`
+#[derive(Clone, Default)]
+pub struct IndexerHealth {
+    /// The channel of each watched collection's indexer
+    pub watching: Arc<DashMap<CollectionName, IndexerChannel>>,
+    /// When each running collection indexer last reported alive, in milliseconds since the
+    /// epoch
+    pub alive: Arc<DashMap<CollectionName, u64>>,
+}
+
+/// The state of one collection's indexer, as reported by `Backend::status`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexerStatus {
+    pub collection: String,
+    /// `true` if the indexer has been asked to watch the collection
+    pub watched: bool,
+    /// Signals waiting in the collection indexer's channel
+    pub queued: usize,
+    /// Milliseconds since the epoch the collection indexer last reported alive. This is
+    /// `None` if it isn't running, and falls behind the current time if it's stuck.
+    pub last_alive: Option<u64>,
+}
+
+impl IndexerHealth {
+    /// The state of the indexer for `collection`
+    pub fn status(&self, collection: &str) -> IndexerStatus {
+        let watch = self.watching.get(collection);
+        IndexerStatus {
+            collection: collection.to_string(),
+            watched: watch.is_some(),
+            queued: watch.map(|watch| watch.value().0.len()).unwrap_or_default(),
+            last_alive: self.alive.get(collection).map(|at| *at),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Indexer {
-    pub watching: Arc<DashMap<CollectionName, (Sender<IndexerSignal>, Receiver<IndexerSignal>)>>,
-    pub mux: Arc<Vec<Sender<IndexerSignal>>>,
+    pub watching: Arc<DashMap<CollectionName, IndexerChannel>>,
+    pub alive: Arc<DashMap<CollectionName, u64>>,
+    /// Time between heartbeat logs, if they are enabled
+    pub heartbeat: Option<Duration>,
 }
 
 impl Indexer {
     pub fn initialize(backend: Backend) -> Result<Self, MauveError> {
-        let watches = DashMap::new();
-        let mut mux = vec![];
+        let health = backend.indexer_health().clone();
+        let watches = health.watching.clone();
 
         for collection in backend.list_collections()? {
             log::info!(collection = collection; "Starting indexer for collection");
             // Create a channel for the indexer thread to control its children
             let (tx, rx) = flume::unbounded();
-            mux.push(tx.clone());
             watches.insert(collection.clone(), (tx.clone(), rx.clone()));
 
             // Start a task thread for each known collection to maintain the index
             let backend = backend.clone();
+            let alive = health.alive.clone();
             tokio::task::spawn(async move {
                 let backend = backend;
                 let chan = (tx.clone(), rx.clone());
@@ -59,7 +178,7 @@ impl Indexer {
                 let indexer = CollectionIndexer::new(collection, chan);
 
                 tokio::task::spawn(async move {
-                    match indexer.run().await {
+                    match indexer.run(alive).await {
                         Ok(_) => log::info!("collection indexer exited"),
                         Err(e) => log::error!("collection indexer error {e}"),
                     }
@@ -69,9 +188,14 @@ impl Indexer {
             });
         }
 
+        let heartbeat = match backend.config().indexer_heartbeat_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
         let this = Self {
-            watching: Arc::new(watches),
-            mux: Arc::new(mux),
+            watching: watches,
+            alive: health.alive,
+            heartbeat,
         };
 
         Ok(this)
@@ -81,22 +205,26 @@ impl Indexer {
         &self,
         signals: (Sender<IndexerSignal>, Receiver<IndexerSignal>),
     ) -> Result<(), MauveError> {
+        // Signals sent while initializing are kept: a Watch for a collection that already
+        // has an indexer is ignored below, and a Rebuild must not be lost.
         let (_tx, rx) = signals;
-        for _ in rx.drain() {
-            ()
-        }
-        let report = tokio::time::interval(Duration::from_secs(120));
+        // When disabled the period doesn't matter, as the branch below is never polled
+        let report = tokio::time::interval(self.heartbeat.unwrap_or(Duration::from_secs(120)));
 
         tokio::pin!(report);
         loop {
             tokio::select! {
-                _ = report.tick() => {
-                    let mut watching = String::new();
-                    for watch in self.watching.iter() {
-                        watching.push_str(&format!("{}, ", watch.key()));
-                    }
-                    let watching = watching.trim_end_matches(',');
-                    log::info!("Indexer is alive, watching: {watching}");
+                _ = report.tick(), if self.heartbeat.is_some() => {
+                    // Each collection with its indexer's queued signals, like `CollectionIndexer`
+                    let watching: Vec<_> = self
+                        .watching
+                        .iter()
+                        .map(|watch| {
+                            let (tx, rx) = watch.value();
+                            format!("{} {}/{}", watch.key(), tx.len(), rx.len())
+                        })
+                        .collect();
+                    log::info!("Indexer is alive, watching: {}", watching.join(", "));
                 }
                 Ok(sig) = rx.recv_async() => {
                     match sig {
@@ -105,8 +233,9 @@ impl Indexer {
                                 let chan = flume::unbounded();
                                 let indexer = CollectionIndexer::new(c.clone(), chan.clone());
                                 let _ = self.watching.insert(c.name.clone(), chan);
+                                let alive = self.alive.clone();
                                 tokio::task::spawn(async move {
-                                    match indexer.clone().run().await {
+                                    match indexer.clone().run(alive).await {
                                         Ok(_) => Ok(()),
                                         Err(e) => {
                                             log::error!("error in collection indexer {indexer}: {e}");
@@ -117,17 +246,20 @@ impl Indexer {
                             }
                         }
                         IndexerSignal::Unwatch(c) => {
-                            match self.watching.get(&c.name) {
-                                Some(entry) => {
-                                    let (tx, _rx) = entry.value();
-                                    tx.send(IndexerSignal::Unwatch(c))?;
-                                },
-                                None => (),
+                            // Forget the collection so a later Watch starts a fresh indexer
+                            if let Some((_, (tx, _rx))) = self.watching.remove(&c.name) {
+                                tx.send(IndexerSignal::Unwatch(c))?;
                             }
                         },
                         IndexerSignal::Shutdown => {
+                            // Every collection watched, including those created since start
+                            let senders: Vec<_> = self
+                                .watching
+                                .iter()
+                                .map(|watch| watch.value().0.clone())
+                                .collect();
                             let mut futures = FuturesUnordered::new();
-                            for tx in self.mux.iter() {
+                            for tx in senders.iter() {
                                 futures.push(tx.send_async(IndexerSignal::Shutdown));
                             }
                             while let Some(r) = futures.next().await {
@@ -138,7 +270,18 @@ impl Indexer {
                             }
                             return Ok(())
                         }
-                        IndexerSignal::Rebuild(_c) => log::warn!("make rebuild work before you try it dumbass"),
+                        IndexerSignal::Rebuild(c) => {
+                            match self.watching.get(&c.name) {
+                                Some(entry) => entry.value().0.send(IndexerSignal::Rebuild(c))?,
+                                None => log::warn!(collection = c.name; "can't rebuild the index of an unwatched collection"),
+                            }
+                        }
+                        IndexerSignal::Reconcile(c) => {
+                            match self.watching.get(&c.name) {
+                                Some(entry) => entry.value().0.send(IndexerSignal::Reconcile(c))?,
+                                None => log::warn!(collection = c.name; "can't reconcile the index of an unwatched collection"),
+                            }
+                        }
                     }
                 }
             }
@@ -172,13 +315,24 @@ impl CollectionIndexer {
         Self { collection, chan }
     }
 
-    pub async fn run(self) -> Result<(), MauveError> {
-        let meta = self.collection.data_tree();
+    /// Index the collection until told to stop, keeping its alive stamp in `alive`
+    pub async fn run(self, alive: Arc<DashMap<CollectionName, u64>>) -> Result<(), MauveError> {
+        let result = self.watch(&alive).await;
+        alive.remove(&self.collection.name);
+        result
+    }
+
+    async fn watch(&self, alive: &DashMap<CollectionName, u64>) -> Result<(), MauveError> {
+        // Subscribe once, as a new subscriber would miss the events written between polls
+        let mut events = self.collection.data_tree().watch_prefix(vec![]);
+        let mut stamp = tokio::time::interval(ALIVE_INTERVAL);
 
         loop {
+            alive.insert(self.collection.name.clone(), now_millis()?);
             tokio::select! {
-                Some(event) = meta.watch_prefix(vec![]) => {
-                    match self.process_event(event) {
+                _ = stamp.tick() => (),
+                Some(event) = &mut events => {
+                    match self.process_event(event).await {
                         Ok(_) => (),
                         Err(e) => log::error!("indexer failure {e}")
                     }
@@ -187,8 +341,25 @@ impl CollectionIndexer {
                     match sig {
                         Ok(sig) => match sig {
                             IndexerSignal::Unwatch(_) => break,
-                            IndexerSignal::Rebuild(_) => (),
-                            IndexerSignal::Shutdown => return Ok(()),
+                            IndexerSignal::Rebuild(_) => {
+                                if let Err(e) = self.rebuild() {
+                                    log::error!("index rebuild failed {e}");
+                                }
+                            }
+                            IndexerSignal::Reconcile(_) => {
+                                if let Err(e) = self.reconcile() {
+                                    log::error!("index reconcile failed {e}");
+                                }
+                            }
+                            IndexerSignal::Shutdown => {
+                                // Index the writes that landed before the shutdown
+                                while let Ok(event) = events.next_timeout(Duration::ZERO) {
+                                    if let Err(e) = self.process_event(event).await {
+                                        log::error!("indexer failure {e}");
+                                    }
+                                }
+                                return Ok(());
+                            }
                             _ => (),
                         },
                         Err(e) => {
@@ -202,118 +373,521 @@ impl CollectionIndexer {
         Ok(())
     }
 
-    fn process_event(&self, event: Event) -> Result<(), MauveError> {
+    #[cfg_attr(
+        feature = "otel",
+        tracing::instrument(skip_all, fields(collection = %self.collection.name))
+    )]
+    async fn process_event(&self, event: Event) -> Result<(), MauveError> {
         match event {
             Event::Insert { key, value: _ } => {
                 let object = String::from_utf8(key.to_vec())?;
-                let or = ObjectRef::new(&self.collection.name, &object);
-                let bytes = match self.collection.meta_tree().get(key)? {
-                    Some(bytes) => bytes,
-                    None => return Ok(()), // Skip if no metadata
-                };
-                let meta: Metadata = Metadata::from_object(bytes.to_vec())?;
-
-                for label in meta.labels {
-                    self.upsert(self.collection.index_fwd(), label.to_fwd(), or.clone())?;
-                    self.upsert(self.collection.index_rev(), label.to_rev(), or.clone())?;
+                if let Err(e) = self.with_retry(|| self.index_insert(&object)).await {
+                    self.dead_letter(&object, IndexOp::Insert, None, e)?;
                 }
             }
             Event::Remove { key } => {
+                // Deletes through `Collection` remove the metadata and labels themselves, so
+                // this only finds metadata for objects removed some other way. The metadata
+                // is left for whoever removed the object.
                 let object = String::from_utf8(key.to_vec())?;
-                let or = ObjectRef::new(&self.collection.name, &object);
-                let bytes = match self.collection.meta_tree().remove(key)? {
+                if self.collection.data_tree().contains_key(&key)? {
+                    return Ok(()); // Written again since
+                }
+                let bytes = match self.collection.meta_tree().get(key)? {
                     Some(bytes) => bytes,
                     None => return Ok(()), // Skip if no metadata
                 };
-                let meta: Metadata = Metadata::from_object(bytes.to_vec())?;
-                for label in meta.labels {
-                    self.downsert(self.collection.index_fwd(), label.to_fwd(), or.clone())?;
-                    self.downsert(self.collection.index_rev(), label.to_rev(), or.clone())?;
+                let meta = match Metadata::from_object(bytes.to_vec()) {
+                    Ok(meta) => meta,
+                    Err(e) => return self.dead_letter(&object, IndexOp::Remove, None, e),
+                };
+                if let Err(e) = self.with_retry(|| self.index_remove(&object, &meta)).await {
+                    self.dead_letter(&object, IndexOp::Remove, Some(meta), e)?;
                 }
             }
         }
         Ok(())
     }
 
-    /// Upsert a label into a target tree
+    /// Index an object under every label in its current metadata
+    fn index_insert(&self, object: &str) -> Result<(), MauveError> {
+        let or = self.collection.object_ref(object);
+        let bytes = match self.collection.meta_tree().get(object)? {
+            Some(bytes) => bytes,
+            None => return Ok(()), // Skip if no metadata
+        };
+        let meta: Metadata = Metadata::from_object(bytes.to_vec())?;
+
+        self.upsert(&meta.labels, &or)?;
+        for label in &meta.labels {
+            self.numeric_insert(object, label)?;
+        }
+        // A later successful index supersedes any earlier failure
+        self.collection.dlq_tree().remove(object)?;
+        Ok(())
+    }
+
+    /// Rebuild the label indexes and Bloom filter from scratch from the metadata tree
+    fn rebuild(&self) -> Result<(), MauveError> {
+        self.collection.index_fwd().clear()?;
+        self.collection.index_rev().clear()?;
+        if let Some(index) = self.collection.index_num() {
+            index.clear()?;
+        }
+        let mut objects = 0;
+        for key in self.collection.meta_tree().iter().keys() {
+            let object = String::from_utf8(key?.to_vec())?;
+            if let Err(e) = self.index_insert(&object) {
+                self.dead_letter(&object, IndexOp::Insert, None, e)?;
+            }
+            objects += 1;
+        }
+        log::info!(collection = self.collection.name; "Rebuilt label index from {objects} objects");
+        self.collection.rebuild_bloom()
+    }
+
+    /// Index the labels of every stored object that are missing from the index, leaving
+    /// labels already indexed alone. Returns how many objects were indexed.
+    fn reconcile(&self) -> Result<usize, MauveError> {
+        let (fwd, rev) = (self.collection.index_fwd(), self.collection.index_rev());
+        let indexed = |index: &sled::Tree, label: String, or: &ObjectRef| {
+            Ok::<_, MauveError>(match index.get(label)? {
+                Some(bytes) => ObjectRefs::from_index(&bytes)?.contains(or),
+                None => false,
+            })
+        };
+        let mut reindexed = 0;
+        for entry in self.collection.meta_tree().iter() {
+            let (key, bytes) = entry?;
+            // Metadata written ahead of its object is indexed when the object lands
+            if !self.collection.data_tree().contains_key(&key)? {
+                continue;
+            }
+            let object = String::from_utf8(key.to_vec())?;
+            let or = self.collection.object_ref(&object);
+            let meta = Metadata::from_object(bytes.to_vec())?;
+            let mut missing = false;
+            for label in &meta.labels {
+                if !indexed(&fwd, label.to_fwd(), &or)? || !indexed(&rev, label.to_rev(), &or)? {
+                    missing = true;
+                    break;
+                }
+            }
+            if missing {
+                if let Err(e) = self.index_insert(&object) {
+                    self.dead_letter(&object, IndexOp::Insert, None, e)?;
+                }
+                reindexed += 1;
+            }
+        }
+        if reindexed > 0 {
+            log::info!(collection = self.collection.name; "Indexed {reindexed} objects missing from the label index");
+        }
+        Ok(reindexed)
+    }
+
+    /// Remove an object from the index under every label in `meta`
+    fn index_remove(&self, object: &str, meta: &Metadata) -> Result<(), MauveError> {
+        let or = self.collection.object_ref(object);
+        self.downsert(&meta.labels, &or)?;
+        for label in &meta.labels {
+            if let (Some(index), Ok(value)) = (self.collection.index_num(), label.value.parse()) {
+                index.remove(numeric_key(&label.name, value, object))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a label to the numeric index if its name is configured as numeric.
     ///
-    /// This inserts the objectref into the list with the given label.  
-    /// This creates a new label if necessary.
-    fn upsert(
+    /// Values that aren't numbers are left to the string index only.
+    fn numeric_insert(&self, object: &str, label: &Label) -> Result<(), MauveError> {
+        let Some(index) = self.collection.index_num() else {
+            return Ok(());
+        };
+        if !self.collection.is_numeric_label(&label.name) {
+            return Ok(());
+        }
+        match label.value.parse::<f64>() {
+            Ok(value) if !value.is_nan() => {
+                index.insert(numeric_key(&label.name, value, object), vec![])?;
+            }
+            _ => {
+                log::warn!(collection = self.collection.name, object = object; "numeric label {label} is not a number, only indexing it as a string");
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a failed index event in the dead letter tree
+    ///
+    /// Watched events get here once `with_retry` gives up on them, and are retried again by
+    /// replaying the dead letters.
+    fn dead_letter(
         &self,
-        target: sled::Tree,
-        labelstr: String,
-        or: ObjectRef,
+        object: &str,
+        op: IndexOp,
+        meta: Option<Metadata>,
+        error: MauveError,
     ) -> Result<(), MauveError> {
-        target.transaction(|target| {
-            match target.get(&labelstr)? {
-                Some(old) => {
-                    let mut old: ObjectRefs =
-                        ObjectRefs::from_object(old.to_vec()).map_err(|e| {
-                            ConflictableTransactionError::Storage(sled::Error::ReportableBug(
-                                e.to_string(),
-                            ))
-                        })?;
-                    old.push(or.clone());
-                    let old = old.to_object().map_err(|e| {
-                        ConflictableTransactionError::Storage(sled::Error::ReportableBug(
-                            e.to_string(),
-                        ))
-                    })?;
-                    let _ = target.insert(labelstr.clone().into_bytes(), old)?;
+        log::error!(collection = self.collection.name, object = object; "indexer failure {error}");
+        let dlq = self.collection.dlq_tree();
+        let attempts = match dlq.get(object)? {
+            Some(old) => DeadLetter::from_object(old.to_vec())?.attempts + 1,
+            None => 1,
+        };
+        let letter = DeadLetter {
+            key: object.to_string(),
+            op,
+            error: error.to_string(),
+            attempts,
+            meta,
+        };
+        dlq.insert(object, letter.to_object()?)?;
+        Ok(())
+    }
+
+    fn replay(&self) -> Result<ReplayReport, MauveError> {
+        let mut report = ReplayReport::default();
+        for letter in self.collection.list_dead_letters()? {
+            let result = match (letter.op, &letter.meta) {
+                (IndexOp::Insert, _) => self.index_insert(&letter.key),
+                (IndexOp::Remove, Some(meta)) => self.index_remove(&letter.key, meta),
+                (IndexOp::Remove, None) => {
+                    log::warn!(object = letter.key; "dropping removal with unreadable metadata");
+                    Ok(())
+                }
+            };
+            match result {
+                Ok(()) => {
+                    self.collection.dlq_tree().remove(&letter.key)?;
+                    report.replayed += 1;
                 }
-                None => {
-                    let new = ObjectRefs::new(vec![or.clone()]);
-                    let new = new.to_object().map_err(|e| {
-                        ConflictableTransactionError::Storage(sled::Error::ReportableBug(
-                            e.to_string(),
-                        ))
-                    })?;
-                    target.insert(labelstr.clone().into_bytes(), new)?;
+                Err(e) => {
+                    self.dead_letter(&letter.key, letter.op, letter.meta, e)?;
+                    report.failed += 1;
                 }
             }
+        }
+        Ok(report)
+    }
+
+    /// Run an index update, retrying storage failures with jittered exponential backoff
+    /// before giving up with the error of the last attempt.
+    ///
+    /// Only `SledError`s are retried. Sled already reruns a transaction that conflicts with a
+    /// concurrent writer, so these are storage failures, which may pass. Anything else, like
+    /// an index value that can't be decoded, won't fix itself and is returned at once.
+    async fn with_retry(&self, f: impl Fn() -> Result<(), MauveError>) -> Result<(), MauveError> {
+        let mut backoff = INDEX_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(MauveError::SledError(e)) if attempt < INDEX_ATTEMPTS => {
+                    log::warn!(collection = self.collection.name, attempt = attempt; "retrying index update {e}");
+                    let jitter = rand::thread_rng().gen_range(0..=backoff.as_micros() as u64);
+                    tokio::time::sleep(backoff + Duration::from_micros(jitter)).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Upsert an object's labels into the forward and reverse indexes
+    ///
+    /// This inserts the objectref into the list of every label, creating labels as
+    /// necessary. Both trees are updated in one transaction, so a label is never in one
+    /// index and missing from the other.
+    fn upsert(&self, labels: &HashSet<Label>, or: &ObjectRef) -> Result<(), MauveError> {
+        let compress = self.collection.config.compress_index;
+        (&self.collection.index_fwd, &self.collection.index_rev).transaction(|(fwd, rev)| {
+            for label in labels {
+                add_ref(fwd, label.to_fwd(), or, compress)?;
+                add_ref(rev, label.to_rev(), or, compress)?;
+            }
             Ok(())
         })?;
-
         Ok(())
     }
 
-    /// Downsert a label from an index tree
+    /// Downsert an object's labels from the forward and reverse indexes
     ///
-    /// This removes the ObjectRef from the list with the given label.  
-    /// If removing the ref would leave an empty list, the label is removed.
-    fn downsert(
-        &self,
-        target: sled::Tree,
-        labelstr: String,
-        or: ObjectRef,
-    ) -> Result<(), MauveError> {
-        target.transaction(|target| {
-            match target.get(&labelstr)? {
-                Some(old) => {
-                    let mut old = ObjectRefs::from_object(old.to_vec()).map_err(|e| {
-                        ConflictableTransactionError::Storage(sled::Error::ReportableBug(
-                            e.to_string(),
-                        ))
-                    })?;
-                    if old.len() == 1 {
-                        // short circuit remove unused label
-                        let _ = target.remove(labelstr.clone().into_bytes())?;
-                        return Ok(());
-                    }
-                    old.retain(|x| x != &or);
-                    let old = old.to_object().map_err(|e| {
-                        ConflictableTransactionError::Storage(sled::Error::ReportableBug(
-                            e.to_string(),
-                        ))
-                    })?;
-                    let _ = target.insert(labelstr.clone().into_bytes(), old)?;
-                }
-                None => (),
+    /// This removes the objectref from the list of every label, in one transaction like
+    /// `upsert`. Labels left with an empty list are removed.
+    fn downsert(&self, labels: &HashSet<Label>, or: &ObjectRef) -> Result<(), MauveError> {
+        let compress = self.collection.config.compress_index;
+        (&self.collection.index_fwd, &self.collection.index_rev).transaction(|(fwd, rev)| {
+            for label in labels {
+                remove_ref(fwd, label.to_fwd(), or, compress)?;
+                remove_ref(rev, label.to_rev(), or, compress)?;
             }
             Ok(())
         })?;
+        Ok(())
+    }
+}
+
+/// Add an objectref to a label's list within an index transaction
+fn add_ref(
+    target: &TransactionalTree,
+    labelstr: String,
+    or: &ObjectRef,
+    compress: bool,
+) -> Result<(), ConflictableTransactionError<MauveError>> {
+    let refs = match target.get(&labelstr)? {
+        Some(old) => {
+            let mut old =
+                ObjectRefs::from_index(&old).map_err(ConflictableTransactionError::Abort)?;
+            old.insert(or.clone());
+            old
+        }
+        None => ObjectRefs::new(vec![or.clone()]),
+    };
+    let refs = refs
+        .to_index(compress)
+        .map_err(ConflictableTransactionError::Abort)?;
+    target.insert(labelstr.into_bytes(), refs)?;
+    Ok(())
+}
+
+/// Remove an objectref from a label's list within an index transaction, removing the label
+/// if that would leave the list empty
+fn remove_ref(
+    target: &TransactionalTree,
+    labelstr: String,
+    or: &ObjectRef,
+    compress: bool,
+) -> Result<(), ConflictableTransactionError<MauveError>> {
+    if let Some(old) = target.get(&labelstr)? {
+        let mut old = ObjectRefs::from_index(&old).map_err(ConflictableTransactionError::Abort)?;
+        old.remove(or);
+        if old.is_empty() {
+            // remove the unused label
+            target.remove(labelstr.into_bytes())?;
+            return Ok(());
+        }
+        let old = old
+            .to_index(compress)
+            .map_err(ConflictableTransactionError::Abort)?;
+        target.insert(labelstr.into_bytes(), old)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::{CollectionIndexer, INDEX_ATTEMPTS};
+    use crate::{
+        api::tests::test_config,
+        backend::Backend,
+        errors::MauveError,
+        labels::Label,
+        meta::Metadata,
+        objects::{ObjectRef, ObjectRefs},
+    };
+
+    #[rocket::async_test]
+    async fn test_with_retry() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let indexer = CollectionIndexer::new(backend.get_collection("flaky")?, flume::unbounded());
+
+        // Storage failures are retried until one passes
+        let calls = AtomicU32::new(0);
+        indexer
+            .with_retry(|| match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(MauveError::SledError(sled::Error::Unsupported(
+                    "flaky".to_string(),
+                ))),
+                _ => Ok(()),
+            })
+            .await?;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // and given up on after `INDEX_ATTEMPTS`
+        let calls = AtomicU32::new(0);
+        let result = indexer
+            .with_retry(|| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(MauveError::SledError(sled::Error::Unsupported(
+                    "down".to_string(),
+                )))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), INDEX_ATTEMPTS);
+
+        // Other errors won't fix themselves, so they are returned at once
+        let calls = AtomicU32::new(0);
+        let result = indexer
+            .with_retry(|| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(MauveError::CborError("eof".to_string()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_upsert_one_label() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("hot")?;
+
+        let writers = 8;
+        let per_writer = 50;
+        thread::scope(|s| {
+            for w in 0..writers {
+                let indexer = CollectionIndexer::new(collection.clone(), flume::unbounded());
+                s.spawn(move || {
+                    let labels = HashSet::from([Label::new("hot", "label")]);
+                    for n in 0..per_writer {
+                        let or = ObjectRef::new("hot", &format!("{w}-{n}"));
+                        indexer.upsert(&labels, &or).unwrap();
+                    }
+                });
+            }
+        });
+
+        for (index, label) in [
+            (&collection.index_fwd, "hot=label"),
+            (&collection.index_rev, "label=hot"),
+        ] {
+            let refs = ObjectRefs::from_index(&index.get(label)?.unwrap())?;
+            assert_eq!(refs.len(), writers * per_writer);
+        }
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_remove_reads_metadata() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("gone")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let wait_for = |key: &'static str, present: bool| {
+            let index = collection.index_fwd.clone();
+            async move {
+                for _ in 0..50 {
+                    if index.contains_key(key)? == present {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                anyhow::Ok(index.contains_key(key)?)
+            }
+        };
+        for (name, color) in [("a", "red"), ("b", "blue")] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", color));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        assert!(wait_for("color=red", true).await?);
+        assert!(wait_for("color=blue", true).await?);
+
+        // Deleting an object drops its metadata and labels straight away
+        collection.delete_object("a")?;
+        assert!(!collection.index_fwd.contains_key("color=red")?);
+        assert!(!collection.meta.contains_key("a")?);
+
+        // Removed behind the collection's back, the indexer drops the labels but leaves the
+        // metadata alone
+        collection.data.remove("b")?;
+        assert!(!wait_for("color=blue", false).await?);
+        assert!(collection.meta.contains_key("b")?);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_reconcile() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("missed")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for (name, color) in [("a", "red"), ("b", "blue")] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", color));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        for _ in 0..50 {
+            if collection.index_fwd.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // Metadata without its object isn't indexed yet
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "green"));
+        collection.put_object_metadata("c", meta)?;
+
+        // Lose one object's labels, as if it was written while the indexer was behind
+        collection.index_fwd.remove("color=blue")?;
+        collection.index_rev.remove("blue=color")?;
+
+        let indexer = CollectionIndexer::new(collection.clone(), flume::unbounded());
+        assert_eq!(indexer.reconcile()?, 1);
+        assert!(collection.index_fwd.contains_key("color=blue")?);
+        assert!(collection.index_rev.contains_key("blue=color")?);
+        assert!(!collection.index_fwd.contains_key("color=green")?);
+        assert_eq!(indexer.reconcile()?, 0);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_upsert_many_labels() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("wide")?;
+        let indexer = CollectionIndexer::new(collection.clone(), flume::unbounded());
+
+        let labels: HashSet<_> = (0..50)
+            .map(|n| Label::new(&format!("name{n}"), &format!("value{n}")))
+            .collect();
+        let started = std::time::Instant::now();
+        for n in 0..20 {
+            let or = ObjectRef::new("wide", &n.to_string());
+            indexer.upsert(&labels, &or)?;
+        }
+        log::info!(
+            "indexed 20 objects with 50 labels in {:?}",
+            started.elapsed()
+        );
+        assert_eq!(collection.index_fwd.len(), 50);
+        assert_eq!(collection.index_rev.len(), 50);
+        let refs = ObjectRefs::from_index(&collection.index_rev.get("value7=name7")?.unwrap())?;
+        assert_eq!(refs.len(), 20);
+
+        for n in 0..20 {
+            let or = ObjectRef::new("wide", &n.to_string());
+            indexer.downsert(&labels, &or)?;
+        }
+        assert!(collection.index_fwd.is_empty());
+        assert!(collection.index_rev.is_empty());
 
+        std::fs::remove_dir_all(config.sled.path)?;
         Ok(())
     }
 }
@@ -17,15 +17,126 @@ impl Label {
         }
     }
 
+    /// A label keeping the case of `name` and `value`. Labels parsed from a string are
+    /// built this way and lowercased on write unless the backend is `case_sensitive`, see
+    /// `Metadata::normalize`.
+    pub fn exact(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    /// Check that the label can be indexed: its name must be non-empty and can't contain
+    /// `=`, which separates it from the value in the index keys. Values may contain `=`.
+    pub fn validate(&self) -> Result<(), MauveError> {
+        if self.name.is_empty() || self.name.contains('=') {
+            return Err(MauveError::InvalidLabel(self.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Key of the label in the forward index, `name=value`
     #[inline(always)]
     pub fn to_fwd(&self) -> String {
         format!("{}={}", self.name, self.value)
     }
 
+    /// Key of the label in the reverse index, `value=name`. As a value may contain `=`, the
+    /// name is the part after the last one; see `Label::from_rev`.
     #[inline(always)]
     pub fn to_rev(&self) -> String {
         format!("{}={}", self.value, self.name)
     }
+
+    /// Parse a reverse index key written by `Label::to_rev`
+    pub fn from_rev(s: &str) -> Result<Self, MauveError> {
+        match s.rsplit_once('=') {
+            Some((value, name)) => {
+                let label = Self::exact(name, value);
+                label.validate()?;
+                Ok(label)
+            }
+            None => Err(MauveError::InvalidLabel(s.to_string())),
+        }
+    }
+
+    /// `name=value` with `%`, `,` and `=` percent-encoded in both, so labels can be joined
+    /// with commas and split apart again unambiguously
+    pub fn encode(&self) -> String {
+        format!(
+            "{}={}",
+            percent_encode(&self.name),
+            percent_encode(&self.value)
+        )
+    }
+
+    /// Parse a label written by `Label::encode`
+    pub fn decode(s: &str) -> Result<Self, MauveError> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| MauveError::InvalidLabel(s.to_string()))?;
+        let label = match (percent_decode(name), percent_decode(value)) {
+            (Some(name), Some(value)) => Self::exact(&name, &value),
+            _ => return Err(MauveError::InvalidLabel(s.to_string())),
+        };
+        label.validate()?;
+        Ok(label)
+    }
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' | ',' | '=' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => decoded.push(b),
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Key of an object in a numeric label index: the label name, a `0x00` separator, the
+/// value encoded by `numeric_bytes`, then the object key. Entries for one name therefore sort
+/// by value, so a value range is a single range scan.
+pub(crate) fn numeric_key(name: &str, value: f64, object: &str) -> Vec<u8> {
+    let mut key = numeric_prefix(name, value);
+    key.extend(object.as_bytes());
+    key
+}
+
+/// Start of the numeric index entries for `name` with value `value`
+pub(crate) fn numeric_prefix(name: &str, value: f64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(name.len() + 9);
+    key.extend(name.as_bytes());
+    key.push(0x00);
+    key.extend(numeric_bytes(value));
+    key
+}
+
+/// Big endian encoding of an `f64` whose byte order matches numeric order
+fn numeric_bytes(value: f64) -> [u8; 8] {
+    let bits = value.to_bits();
+    let bits = match value.is_sign_negative() {
+        true => !bits,
+        false => bits | (1 << 63),
+    };
+    bits.to_be_bytes()
 }
 
 impl Display for Label {
@@ -38,9 +149,55 @@ impl FromStr for Label {
     type Err = MauveError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Names can't contain `=`, so the first one ends the name
         match s.split_once('=') {
-            Some((name, value)) => Ok(Self::new(name, value)),
+            Some((name, value)) => {
+                let label = Self::exact(name, value);
+                label.validate()?;
+                Ok(label)
+            }
             None => Err(MauveError::InvalidLabel(s.to_string())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Label;
+
+    #[test]
+    fn test_label_encoding() -> anyhow::Result<()> {
+        let label = Label::new("path", "a,b=c%d.txt");
+        assert_eq!(label.encode(), "path=a%2Cb%3Dc%25d.txt");
+        assert_eq!(Label::decode(&label.encode())?, label);
+        // Labels without reserved characters encode as themselves
+        assert_eq!(Label::decode("color=mauve")?, Label::new("color", "mauve"));
+        assert_eq!(
+            Label::decode("Color=Mauve")?,
+            Label::exact("Color", "Mauve")
+        );
+        assert!(Label::decode("bad=%zz").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_validation() -> anyhow::Result<()> {
+        assert!("=mauve".parse::<Label>().is_err());
+        assert!(Label::decode("a%3Db=c").is_err());
+        assert!(Label::new("a=b", "c").validate().is_err());
+        assert!(Label::new("", "c").validate().is_err());
+
+        // Values may contain `=`, and every key round-trips
+        for label in [
+            Label::new("query", "x=1"),
+            Label::new("color", "mauve"),
+            Label::new("empty", ""),
+            Label::new("eq", "=="),
+        ] {
+            assert_eq!(label.to_fwd().parse::<Label>()?, label);
+            assert_eq!(Label::from_rev(&label.to_rev())?, label);
+            assert_eq!(Label::decode(&label.encode())?, label);
+        }
+        Ok(())
+    }
+}
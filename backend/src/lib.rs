@@ -1,9 +1,20 @@
+pub mod api;
 pub mod backend;
+pub mod bloom;
+pub mod changes;
 pub mod collection;
 pub mod config;
+pub mod encoding;
 pub mod errors;
+pub mod export;
 pub mod indexer;
 pub mod labels;
 pub mod meta;
+pub mod mirror;
 pub mod objects;
 pub mod search;
+pub mod sniff;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod tombstones;
+pub mod upstream;
@@ -1,32 +1,374 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
 
 use crate::objects::ToFromMauve;
-use crate::{errors::MauveError, labels::Label};
+use crate::{config::MauveConfig, errors::MauveError, labels::Label, sniff};
 use macros::MauveObject;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default, MauveObject)]
 pub struct Metadata {
-    pub(crate) content_type: String,
-    pub(crate) content_encoding: String,
-    pub(crate) content_language: String,
-    pub(crate) size: u64,
-    pub(crate) labels: HashSet<Label>,
-    pub(crate) offset_map: String,
+    pub content_type: String,
+    pub content_encoding: String,
+    pub content_language: String,
+    pub size: u64,
+    pub labels: HashSet<Label>,
+    /// Named byte ranges, in the `OffsetMap` form. Metadata written before segments were
+    /// typed kept an unnamed segment list here instead, see `Metadata::segments`.
+    #[serde(default)]
+    pub offset_map: String,
+    /// `(start, end_inclusive)` byte ranges of the parts an object was assembled from
+    #[serde(default)]
+    pub segments: Vec<(u64, u64)>,
+    /// Hex MD5 digest of the object, computed when it is written and served as its `ETag`.
+    /// An upload can send the digest it expects, see `parse_content_hash`. Empty for objects
+    /// written before digests were stored.
+    #[serde(default)]
+    pub content_hash: String,
+    /// When the object was first stored, in milliseconds since the epoch. Set by
+    /// `Collection::put_object_metadata` and kept when the object is replaced.
+    #[serde(default)]
+    pub created_at: u64,
+    /// When the object was last stored, in milliseconds since the epoch. Set by
+    /// `Collection::put_object_metadata` on every write.
+    #[serde(default)]
+    pub updated_at: u64,
+    /// User-defined fields, sent and served as `x-mauve-meta-<name>` headers. Names are
+    /// lowercase, as header names are case-insensitive.
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
 }
 
 impl Metadata {
+    /// Every label, encoded by `Label::encode` and joined with commas
     pub fn label_str(&self) -> String {
         let mut s = String::new();
         for label in &self.labels {
-            s.push_str(&label.to_fwd());
+            s.push_str(&label.encode());
             s.push(',');
         }
         s.trim_end_matches(',').to_string()
     }
+
+    /// Byte ranges of the object's segments as `(start, end_inclusive)` pairs
+    ///
+    /// Metadata written before segments were typed kept them in `offset_map` as
+    /// `start-end` pairs separated by commas, which is parsed when `segments` is empty and
+    /// `offset_map` holds no named ranges.
+    pub fn segments(&self) -> Result<Vec<(u64, u64)>, MauveError> {
+        if !self.segments.is_empty() || self.offset_map.is_empty() || self.has_named_offsets() {
+            return Ok(self.segments.clone());
+        }
+        parse_segments(&self.offset_map)
+    }
+
+    /// Every byte range of the object: the segments, named by their position, and the
+    /// named ranges kept in `offset_map`
+    pub fn offsets(&self) -> Result<OffsetMap, MauveError> {
+        let mut offsets = OffsetMap::default();
+        for (i, segment) in self.segments()?.into_iter().enumerate() {
+            offsets.0.insert(i.to_string(), segment);
+        }
+        if self.has_named_offsets() {
+            offsets.0.extend(self.offset_map.parse::<OffsetMap>()?.0);
+        }
+        Ok(offsets)
+    }
+
+    /// Set the segments and named ranges from the `x-mauve-offsets-inclusive` form, where
+    /// bare `start-end` entries are segments and `name=start-end` entries are named ranges
+    pub fn set_offsets(&mut self, s: &str) -> Result<(), MauveError> {
+        let mut segments = Vec::new();
+        let mut named = OffsetMap::default();
+        for entry in offset_entries(s) {
+            match entry.split_once('=') {
+                Some((name, _)) if name.trim().is_empty() => {
+                    return Err(MauveError::InvalidSegments(entry.to_string()));
+                }
+                Some((name, range)) => {
+                    named.0.insert(name.trim().to_string(), parse_range(range)?);
+                }
+                None => segments.push(parse_range(entry)?),
+            }
+        }
+        self.segments = segments;
+        self.offset_map = named.to_string();
+        Ok(())
+    }
+
+    /// Canonical `start-end,start-end` form of the segments, as sent in
+    /// `x-mauve-offsets-inclusive`
+    pub fn segments_str(&self) -> Result<String, MauveError> {
+        Ok(self
+            .segments()?
+            .iter()
+            .map(|(start, end)| format!("{start}-{end}"))
+            .collect::<Vec<_>>()
+            .join(","))
+    }
+
+    /// Canonical form of the segments followed by the named ranges, as sent in
+    /// `x-mauve-offsets-inclusive`. The inverse of `set_offsets`.
+    pub fn offsets_str(&self) -> Result<String, MauveError> {
+        let mut s = self.segments_str()?;
+        if self.has_named_offsets() {
+            let named = self.offset_map.parse::<OffsetMap>()?.to_string();
+            if !s.is_empty() {
+                s.push(',');
+            }
+            s.push_str(&named);
+        }
+        Ok(s)
+    }
+
+    fn has_named_offsets(&self) -> bool {
+        self.offset_map.contains('=')
+    }
+
+    /// Check that every segment and named range is a non-empty range within the object's
+    /// `size`
+    pub fn check_segments(&self) -> Result<(), MauveError> {
+        for (start, end) in self.offsets()?.0.into_values() {
+            if start > end || end >= self.size {
+                return Err(MauveError::InvalidSegments(format!(
+                    "{start}-{end} for {} bytes",
+                    self.size
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in a missing `content_type` from the object's first bytes, if the config asks for
+    /// it. See `sniff`.
+    pub fn sniff_content_type(&mut self, object: &[u8], config: &MauveConfig) {
+        if config.sniff_content_type
+            && self.content_type.is_empty()
+            && self.content_encoding.is_empty()
+        {
+            if let Some(content_type) = sniff::sniff(object) {
+                self.content_type = content_type.to_string();
+            }
+        }
+    }
+
+    /// Check `object` against the expected `content_hash`, if there is one
+    pub fn verify_content_hash(&self, object: &[u8]) -> Result<(), MauveError> {
+        if self.content_hash.is_empty() {
+            return Ok(());
+        }
+        let actual = etag(object);
+        if actual != self.content_hash {
+            return Err(MauveError::ContentHashMismatch(format!(
+                "expected md5:{} but received md5:{actual}",
+                self.content_hash
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lowercase the labels, unless the backend is `case_sensitive`. Done to every label as
+    /// it is written, before `check_limits`.
+    pub fn normalize(&mut self, config: &MauveConfig) {
+        if !config.case_sensitive {
+            self.labels = self
+                .labels
+                .drain()
+                .map(|label| Label::new(&label.name, &label.value))
+                .collect();
+        }
+    }
+
+    /// Check that every label can be indexed, and the label count and serialized size
+    /// against the configured limits.
+    ///
+    /// Every label fans out into both label indexes, so these are enforced before writing.
+    pub fn check_limits(&self, config: &MauveConfig) -> Result<(), MauveError> {
+        for label in &self.labels {
+            label.validate()?;
+        }
+        if self.labels.len() > config.max_labels_per_object {
+            return Err(MauveError::TooManyLabels(config.max_labels_per_object));
+        }
+        if self.to_object()?.len() > config.max_metadata_bytes {
+            return Err(MauveError::MetadataTooLarge(config.max_metadata_bytes));
+        }
+        Ok(())
+    }
+}
+
+/// Named `(start, end_inclusive)` byte ranges of an object, see `Metadata::offsets`
+///
+/// Written as `name=start-end` entries separated by commas or semicolons. A bare
+/// `start-end` entry is named by its position, so a plain segment list parses too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OffsetMap(pub BTreeMap<String, (u64, u64)>);
+
+impl FromStr for OffsetMap {
+    type Err = MauveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut offsets = BTreeMap::new();
+        for (i, entry) in offset_entries(s).enumerate() {
+            let (name, range) = match entry.split_once('=') {
+                Some((name, range)) => (name.trim().to_string(), range),
+                None => (i.to_string(), entry),
+            };
+            if name.is_empty() {
+                return Err(MauveError::InvalidSegments(entry.to_string()));
+            }
+            offsets.insert(name, parse_range(range)?);
+        }
+        Ok(OffsetMap(offsets))
+    }
+}
+
+impl fmt::Display for OffsetMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, (start, end))) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{name}={start}-{end}")?;
+        }
+        Ok(())
+    }
+}
+
+fn offset_entries(s: &str) -> impl Iterator<Item = &str> {
+    s.split([',', ';'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+}
+
+fn parse_range(s: &str) -> Result<(u64, u64), MauveError> {
+    s.split_once('-')
+        .and_then(|(start, end)| Some((start.trim().parse().ok()?, end.trim().parse().ok()?)))
+        .ok_or_else(|| MauveError::InvalidSegments(s.trim().to_string()))
+}
+
+/// Parse segments written as `start-end` pairs separated by commas
+pub fn parse_segments(s: &str) -> Result<Vec<(u64, u64)>, MauveError> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .map(parse_range)
+        .collect()
+}
+
+/// Parse a content hash written `<algorithm>:<hex digest>`, returning the lowercase digest.
+/// MD5 is the only supported algorithm, as it is also the object's ETag.
+pub fn parse_content_hash(s: &str) -> Result<String, MauveError> {
+    let invalid = || MauveError::InvalidContentHash(s.to_string());
+    let (algorithm, digest) = s.trim().split_once(':').ok_or_else(invalid)?;
+    if !algorithm.eq_ignore_ascii_case("md5") {
+        return Err(invalid());
+    }
+    match hex::decode(digest) {
+        Ok(bytes) if bytes.len() == 16 => Ok(hex::encode(bytes)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Entity tag for an object body: the hex MD5 digest of its bytes, as S3 uses
+pub fn etag(object: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    hex::encode(Md5::digest(object))
 }
 
 pub struct ObjectWithMetadata {
     pub(crate) object: Vec<u8>,
     pub(crate) meta: Metadata,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Metadata, OffsetMap};
+    use crate::objects::ToFromMauve;
+
+    #[test]
+    fn test_segments() -> anyhow::Result<()> {
+        let legacy = Metadata {
+            size: 10,
+            offset_map: "0-3, 4-9".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(legacy.segments()?, vec![(0, 3), (4, 9)]);
+        assert_eq!(legacy.segments_str()?, "0-3,4-9");
+        legacy.check_segments()?;
+
+        // Typed segments win over the legacy string
+        let typed = Metadata {
+            segments: vec![(0, 9)],
+            ..legacy.clone()
+        };
+        assert_eq!(typed.segments()?, vec![(0, 9)]);
+
+        let past_end = Metadata {
+            segments: vec![(5, 10)],
+            ..legacy
+        };
+        assert!(past_end.check_segments().is_err());
+        assert!(super::parse_segments("0-x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_offsets() -> anyhow::Result<()> {
+        let mut meta = Metadata {
+            size: 10,
+            ..Default::default()
+        };
+        meta.set_offsets("0-3, 4-9; header=0-1,body=2-9")?;
+        assert_eq!(meta.segments()?, vec![(0, 3), (4, 9)]);
+        assert_eq!(meta.offset_map, "body=2-9,header=0-1");
+        assert_eq!(meta.offsets_str()?, "0-3,4-9,body=2-9,header=0-1");
+        meta.check_segments()?;
+
+        let offsets = meta.offsets()?;
+        assert_eq!(offsets.0.get("1"), Some(&(4, 9)));
+        assert_eq!(offsets.0.get("header"), Some(&(0, 1)));
+
+        // The header form round-trips, with bare entries named by position
+        let parsed: OffsetMap = "0-3;body=2-9".parse()?;
+        assert_eq!(parsed.to_string(), "0=0-3,body=2-9");
+        assert_eq!(parsed.to_string().parse::<OffsetMap>()?, parsed);
+        assert!("=0-3".parse::<OffsetMap>().is_err());
+        assert!("body=2".parse::<OffsetMap>().is_err());
+
+        meta.set_offsets("tail=5-10")?;
+        assert!(meta.segments()?.is_empty());
+        assert!(meta.check_segments().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hash() -> anyhow::Result<()> {
+        let digest = super::etag(b"hello");
+        let parsed = super::parse_content_hash(&format!("MD5:{}", digest.to_uppercase()))?;
+        assert_eq!(parsed, digest);
+        assert!(super::parse_content_hash(&format!("sha256:{digest}")).is_err());
+        assert!(super::parse_content_hash("md5:abc").is_err());
+        assert!(super::parse_content_hash(&digest).is_err());
+
+        let meta = Metadata {
+            content_hash: parsed,
+            ..Default::default()
+        };
+        meta.verify_content_hash(b"hello")?;
+        assert!(meta.verify_content_hash(b"hellO").is_err());
+        Metadata::default().verify_content_hash(b"anything")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_extra_roundtrip() -> anyhow::Result<()> {
+        let mut meta = Metadata::default();
+        meta.extra.insert("owner".to_string(), "mauve".to_string());
+        meta.extra.insert("empty".to_string(), String::new());
+        let decoded = Metadata::from_object(meta.to_object()?)?;
+        assert_eq!(decoded.extra, meta.extra);
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+//! Mirror
+//!
+//! Best effort asynchronous replication to a warm standby. Every put and delete in a
+//! mirrored collection is appended to the `mauve_mirror_queue` tree from the same data tree
+//! change stream the indexer watches, and a sender task replays the queue in order against
+//! the remote's S3 API. The queue lives in sled, so writes made while the remote is down are
+//! sent once it comes back, even across restarts.
+//!
+//! Mirroring is eventually consistent. A queued put sends the object as it is when the entry
+//! is replayed, not as it was written, and nothing is read back from the remote.
+//!
+//! Only what the S3 API can carry is mirrored: the object, its content type, encoding and
+//! language, and its labels as tags. Segments and named offsets, `x-mauve-meta-<name>` fields
+//! and the stored content hash are not sent, and the standby computes its own hash.
+
+use std::time::Duration;
+
+use macros::MauveObject;
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sled::Event;
+use url::Url;
+
+use crate::{
+    backend::Backend,
+    config::MirrorConfig,
+    errors::{CollectionError, MauveError},
+    objects::ToFromMauve,
+};
+
+const QUEUE_TREE: &str = "mauve_mirror_queue";
+
+/// Delay before retrying a failed send, doubled on each failure up to `RETRY_MAX`
+const RETRY_MIN: Duration = Duration::from_millis(100);
+
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// A change waiting to be sent to the remote
+#[derive(Clone, Debug, Serialize, Deserialize, MauveObject)]
+struct MirrorOp {
+    collection: String,
+    key: String,
+    delete: bool,
+}
+
+/// Start queueing changes for the configured collections and sending them to the remote
+pub(crate) fn start(backend: Backend, config: MirrorConfig) -> Result<(), MauveError> {
+    let url = Url::parse(&config.url).map_err(|e| MauveError::ConfigError(e.to_string()))?;
+    if url.cannot_be_a_base() {
+        return Err(MauveError::ConfigError(format!(
+            "mirror url {url} cannot be a base"
+        )));
+    }
+    let queue = backend.get_db().open_tree(QUEUE_TREE)?;
+    let (notify, wake) = flume::unbounded::<()>();
+
+    for name in &config.collections {
+        let collection = backend.get_collection(name)?;
+        let mut events = collection.data_tree().watch_prefix(vec![]);
+        let db = backend.get_db().clone();
+        let queue = queue.clone();
+        let notify = notify.clone();
+        tokio::task::spawn(async move {
+            while let Some(event) = (&mut events).await {
+                let (key, delete) = match event {
+                    Event::Insert { key, value: _ } => (key, false),
+                    Event::Remove { key } => (key, true),
+                };
+                // A change that can't be queued is lost, but the ones after it still go out
+                if let Err(e) = enqueue(&db, &queue, &collection.name, &key, delete) {
+                    log::error!(collection = collection.name; "failed to queue mirror change {e}");
+                    continue;
+                }
+                let _ = notify.send(());
+            }
+        });
+    }
+
+    tokio::task::spawn(async move {
+        if let Err(e) = send_queue(backend, queue, url, wake).await {
+            log::error!("mirror sender exited with error {e}");
+        }
+    });
+
+    Ok(())
+}
+
+/// Append a change to the queue
+fn enqueue(
+    db: &sled::Db,
+    queue: &sled::Tree,
+    collection: &str,
+    key: &[u8],
+    delete: bool,
+) -> Result<(), MauveError> {
+    let op = MirrorOp {
+        collection: collection.to_string(),
+        key: String::from_utf8(key.to_vec())?,
+        delete,
+    };
+    // Ids are monotonic, so big endian keys keep the queue in write order
+    queue.insert(db.generate_id()?.to_be_bytes(), op.to_object()?)?;
+    Ok(())
+}
+
+/// Send queued changes to the remote in order
+///
+/// Transport errors and `5xx` answers may go away, so the change is retried until the remote
+/// accepts it. Any other answer would be given again, as would a local error like an object
+/// whose metadata can't be decoded, so the change is logged and dropped rather than holding
+/// up the rest of the queue.
+async fn send_queue(
+    backend: Backend,
+    queue: sled::Tree,
+    url: Url,
+    wake: flume::Receiver<()>,
+) -> Result<(), MauveError> {
+    let client = reqwest::Client::new();
+    let mut backoff = RETRY_MIN;
+    loop {
+        let (id, op) = match queue.first()? {
+            Some((id, op)) => (id, op),
+            None => {
+                let _ = wake.recv_async().await;
+                wake.drain();
+                continue;
+            }
+        };
+        let op = match MirrorOp::from_object(op.to_vec()) {
+            Ok(op) => op,
+            Err(e) => {
+                log::error!("dropping undecodable mirror change {e}");
+                queue.remove(id)?;
+                continue;
+            }
+        };
+        match send(&backend, &client, &url, &op).await {
+            Ok(status) => {
+                if !status.is_success() {
+                    log::error!(collection = op.collection, key = op.key; "mirror refused change with {status}, dropping it");
+                }
+                queue.remove(id)?;
+                backoff = RETRY_MIN;
+            }
+            Err(e @ MauveError::HttpError(_)) => {
+                log::warn!(collection = op.collection, key = op.key; "mirror send failed, retrying in {backoff:?}: {e}");
+                let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+                tokio::time::sleep(backoff + Duration::from_millis(jitter)).await;
+                backoff = (backoff * 2).min(RETRY_MAX);
+            }
+            Err(e) => {
+                log::error!(collection = op.collection, key = op.key; "failed to read mirror change, dropping it: {e}");
+                queue.remove(id)?;
+            }
+        }
+    }
+}
+
+/// Replay one queued change against the remote, returning its answer unless that was a
+/// `5xx` worth retrying. Transport errors and `5xx` answers are `HttpError`s, and any other
+/// error is local.
+async fn send(
+    backend: &Backend,
+    client: &reqwest::Client,
+    base: &Url,
+    op: &MirrorOp,
+) -> Result<StatusCode, MauveError> {
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .map_err(|_| MauveError::ConfigError(format!("mirror url {base} cannot be a base")))?
+        .pop_if_empty()
+        .extend(["s3", &op.collection])
+        .extend(op.key.split('/'));
+
+    let request = match op.delete {
+        true => client.delete(url),
+        false => {
+            let collection = backend.get_collection(&op.collection)?;
+            let object = match collection.get_object(&op.key) {
+                Ok(object) => object,
+                // Deleted since it was queued, and the delete is queued behind this
+                Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+                    return Ok(StatusCode::OK)
+                }
+                Err(e) => return Err(e),
+            };
+            let meta = match collection.get_object_metadata(&op.key) {
+                Ok(meta) => meta,
+                Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+                    Default::default()
+                }
+                Err(e) => return Err(e),
+            };
+
+            let mut request = client.put(url).body(object);
+            for (header, value) in [
+                ("content-type", &meta.content_type),
+                ("content-encoding", &meta.content_encoding),
+                ("content-language", &meta.content_language),
+            ] {
+                if !value.is_empty() {
+                    request = request.header(header, value);
+                }
+            }
+            if !meta.labels.is_empty() {
+                let tagging = url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(meta.labels.iter().map(|l| (&l.name, &l.value)))
+                    .finish();
+                request = request.header("x-amz-tagging", tagging);
+            }
+            request
+        }
+    };
+
+    let status = request.send().await?.status();
+    if status.is_server_error() {
+        return Err(MauveError::HttpError(format!("mirror answered {status}")));
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::fairing::AdHoc;
+
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::Backend,
+        config::MirrorConfig,
+        labels::Label,
+        meta::Metadata,
+    };
+
+    #[rocket::async_test]
+    async fn test_mirror_put_and_delete() -> anyhow::Result<()> {
+        let mut remote_config = test_config();
+        remote_config.mauve.s3_enabled = true;
+        remote_config.mauve.max_key_len = 16;
+        let remote = Backend::open(remote_config.clone())?;
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let rocket = mauve_rocket(remote.clone(), remote_config.clone())
+            .configure(rocket::Config {
+                port: 0,
+                ..rocket::Config::debug_default()
+            })
+            .attach(AdHoc::on_liftoff("port", |rocket| {
+                Box::pin(async move {
+                    let _ = tx.send(rocket.config().port);
+                })
+            }));
+        let shutdown = rocket.ignite().await?;
+        let handle = shutdown.shutdown();
+        tokio::spawn(shutdown.launch());
+        let port = rx.await?;
+
+        let mut config = test_config();
+        config.mauve.mirror = Some(MirrorConfig {
+            url: format!("http://127.0.0.1:{port}"),
+            collections: vec!["mirrored".to_string()],
+        });
+        let backend = Backend::open(config.clone())?;
+        let local = backend.get_collection("mirrored")?;
+        // The remote refuses this key, which mustn't hold up the changes queued after it
+        let long = "k".repeat(17);
+        local.put_object(&long, b"refused".to_vec(), false)?;
+        // Nor must a change that can't be read locally
+        local.meta.insert("bad", b"\xff".as_slice())?;
+        local.put_object("bad", b"corrupt".to_vec(), false)?;
+        let mut meta = Metadata {
+            content_type: "text/plain".to_string(),
+            ..Default::default()
+        };
+        meta.labels.insert(Label::new("color", "mauve"));
+        local.put_object_metadata("a/b", meta)?;
+        local.put_object("a/b", b"hello".to_vec(), false)?;
+
+        let mirrored = remote.get_collection("mirrored")?;
+        let mut found = false;
+        for _ in 0..100 {
+            if mirrored.head_object("a/b")? {
+                found = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(found);
+        assert_eq!(mirrored.get_object("a/b")?, b"hello");
+        let meta = mirrored.get_object_metadata("a/b")?;
+        assert_eq!(meta.content_type, "text/plain");
+        assert!(meta.labels.contains(&Label::new("color", "mauve")));
+        assert!(!mirrored.head_object(&long)?);
+        assert!(!mirrored.head_object("bad")?);
+
+        local.delete_object("a/b")?;
+        for _ in 0..100 {
+            if !mirrored.head_object("a/b")? {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!mirrored.head_object("a/b")?);
+
+        handle.notify();
+        std::fs::remove_dir_all(config.sled.path)?;
+        std::fs::remove_dir_all(remote_config.sled.path)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,7 @@
 use macros::MauveObject;
 use serde::{Deserialize, Serialize};
 use sled::IVec;
-use std::{
-    fmt::Display,
-    ops::{Deref, DerefMut},
-};
+use std::{fmt::Display, ops::Deref};
 
 use crate::errors::MauveError;
 
@@ -21,6 +18,14 @@ impl ObjectRef {
             name: name.to_ascii_lowercase(),
         }
     }
+
+    /// A ref keeping the case of `collection` and `name`, for `case_sensitive` backends
+    pub fn exact(collection: &str, name: &str) -> Self {
+        Self {
+            collection: collection.to_string(),
+            name: name.to_string(),
+        }
+    }
 }
 
 impl Display for ObjectRef {
@@ -39,13 +44,87 @@ impl TryFrom<(IVec, IVec)> for ObjectRef {
     }
 }
 
+/// The objects under one label in the label index
+///
+/// This is a set: refs are kept sorted with no duplicates, which `new`, `from_index`,
+/// `insert` and `remove` all maintain.
 #[derive(Clone, Debug, Serialize, Deserialize, MauveObject)]
 pub struct ObjectRefs(Vec<ObjectRef>);
 
+/// Format byte for index values stored as plain CBOR
+const INDEX_FORMAT_CBOR: u8 = 0x00;
+
+/// Format byte for index values stored as zstd compressed CBOR
+const INDEX_FORMAT_ZSTD: u8 = 0x01;
+
 impl ObjectRefs {
-    pub fn new(inner: Vec<ObjectRef>) -> Self {
+    /// Build a set from `inner`, dropping any duplicates
+    pub fn new(mut inner: Vec<ObjectRef>) -> Self {
+        inner.sort();
+        inner.dedup();
         Self(inner)
     }
+
+    /// Returns `true` if `or` is in the set
+    pub fn contains(&self, or: &ObjectRef) -> bool {
+        self.0.binary_search(or).is_ok()
+    }
+
+    /// Add `or`, returning `false` if it was already there
+    pub fn insert(&mut self, or: ObjectRef) -> bool {
+        match self.0.binary_search(&or) {
+            Ok(_) => false,
+            Err(at) => {
+                self.0.insert(at, or);
+                true
+            }
+        }
+    }
+
+    /// Remove `or`, returning `false` if it wasn't there
+    pub fn remove(&mut self, or: &ObjectRef) -> bool {
+        match self.0.binary_search(or) {
+            Ok(at) => {
+                self.0.remove(at);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Serialize for storage as a label index value.
+    ///
+    /// The value is prefixed with a format byte so compressed and uncompressed values can
+    /// coexist in the same index tree.
+    pub fn to_index(&self, compress: bool) -> Result<Vec<u8>, MauveError> {
+        let bytes = self.to_object()?;
+        let mut value = Vec::with_capacity(bytes.len() + 1);
+        match compress {
+            true => {
+                value.push(INDEX_FORMAT_ZSTD);
+                value.extend(zstd::encode_all(&*bytes, 0)?);
+            }
+            false => {
+                value.push(INDEX_FORMAT_CBOR);
+                value.extend(bytes);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Deserialize a label index value written by `to_index`.
+    ///
+    /// Values written before the format byte existed are bare CBOR arrays, whose first
+    /// byte can never be one of the format bytes, and are read as-is. Older values may also
+    /// be unsorted or hold duplicates, so the refs are made a set again.
+    pub fn from_index(value: &[u8]) -> Result<Self, MauveError> {
+        let refs = match value.split_first() {
+            Some((&INDEX_FORMAT_CBOR, bytes)) => Self::from_object(bytes.to_vec())?,
+            Some((&INDEX_FORMAT_ZSTD, bytes)) => Self::from_object(zstd::decode_all(bytes)?)?,
+            _ => Self::from_object(value.to_vec())?,
+        };
+        Ok(Self::new(refs.0))
+    }
 }
 
 impl IntoIterator for ObjectRefs {
@@ -58,12 +137,6 @@ impl IntoIterator for ObjectRefs {
     }
 }
 
-impl DerefMut for ObjectRefs {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
-    }
-}
-
 impl Deref for ObjectRefs {
     type Target = Vec<ObjectRef>;
     fn deref(&self) -> &Self::Target {
@@ -78,11 +151,11 @@ pub trait ToFromMauve: Serialize + for<'de> Deserialize<'de> {
 
 #[cfg(test)]
 mod tests {
-    use super::ToFromMauve;
+    use super::{ObjectRef, ObjectRefs, ToFromMauve};
     use crate::errors::MauveError;
     use macros::MauveObject;
     use rand::{thread_rng, Rng, RngCore};
-    use serde::{Deserialize, Serialize};
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
     #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, MauveObject)]
     struct TestObject {
@@ -116,4 +189,212 @@ mod tests {
         }
         Ok(())
     }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[serde(bound(deserialize = "T: DeserializeOwned"))]
+    struct Wrapper<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        items: Vec<T>,
+        label: String,
+    }
+
+    #[test]
+    fn test_generic_mauve_object() -> anyhow::Result<()> {
+        let wrapper = Wrapper {
+            items: vec![TestObject::rand(), TestObject::rand()],
+            label: "pair".to_string(),
+        };
+        let got = Wrapper::<TestObject>::from_object(wrapper.to_object()?)?;
+        assert_eq!(got, wrapper);
+
+        let numbers = Wrapper {
+            items: vec![1u32, 2, 3],
+            label: "numbers".to_string(),
+        };
+        assert_eq!(Wrapper::from_object(numbers.to_object()?)?, numbers);
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[mauve(format = "cbor")]
+    struct CborObject {
+        s: String,
+        x: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[mauve(format = "json")]
+    struct JsonObject {
+        s: String,
+        x: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[mauve(format = "bincode")]
+    struct BincodeObject {
+        s: String,
+        x: u32,
+    }
+
+    #[test]
+    fn test_cbor_format() -> anyhow::Result<()> {
+        let object = CborObject {
+            s: "mauve".to_string(),
+            x: 6,
+        };
+        let bytes = object.to_object()?;
+        let mut cbor = vec![];
+        ciborium::into_writer(&object, &mut cbor)?;
+        assert_eq!(bytes, cbor);
+        assert_eq!(CborObject::from_object(bytes)?, object);
+        assert!(matches!(
+            CborObject::from_object(b"{".to_vec()),
+            Err(MauveError::CborError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_format() -> anyhow::Result<()> {
+        let object = JsonObject {
+            s: "mauve".to_string(),
+            x: 6,
+        };
+        let bytes = object.to_object()?;
+        assert_eq!(bytes, br#"{"s":"mauve","x":6}"#);
+        assert_eq!(JsonObject::from_object(bytes)?, object);
+        assert!(matches!(
+            JsonObject::from_object(b"{".to_vec()),
+            Err(MauveError::JsonError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bincode_format() -> anyhow::Result<()> {
+        let object = BincodeObject {
+            s: "mauve".to_string(),
+            x: 6,
+        };
+        let bytes = object.to_object()?;
+        // A u64 string length, the string, then the u32
+        assert_eq!(
+            bytes,
+            [&5u64.to_le_bytes()[..], b"mauve", &6u32.to_le_bytes()].concat()
+        );
+        assert_eq!(BincodeObject::from_object(bytes)?, object);
+        assert!(matches!(
+            BincodeObject::from_object(b"{".to_vec()),
+            Err(MauveError::BincodeError(_))
+        ));
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    struct Pair(String, u32);
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    struct Marker;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    enum Color {
+        Mauve,
+        Lilac,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[non_exhaustive]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+        Named(String, Box<Shape>),
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, MauveObject)]
+    #[serde(bound(deserialize = "T: DeserializeOwned"))]
+    #[mauve(format = "json")]
+    enum Maybe<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        Just(T),
+        Nothing,
+    }
+
+    fn roundtrip<T: ToFromMauve + PartialEq + std::fmt::Debug>(value: T) -> anyhow::Result<()> {
+        assert_eq!(T::from_object(value.to_object()?)?, value);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mauve_object_shapes() -> anyhow::Result<()> {
+        roundtrip(Pair("mauve".to_string(), 6))?;
+        roundtrip(Marker)?;
+        roundtrip(Color::Mauve)?;
+        roundtrip(Color::Lilac)?;
+        roundtrip(Shape::Point)?;
+        roundtrip(Shape::Circle(1.5))?;
+        roundtrip(Shape::Rect { w: 2, h: 3 })?;
+        roundtrip(Shape::Named(
+            "ring".to_string(),
+            Box::new(Shape::Circle(2.0)),
+        ))?;
+        roundtrip(Maybe::Just(Color::Lilac))?;
+        roundtrip(Maybe::<u32>::Nothing)?;
+
+        // A variant the type doesn't have is an error, not a panic
+        assert!(matches!(
+            Color::from_object(Shape::Point.to_object()?),
+            Err(MauveError::CborError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_compression() -> anyhow::Result<()> {
+        let refs = ObjectRefs::new(
+            (0..1000)
+                .map(|n| ObjectRef::new("camera-uploads", &format!("2024/06/01/img-{n:06}.jpg")))
+                .collect(),
+        );
+
+        let plain = refs.to_index(false)?;
+        let compressed = refs.to_index(true)?;
+        assert!(
+            compressed.len() * 4 < plain.len(),
+            "plain {} bytes, compressed {} bytes",
+            plain.len(),
+            compressed.len()
+        );
+
+        for value in [plain, compressed, refs.to_object()?] {
+            assert_eq!(*ObjectRefs::from_index(&value)?, *refs);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_refs_set() -> anyhow::Result<()> {
+        let a = ObjectRef::new("c", "a");
+        let b = ObjectRef::new("c", "b");
+        let mut refs = ObjectRefs::new(vec![b.clone(), a.clone(), b.clone()]);
+        assert_eq!(*refs, [a.clone(), b.clone()]);
+
+        let mut once = ObjectRefs::new(vec![]);
+        assert!(once.insert(a.clone()));
+        assert!(!once.insert(a.clone()));
+        assert_eq!(once.len(), 1);
+
+        assert!(refs.remove(&a));
+        assert!(!refs.remove(&a));
+        assert!(!refs.contains(&a) && refs.contains(&b));
+
+        // Duplicates in stored values are dropped when read
+        let legacy = super::ObjectRefs(vec![b.clone(), b.clone()]).to_object()?;
+        assert_eq!(*ObjectRefs::from_index(&legacy)?, [b]);
+        Ok(())
+    }
 }
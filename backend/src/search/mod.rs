@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 pub mod search;
 
+use std::fmt::Display;
+
 use crate::{labels::Label, meta::Metadata, objects::ObjectRef};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -8,31 +11,198 @@ use thiserror::Error;
 pub enum SearchError {
     #[error("Search has not been executed")]
     NotYetExecuted,
+
+    /// The index lookup of one label failed or panicked
+    #[error("Search of {label} failed: {error}")]
+    LookupFailed { label: String, error: String },
+
+    /// The search was cancelled before its lookups finished, so its results are incomplete
+    #[error("Search was cancelled")]
+    Cancelled,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SearchLabel {
+    /// Include objects carrying the label. A label with an empty value matches every value
+    /// of its name, and one with an empty name matches its value under any name.
     Include(Label),
+    /// Exclude objects carrying the label, matched the same way as `Include`
     Exclude(Label),
+    /// Include objects whose label `name` is a number from `min` to `max`, inclusive.
+    /// Only labels configured under `numeric_labels` can be searched this way.
+    NumericRange { name: String, min: f64, max: f64 },
+    /// Objects matching every one of the labels. An empty group matches every object.
+    And(Vec<SearchLabel>),
+    /// Objects matching any of the labels. An empty group matches nothing.
+    Or(Vec<SearchLabel>),
+    /// Objects not matching the label
+    Not(Box<SearchLabel>),
+}
+
+impl Display for SearchLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let group = |f: &mut std::fmt::Formatter<'_>, labels: &[SearchLabel], op| {
+            let labels: Vec<_> = labels.iter().map(ToString::to_string).collect();
+            write!(f, "({})", labels.join(op))
+        };
+        match self {
+            SearchLabel::Include(label) => write!(f, "{label}"),
+            SearchLabel::Exclude(label) => write!(f, "NOT {label}"),
+            SearchLabel::NumericRange { name, min, max } => write!(f, "{name} in {min}..={max}"),
+            SearchLabel::And(labels) => group(f, labels, " AND "),
+            SearchLabel::Or(labels) => group(f, labels, " OR "),
+            SearchLabel::Not(label) => write!(f, "NOT {label}"),
+        }
+    }
+}
+
+impl SearchLabel {
+    /// Lowercase every label in the expression
+    fn fold_case(&mut self) {
+        match self {
+            SearchLabel::Include(label) | SearchLabel::Exclude(label) => {
+                *label = Label::new(&label.name, &label.value)
+            }
+            SearchLabel::NumericRange { name, .. } => *name = name.to_ascii_lowercase(),
+            SearchLabel::And(labels) | SearchLabel::Or(labels) => {
+                labels.iter_mut().for_each(SearchLabel::fold_case)
+            }
+            SearchLabel::Not(label) => label.fold_case(),
+        }
+    }
+}
+
+/// How the include labels of a search combine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchMode {
+    /// Objects matching every include label, whether or not the label names differ
+    #[default]
+    All,
+    /// Objects matching any include label
+    Any,
+}
+
+/// The collections a search covers, given in JSON as one name or a list of names
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SearchCollections {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SearchCollections {
+    /// The collection names, without repeats
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            SearchCollections::One(name) => vec![name],
+            SearchCollections::Many(names) => {
+                let mut unique: Vec<&str> = vec![];
+                for name in names {
+                    if !unique.contains(&name.as_str()) {
+                        unique.push(name);
+                    }
+                }
+                unique
+            }
+        }
+    }
+}
+
+impl Display for SearchCollections {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.names().join(","))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SearchRequest {
-    /// Name of the collection to search
-    pub(crate) collection: String,
+    /// Collections to search. Their results are merged, each `ObjectRef` naming the
+    /// collection it was found in.
+    pub(crate) collection: SearchCollections,
 
-    /// Labels to apply to the search
+    /// Labels to apply to the search. `And`, `Or` and `Not` groups nest to any depth, and
+    /// combine with the other labels like an include label.
     pub(crate) labels: Vec<SearchLabel>,
+
+    /// Only count the matching objects, leaving the result empty. See `SearchStats::matched`.
+    #[serde(default)]
+    pub(crate) count_only: bool,
+
+    /// How the include labels and groups combine. Exclude labels always remove their
+    /// objects.
+    #[serde(default)]
+    pub(crate) mode: SearchMode,
+
+    /// Most objects to return, capped at `max_search_results`, which is also the default.
+    /// Results are ordered by collection and name, so pages follow on from each other.
+    /// The streaming search returns every match and ignores this and `offset`.
+    #[serde(default)]
+    pub(crate) limit: Option<usize>,
+
+    /// Matching objects to skip before the page returned
+    #[serde(default)]
+    pub(crate) offset: usize,
+
+    /// Return only the refs of the matching objects, without reading their metadata
+    #[serde(default)]
+    pub(crate) names_only: bool,
 }
 
 impl SearchRequest {
     pub fn new(c: &str) -> Self {
         Self {
-            collection: c.to_string(),
+            collection: SearchCollections::One(c.to_string()),
             labels: vec![],
+            count_only: false,
+            mode: SearchMode::All,
+            limit: None,
+            offset: 0,
+            names_only: false,
+        }
+    }
+
+    /// Search every one of `collections`
+    pub fn across(collections: &[&str]) -> Self {
+        Self {
+            collection: SearchCollections::Many(
+                collections.iter().map(|c| c.to_string()).collect(),
+            ),
+            ..Self::new("")
         }
     }
 
+    pub fn count_only(&mut self, count_only: bool) {
+        self.count_only = count_only
+    }
+
+    pub fn mode(&mut self, mode: SearchMode) {
+        self.mode = mode
+    }
+
+    pub fn limit(&mut self, limit: usize) {
+        self.limit = Some(limit)
+    }
+
+    pub fn offset(&mut self, offset: usize) {
+        self.offset = offset
+    }
+
+    pub fn names_only(&mut self, names_only: bool) {
+        self.names_only = names_only
+    }
+
+    /// Lowercase the labels searched for, to match the labels of a backend that isn't
+    /// `case_sensitive`
+    pub(crate) fn fold_case(&mut self) {
+        self.labels.iter_mut().for_each(SearchLabel::fold_case)
+    }
+
+    /// Add a boolean expression, e.g. `(a=1 OR a=2) AND NOT b=3` as
+    /// `And(vec![Or(vec![Include(a=1), Include(a=2)]), Not(Box::new(Include(b=3)))])`
+    pub fn expression(&mut self, label: SearchLabel) {
+        self.labels.push(label)
+    }
+
     pub fn include(&mut self, label: Label) {
         self.labels.push(SearchLabel::Include(label))
     }
@@ -41,6 +211,14 @@ impl SearchRequest {
         self.labels.push(SearchLabel::Exclude(label))
     }
 
+    pub fn numeric_range(&mut self, name: &str, min: f64, max: f64) {
+        self.labels.push(SearchLabel::NumericRange {
+            name: name.to_ascii_lowercase(),
+            min,
+            max,
+        })
+    }
+
     pub fn includes(&mut self, labels: impl IntoIterator<Item = Label>) {
         for label in labels.into_iter() {
             self.include(label);
@@ -66,13 +244,71 @@ impl FoundObject {
     }
 }
 
+/// The objects a search returns, with their metadata or, for a `names_only` search, as
+/// refs alone
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SearchResults {
+    Objects(Vec<FoundObject>),
+    Names(Vec<ObjectRef>),
+}
+
+impl Default for SearchResults {
+    fn default() -> Self {
+        Self::Objects(vec![])
+    }
+}
+
+impl SearchResults {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Objects(objects) => objects.len(),
+            Self::Names(refs) => refs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The refs of the objects returned, however they were returned
+    pub fn refs(&self) -> Vec<&ObjectRef> {
+        match self {
+            Self::Objects(objects) => objects.iter().map(|found| &found.object).collect(),
+            Self::Names(refs) => refs.iter().collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SearchResponse {
     /// The search request
     pub req: SearchRequest,
 
-    /// The result of the search
-    pub result: Result<Vec<FoundObject>, SearchError>,
+    /// The result of the search, one page of it if there are more matches than the limit
+    pub result: Result<SearchResults, SearchError>,
+
+    /// Objects matching the search across every page
+    #[serde(default)]
+    pub total: usize,
+
+    /// How the search went, once it has been executed
+    #[serde(default)]
+    pub stats: Option<SearchStats>,
+}
+
+/// Timing and index usage of an executed search
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SearchStats {
+    /// Wall time of the whole search, including fetching metadata
+    pub took_ms: u64,
+    /// Labels whose index entries were read
+    pub labels_scanned: usize,
+    /// Object refs read from the index across all labels
+    pub candidates_examined: usize,
+    /// Included objects dropped by an exclude label
+    pub excluded: usize,
+    /// Objects matching the search
+    pub matched: usize,
 }
 
 impl SearchResponse {
@@ -80,11 +316,17 @@ impl SearchResponse {
         Self {
             req,
             result: Err(SearchError::NotYetExecuted),
+            total: 0,
+            stats: None,
         }
     }
 
     pub fn set_ok(&mut self, objects: impl IntoIterator<Item = FoundObject>) {
-        self.result = Ok(objects.into_iter().collect())
+        self.result = Ok(SearchResults::Objects(objects.into_iter().collect()))
+    }
+
+    pub fn set_names(&mut self, refs: impl IntoIterator<Item = ObjectRef>) {
+        self.result = Ok(SearchResults::Names(refs.into_iter().collect()))
     }
 
     pub fn set_err(&mut self, e: SearchError) {
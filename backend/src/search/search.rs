@@ -1,79 +1,1062 @@
-use std::{collections::HashSet, sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Instant};
 
 use dashmap::DashSet;
+use tokio::{sync::Semaphore, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 
 use super::*;
 use crate::{
     backend::Backend,
     collection::Collection,
-    errors::MauveError,
-    objects::{ObjectRefs, ToFromMauve},
+    errors::{CollectionError, MauveError},
+    labels::numeric_prefix,
+    objects::ObjectRefs,
 };
 
 impl Backend {
     /// Perform a search against the backend
+    ///
+    /// The label lookups run as spawned tasks. They are cancelled if this future is dropped
+    /// before completing, e.g. when the HTTP client disconnects mid-search.
     pub async fn perform_search(&self, req: SearchRequest) -> Result<SearchResponse, MauveError> {
-        let collection = self.get_collection(&req.collection)?;
-
-        let includes = Arc::new(DashSet::new());
-        let excludes = Arc::new(DashSet::new());
-
-        for label in req.clone().labels {
-            let collection = collection.clone();
-            let (inc, exc) = (includes.clone(), excludes.clone());
-            tokio::task::spawn(async move {
-                let res = match &label {
-                    SearchLabel::Include(inner) => collection.search_label(inner.clone(), inc),
-                    SearchLabel::Exclude(inner) => collection.search_label(inner.clone(), exc),
-                }
-                .await;
-                match res {
-                    Ok(n) => log::debug!("query found {n} objects"),
-                    Err(e) => log::error!("query error {e}"),
-                }
-            });
+        let cancel = CancellationToken::new();
+        let _guard = cancel.clone().drop_guard();
+        self.perform_search_cancellable(req, cancel).await
+    }
+
+    /// Perform a search against the backend, stopping the label lookups once `cancel` fires
+    pub async fn perform_search_cancellable(
+        &self,
+        req: SearchRequest,
+        cancel: CancellationToken,
+    ) -> Result<SearchResponse, MauveError> {
+        let started = Instant::now();
+        let found = self.find_all_matches(&req, cancel).await;
+
+        let count_only = req.count_only;
+        let mut response = SearchResponse::new(req);
+        let (results, mut stats) = match found {
+            Ok(found) => found,
+            Err(MauveError::SearchError(e)) => {
+                response.set_err(e);
+                return Ok(response);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Only the page returned has its metadata fetched, which is the expensive part of a
+        // big result. Counting needs none of it, and names only searches skip it.
+        let limit = match count_only {
+            true => 0,
+            false => response
+                .req
+                .limit
+                .unwrap_or(usize::MAX)
+                .min(self.config().max_search_results),
+        };
+        let mut page = vec![];
+        if limit > 0 {
+            page = results
+                .iter()
+                .flat_map(|(collection, objects)| objects.iter().map(move |o| (o, collection)))
+                .collect();
+            page.sort_unstable_by_key(|(object, _)| *object);
+            page = page
+                .into_iter()
+                .skip(response.req.offset)
+                .take(limit)
+                .collect();
+        }
+        // An object deleted since its labels were looked up is left out rather than failing
+        // the search, and no longer counts towards the total
+        let mut vanished = 0;
+        if response.req.names_only {
+            response.set_names(page.into_iter().map(|(object, _)| object.clone()));
+        } else {
+            let mut response_items = vec![];
+            for (object, collection) in page {
+                let meta = match collection.get_object_metadata(&object.name) {
+                    Ok(meta) => meta,
+                    Err(MauveError::CollectionError(CollectionError::ObjectNotFound)) => {
+                        vanished += 1;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                response_items.push(FoundObject::new(object.clone(), meta));
+            }
+            response.set_ok(response_items);
         }
+        response.total = stats.matched - vanished;
 
-        while Arc::strong_count(&includes) > 1 && Arc::strong_count(&excludes) > 1 {
-            tokio::time::sleep(Duration::from_millis(200)).await
+        stats.took_ms = started.elapsed().as_millis() as u64;
+        response.stats = Some(stats);
+        Ok(response)
+    }
+
+    /// Find the objects matching a search without fetching their metadata, so the caller can
+    /// fetch it as each result is used. The objects are grouped by the collection they were
+    /// found in.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %req.collection)))]
+    pub async fn search_matches(
+        &self,
+        req: &SearchRequest,
+    ) -> Result<Vec<(Collection, Vec<ObjectRef>)>, MauveError> {
+        let cancel = CancellationToken::new();
+        let _guard = cancel.clone().drop_guard();
+        let (results, _) = self.find_all_matches(req, cancel).await?;
+        Ok(results
+            .into_iter()
+            .map(|(collection, objects)| (collection, objects.into_iter().collect()))
+            .collect())
+    }
+
+    /// Run a search against each of its collections at once, with the label lookups of all
+    /// of them sharing `query_concurrency`. The stats are summed across the collections.
+    ///
+    /// Labels are indexed lowercased unless the backend is `case_sensitive`, so the query's
+    /// labels are folded to match here, where every kind of search goes through.
+    async fn find_all_matches(
+        &self,
+        req: &SearchRequest,
+        cancel: CancellationToken,
+    ) -> Result<(Vec<(Collection, HashSet<ObjectRef>)>, SearchStats), MauveError> {
+        let folded;
+        let req = match self.config().case_sensitive {
+            true => req,
+            false => {
+                let mut req = req.clone();
+                req.fold_case();
+                folded = req;
+                &folded
+            }
+        };
+        let collections = req
+            .collection
+            .names()
+            .into_iter()
+            .map(|name| self.find_collection(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let limit = Arc::new(Semaphore::new(self.config().query_concurrency.max(1)));
+        let found =
+            futures::future::join_all(collections.iter().map(|collection| {
+                self.find_matches(collection, req, limit.clone(), cancel.clone())
+            }))
+            .await;
+
+        let mut results = vec![];
+        let mut stats = SearchStats::default();
+        for (collection, found) in collections.into_iter().zip(found) {
+            let (objects, found) = found?;
+            stats.labels_scanned += found.labels_scanned;
+            stats.candidates_examined += found.candidates_examined;
+            stats.excluded += found.excluded;
+            stats.matched += found.matched;
+            results.push((collection, objects));
         }
+        Ok((results, stats))
+    }
 
-        let mut results = HashSet::new();
-        for item in includes.iter() {
-            results.insert(item.clone());
+    /// Run the label lookups of a search in one collection. The stats are complete apart
+    /// from `took_ms`.
+    ///
+    /// The first lookup to fail fails the search with `SearchError::LookupFailed`, and a
+    /// search cancelled before its lookups finished fails with `SearchError::Cancelled`
+    /// rather than returning whatever they had found.
+    async fn find_matches(
+        &self,
+        collection: &Collection,
+        req: &SearchRequest,
+        limit: Arc<Semaphore>,
+        cancel: CancellationToken,
+    ) -> Result<(HashSet<ObjectRef>, SearchStats), MauveError> {
+        let mut lookups = Lookups {
+            collection: collection.clone(),
+            cancel,
+            limit,
+            sets: vec![],
+            handles: vec![],
+        };
+        for label in &req.labels {
+            lookups.spawn(label);
         }
+
+        let (mut scanned, mut examined) = (0, 0);
+        let (labels, handles): (Vec<_>, Vec<_>) = lookups.handles.into_iter().unzip();
+        for (label, res) in labels
+            .into_iter()
+            .zip(futures::future::join_all(handles).await)
+        {
+            let error = match res {
+                Ok(Ok(Some(n))) => {
+                    log::debug!("query found {n} objects");
+                    scanned += 1;
+                    examined += n;
+                    continue;
+                }
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            log::error!("query error {error}");
+            return Err(SearchError::LookupFailed {
+                label: label.to_string(),
+                error,
+            }
+            .into());
+        }
+        // A lookup stopped by the token may also have returned early with what it had
+        if lookups.cancel.is_cancelled() {
+            return Err(SearchError::Cancelled.into());
+        }
+
+        // The sets are read back in the order their lookups were spawned
+        let mut sets = lookups.sets.into_iter();
+        let mut terms = vec![];
+        let mut excludes = HashSet::new();
+        for label in &req.labels {
+            match label {
+                SearchLabel::Exclude(_) => excludes.extend(Matches::take(&mut sets)),
+                label => terms.push(Matches::eval(label, &mut sets)),
+            }
+        }
+        let matches = match req.mode {
+            SearchMode::Any => terms
+                .into_iter()
+                .fold(Matches::Only(HashSet::new()), Matches::or),
+            SearchMode::All => terms
+                .into_iter()
+                .reduce(Matches::and)
+                .unwrap_or(Matches::Only(HashSet::new())),
+        };
+        let mut results = matches.resolve(collection)?;
+        let candidates = results.len();
         results.retain(|item| !excludes.contains(item));
 
-        let mut response = SearchResponse::new(req);
+        let stats = SearchStats {
+            took_ms: 0,
+            labels_scanned: scanned,
+            candidates_examined: examined,
+            excluded: candidates - results.len(),
+            matched: results.len(),
+        };
+        Ok((results, stats))
+    }
+}
 
-        let mut response_items = vec![];
-        for object in results {
-            let meta = collection.get_object_metadata(&object.name)?;
-            response_items.push(FoundObject::new(object, meta));
+/// A label lookup's task, which finishes with the number of objects it found, or `None` if
+/// it was cancelled
+type Lookup = JoinHandle<Result<Option<usize>, MauveError>>;
+
+/// The label lookups of one search, each filling its own set
+struct Lookups {
+    collection: Collection,
+    cancel: CancellationToken,
+    /// Bounds the lookups running at once to `query_concurrency`
+    limit: Arc<Semaphore>,
+    sets: Vec<Arc<DashSet<ObjectRef>>>,
+    handles: Vec<(SearchLabel, Lookup)>,
+}
+
+impl Lookups {
+    /// Start a lookup for every label in `label`, depth first
+    fn spawn(&mut self, label: &SearchLabel) {
+        match label {
+            SearchLabel::And(labels) | SearchLabel::Or(labels) => {
+                labels.iter().for_each(|label| self.spawn(label))
+            }
+            SearchLabel::Not(label) => self.spawn(label),
+            label => self.spawn_lookup(label.clone()),
         }
-        response.set_ok(response_items);
+    }
 
-        Ok(response)
+    /// Start the lookup of a single label into a new set
+    fn spawn_lookup(&mut self, label: SearchLabel) {
+        let target = Arc::new(DashSet::new());
+        self.sets.push(target.clone());
+        let collection = self.collection.clone();
+        let (limit, cancel) = (self.limit.clone(), self.cancel.clone());
+        let query = label.clone();
+        let handle = tokio::task::spawn(async move {
+            let lookup = async {
+                // The semaphore is never closed
+                let _permit = limit.acquire_owned().await;
+                match &label {
+                    SearchLabel::Include(inner) | SearchLabel::Exclude(inner) => {
+                        collection
+                            .search_label(inner.clone(), target, &cancel)
+                            .await
+                    }
+                    SearchLabel::NumericRange { name, min, max } => {
+                        collection
+                            .search_numeric(name, *min, *max, target, &cancel)
+                            .await
+                    }
+                    // Split up by `spawn`
+                    SearchLabel::And(_) | SearchLabel::Or(_) | SearchLabel::Not(_) => Ok(0),
+                }
+            };
+            tokio::select! {
+                res = lookup => res.map(Some),
+                _ = cancel.cancelled() => {
+                    log::debug!("query cancelled");
+                    Ok(None)
+                }
+            }
+        });
+        self.handles.push((query, handle));
+    }
+}
+
+/// The objects matching part of a search. A `Not` is kept as the objects it leaves out,
+/// so the collection only has to be listed if the whole search ends up negated.
+enum Matches {
+    Only(HashSet<ObjectRef>),
+    AllBut(HashSet<ObjectRef>),
+}
+
+impl Matches {
+    /// Combine the lookup sets of the labels in `label`, taking them from `sets` in the
+    /// order `Lookups::spawn` started them
+    fn eval(label: &SearchLabel, sets: &mut impl Iterator<Item = Arc<DashSet<ObjectRef>>>) -> Self {
+        match label {
+            SearchLabel::Include(_) | SearchLabel::NumericRange { .. } => {
+                Self::Only(Self::take(sets))
+            }
+            SearchLabel::Exclude(_) => Self::AllBut(Self::take(sets)),
+            SearchLabel::And(labels) => labels
+                .iter()
+                .fold(Self::AllBut(HashSet::new()), |acc, label| {
+                    acc.and(Self::eval(label, sets))
+                }),
+            SearchLabel::Or(labels) => labels
+                .iter()
+                .fold(Self::Only(HashSet::new()), |acc, label| {
+                    acc.or(Self::eval(label, sets))
+                }),
+            SearchLabel::Not(label) => Self::eval(label, sets).not(),
+        }
+    }
+
+    fn take(sets: &mut impl Iterator<Item = Arc<DashSet<ObjectRef>>>) -> HashSet<ObjectRef> {
+        sets.next()
+            .map(|set| set.iter().map(|item| item.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Only(mut a), Self::Only(b)) => {
+                a.retain(|item| b.contains(item));
+                Self::Only(a)
+            }
+            (Self::Only(mut a), Self::AllBut(b)) | (Self::AllBut(b), Self::Only(mut a)) => {
+                a.retain(|item| !b.contains(item));
+                Self::Only(a)
+            }
+            (Self::AllBut(mut a), Self::AllBut(b)) => {
+                a.extend(b);
+                Self::AllBut(a)
+            }
+        }
+    }
+
+    fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Only(mut a), Self::Only(b)) => {
+                a.extend(b);
+                Self::Only(a)
+            }
+            (Self::Only(a), Self::AllBut(mut b)) | (Self::AllBut(mut b), Self::Only(a)) => {
+                b.retain(|item| !a.contains(item));
+                Self::AllBut(b)
+            }
+            (Self::AllBut(mut a), Self::AllBut(b)) => {
+                a.retain(|item| b.contains(item));
+                Self::AllBut(a)
+            }
+        }
+    }
+
+    fn not(self) -> Self {
+        match self {
+            Self::Only(set) => Self::AllBut(set),
+            Self::AllBut(set) => Self::Only(set),
+        }
+    }
+
+    /// The matching objects, listing the collection for a negated search
+    fn resolve(self, collection: &Collection) -> Result<HashSet<ObjectRef>, MauveError> {
+        match self {
+            Self::Only(set) => Ok(set),
+            Self::AllBut(set) => {
+                let mut objects = HashSet::new();
+                for key in collection.data_tree().iter().keys() {
+                    let object = collection.object_ref(&String::from_utf8(key?.to_vec())?);
+                    if !set.contains(&object) {
+                        objects.insert(object);
+                    }
+                }
+                Ok(objects)
+            }
+        }
     }
 }
 
 impl Collection {
+    /// Add every object carrying `label` to `target`. A label with an empty value matches
+    /// any value of its name, from a scan of the forward index, and one with an empty name
+    /// matches its value under any name, from a scan of the reverse index.
     async fn search_label(
         &self,
         label: Label,
         target: Arc<DashSet<ObjectRef>>,
+        cancel: &CancellationToken,
     ) -> Result<usize, MauveError> {
-        match self.index_fwd().get(label.to_fwd().as_bytes()) {
-            Ok(Some(bytes)) => {
-                let objects = ObjectRefs::from_object(bytes.to_vec())?;
-                let len = objects.len();
-                for o in objects {
-                    target.insert(o.clone());
+        let entries: Box<dyn Iterator<Item = sled::Result<sled::IVec>> + Send> =
+            match (label.name.is_empty(), label.value.is_empty()) {
+                (false, false) => Box::new(
+                    self.index_fwd()
+                        .get(label.to_fwd().as_bytes())
+                        .transpose()
+                        .into_iter(),
+                ),
+                (false, true) => Box::new(
+                    self.index_fwd()
+                        .scan_prefix(format!("{}=", label.name))
+                        .values(),
+                ),
+                (true, false) => {
+                    // The prefix also matches longer values containing `=`, whose keys
+                    // have another `=` left after it
+                    let prefix = format!("{}=", label.value);
+                    Box::new(
+                        self.index_rev().scan_prefix(&prefix).filter_map(
+                            move |entry| match entry {
+                                Ok((key, refs)) => {
+                                    (!key[prefix.len()..].contains(&b'=')).then_some(Ok(refs))
+                                }
+                                Err(e) => Some(Err(e)),
+                            },
+                        ),
+                    )
+                }
+                (true, true) => return Ok(0),
+            };
+
+        let mut found = 0;
+        for bytes in entries {
+            let objects = ObjectRefs::from_index(&bytes?)?;
+            found += objects.len();
+            for o in objects {
+                if cancel.is_cancelled() {
+                    return Ok(found);
                 }
-                Ok(len)
+                target.insert(o.clone());
             }
-            Ok(None) => Ok(0),
-            Err(e) => Err(e.into()),
         }
+        Ok(found)
+    }
+
+    /// Add every object whose numeric label `name` lies in `min..=max` to `target`
+    async fn search_numeric(
+        &self,
+        name: &str,
+        min: f64,
+        max: f64,
+        target: Arc<DashSet<ObjectRef>>,
+        cancel: &CancellationToken,
+    ) -> Result<usize, MauveError> {
+        let Some(index) = self.index_num() else {
+            log::warn!(collection = self.name, label = name; "numeric search on a collection without a numeric index");
+            return Ok(0);
+        };
+        let start = numeric_prefix(name, min);
+        let end = numeric_prefix(name, max);
+        let mut found = 0;
+        for key in index.range(start..).keys() {
+            let key = key?;
+            if key.len() < end.len() || key[..end.len()] > end[..] || cancel.is_cancelled() {
+                break;
+            }
+            let object = String::from_utf8(key[end.len()..].to_vec())?;
+            target.insert(self.object_ref(&object));
+            found += 1;
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        api::tests::test_config,
+        backend::Backend,
+        labels::Label,
+        meta::Metadata,
+        objects::{ObjectRef, ObjectRefs},
+        search::{SearchError, SearchLabel, SearchMode, SearchRequest, SearchResults},
+    };
+
+    #[rocket::async_test]
+    async fn test_numeric_range() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config
+            .mauve
+            .numeric_labels
+            .insert("files".to_string(), vec!["size_bytes".to_string()]);
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("files")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for (name, size) in [
+            ("small", "512"),
+            ("big", "10485760"),
+            ("neg", "-3"),
+            ("odd", "lots"),
+        ] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("size_bytes", size));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        for _ in 0..50 {
+            if collection.index_num.as_ref().map(|t| t.len()) == Some(3) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let search = |min, max| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("files");
+                req.numeric_range("size_bytes", min, max);
+                let mut names: Vec<_> = backend
+                    .perform_search(req)
+                    .await?
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.name.clone())
+                    .collect();
+                names.sort();
+                anyhow::Ok(names)
+            }
+        };
+        assert_eq!(search(1_000_000.0, f64::MAX).await?, ["big"]);
+        assert_eq!(search(-10.0, 512.0).await?, ["neg", "small"]);
+        assert!(search(513.0, 1000.0).await?.is_empty());
+
+        // Non-numeric values are still indexed as strings
+        assert!(collection.index_fwd.contains_key("size_bytes=lots")?);
+
+        collection.delete_object("small")?;
+        for _ in 0..50 {
+            if collection.index_num.as_ref().map(|t| t.len()) == Some(2) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(search(-10.0, 512.0).await?, ["neg"]);
+
+        let mut req = SearchRequest::new("files");
+        req.numeric_range("size_bytes", f64::MIN, f64::MAX);
+        req.exclude(Label::new("size_bytes", "-3"));
+        req.count_only(true);
+        let res = backend.perform_search(req).await?;
+        assert!(res.result.unwrap_or_default().is_empty());
+        let stats = res.stats.unwrap_or_default();
+        assert_eq!(stats.labels_scanned, 2);
+        assert_eq!(stats.candidates_examined, 3);
+        assert_eq!((stats.excluded, stats.matched), (1, 1));
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_all_mode() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("teams")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for (name, labels) in [
+            ("both", vec![("env", "prod"), ("team", "infra")]),
+            ("prod", vec![("env", "prod"), ("team", "web")]),
+            ("infra", vec![("env", "dev"), ("team", "infra")]),
+        ] {
+            let mut meta = Metadata::default();
+            for (label, value) in labels {
+                meta.labels.insert(Label::new(label, value));
+            }
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        let search = |mode: Option<SearchMode>| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("teams");
+                req.include(Label::new("env", "prod"));
+                req.include(Label::new("team", "infra"));
+                if let Some(mode) = mode {
+                    req.mode(mode);
+                }
+                let mut names: Vec<_> = backend
+                    .perform_search(req)
+                    .await?
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.name.clone())
+                    .collect();
+                names.sort();
+                anyhow::Ok(names)
+            }
+        };
+        // Wait for the indexer to catch up with every label
+        for _ in 0..50 {
+            if search(Some(SearchMode::Any)).await?.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(
+            search(Some(SearchMode::Any)).await?,
+            ["both", "infra", "prod"]
+        );
+        assert_eq!(search(Some(SearchMode::All)).await?, ["both"]);
+        // Includes intersect unless the search asks for any of them
+        assert_eq!(search(None).await?, ["both"]);
+        let req: SearchRequest =
+            serde_json::from_value(serde_json::json!({ "collection": "teams", "labels": [] }))?;
+        assert_eq!(req.mode, SearchMode::All);
+
+        // Excludes subtract from the final set in either mode
+        for mode in [SearchMode::All, SearchMode::Any] {
+            let mut req = SearchRequest::new("teams");
+            req.include(Label::new("env", "prod"));
+            req.include(Label::new("team", "infra"));
+            req.exclude(Label::new("team", "web"));
+            req.mode(mode);
+            let found = backend
+                .perform_search(req)
+                .await?
+                .result
+                .unwrap_or_default();
+            let mut names: Vec<_> = found.refs().into_iter().map(|o| o.name.clone()).collect();
+            names.sort();
+            let expected: &[&str] = match mode {
+                SearchMode::All => &["both"],
+                SearchMode::Any => &["both", "infra"],
+            };
+            assert_eq!(names, expected);
+        }
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_case_sensitive() -> anyhow::Result<()> {
+        for case_sensitive in [false, true] {
+            let mut config = test_config();
+            config.mauve.case_sensitive = case_sensitive;
+            let backend = Backend::open(config.clone())?;
+            let collection = backend.get_collection("files")?;
+            // Give the collection indexer time to start watching
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let name = match case_sensitive {
+                true => "MyFile",
+                false => "myfile",
+            };
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::exact("Color", "Mauve"));
+            meta.normalize(&config.mauve);
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+
+            // Without case sensitivity the label was folded and so is the search
+            let mut found = SearchResults::default();
+            for _ in 0..50 {
+                let mut req = SearchRequest::new("files");
+                req.include(Label::exact("Color", "Mauve"));
+                found = backend
+                    .perform_search(req)
+                    .await?
+                    .result
+                    .unwrap_or_default();
+                if !found.is_empty() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert_eq!(found.len(), 1);
+            assert_eq!(found.refs()[0].name, name);
+            let mut req = SearchRequest::new("files");
+            req.include(Label::exact("color", "mauve"));
+            let folded = backend
+                .perform_search(req)
+                .await?
+                .result
+                .unwrap_or_default();
+            assert_eq!(folded.len(), !case_sensitive as usize);
+
+            std::fs::remove_dir_all(config.sled.path)?;
+        }
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_partial_labels() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("pets")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for (name, labels) in [
+            ("rex", vec![("kind", "dog"), ("color", "brown")]),
+            ("tom", vec![("kind", "cat"), ("color", "black")]),
+            ("crow", vec![("kind", "bird"), ("mood", "black")]),
+            // A value containing `=` doesn't pass for a shorter one in the reverse index
+            ("rock", vec![("shape", "round"), ("note", "black=white")]),
+        ] {
+            let mut meta = Metadata::default();
+            for (label, value) in labels {
+                meta.labels.insert(Label::new(label, value));
+            }
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        let search = |include: Label, exclude: Option<Label>| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("pets");
+                req.include(include);
+                req.excludes(exclude);
+                let mut names: Vec<_> = backend
+                    .perform_search(req)
+                    .await?
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.name.clone())
+                    .collect();
+                names.sort();
+                anyhow::Ok(names)
+            }
+        };
+        // Wait for the indexer to catch up with every label
+        for _ in 0..50 {
+            if search(Label::new("kind", ""), None).await?.len() == 3
+                && search(Label::new("", "black=white"), None).await?.len() == 1
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(search(Label::new("kind", "cat"), None).await?, ["tom"]);
+        assert_eq!(
+            search(Label::new("kind", ""), None).await?,
+            ["crow", "rex", "tom"]
+        );
+        assert_eq!(
+            search(Label::new("", "black"), None).await?,
+            ["crow", "tom"]
+        );
+        assert_eq!(
+            search(Label::new("kind", ""), Some(Label::new("", "black"))).await?,
+            ["rex"]
+        );
+        assert_eq!(
+            search(Label::new("", "black"), Some(Label::new("mood", ""))).await?,
+            ["tom"]
+        );
+        assert!(search(Label::new("", ""), None).await?.is_empty());
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_expressions() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("exprs")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for (name, labels) in [
+            ("one", vec![("a", "1"), ("b", "3")]),
+            ("two", vec![("a", "1")]),
+            ("three", vec![("a", "2")]),
+            ("four", vec![("a", "3")]),
+        ] {
+            let mut meta = Metadata::default();
+            for (label, value) in labels {
+                meta.labels.insert(Label::new(label, value));
+            }
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        let include = |name, value| SearchLabel::Include(Label::new(name, value));
+        let search = |labels: Vec<SearchLabel>| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("exprs");
+                for label in labels {
+                    req.expression(label);
+                }
+                let mut names: Vec<_> = backend
+                    .perform_search(req)
+                    .await?
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.name.clone())
+                    .collect();
+                names.sort();
+                anyhow::Ok(names)
+            }
+        };
+        // Wait for the indexer to catch up with every label
+        for _ in 0..50 {
+            if search(vec![include("a", "")]).await?.len() == 4
+                && search(vec![include("b", "3")]).await?.len() == 1
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // (a=1 OR a=2) AND NOT b=3
+        let expr = SearchLabel::And(vec![
+            SearchLabel::Or(vec![include("a", "1"), include("a", "2")]),
+            SearchLabel::Not(Box::new(include("b", "3"))),
+        ]);
+        assert_eq!(search(vec![expr]).await?, ["three", "two"]);
+        // A negation on its own matches the rest of the collection
+        assert_eq!(
+            search(vec![SearchLabel::Not(Box::new(include("a", "1")))]).await?,
+            ["four", "three"]
+        );
+        assert_eq!(
+            search(vec![SearchLabel::Or(vec![
+                include("b", "3"),
+                SearchLabel::Not(Box::new(include("a", ""))),
+            ])])
+            .await?,
+            ["one"]
+        );
+        assert!(search(vec![SearchLabel::Or(vec![])]).await?.is_empty());
+        assert_eq!(search(vec![SearchLabel::And(vec![])]).await?.len(), 4);
+
+        // Groups combine with flat labels by the search mode
+        let mut req = SearchRequest::new("exprs");
+        req.include(Label::new("a", "1"));
+        req.expression(SearchLabel::Not(Box::new(include("b", "3"))));
+        req.mode(SearchMode::All);
+        let res = backend.perform_search(req).await?;
+        let found = res.result.unwrap_or_default();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found.refs()[0].name, "two");
+
+        // Expressions nest in the JSON form of a search
+        let req: SearchRequest = serde_json::from_value(serde_json::json!({
+            "collection": "exprs",
+            "labels": [{ "Or": [
+                { "Include": { "name": "a", "value": "2" } },
+                { "Not": { "Include": { "name": "a", "value": "" } } },
+            ] }],
+        }))?;
+        let res = backend.perform_search(req).await?;
+        assert_eq!(res.result.unwrap_or_default().len(), 1);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_lookup_failure() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.query_concurrency = 1;
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("broken")?;
+        collection
+            .index_fwd
+            .insert("color=mauve", b"not refs".to_vec())?;
+
+        let mut req = SearchRequest::new("broken");
+        req.mode(SearchMode::Any);
+        req.include(Label::new("shape", "round"));
+        req.include(Label::new("color", "mauve"));
+        let res = backend.perform_search(req).await?;
+        match res.result {
+            Err(SearchError::LookupFailed { label, .. }) => assert_eq!(label, "color=mauve"),
+            other => panic!("expected a failed lookup, got {other:?}"),
+        }
+        assert!(res.stats.is_none());
+
+        // A search whose lookups all succeed isn't held up
+        let mut req = SearchRequest::new("broken");
+        req.include(Label::new("shape", "round"));
+        let res = backend.perform_search(req).await?;
+        assert!(res.result.unwrap_or_default().is_empty());
+        assert_eq!(res.stats.unwrap_or_default().labels_scanned, 1);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_cancelled() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("cancelled")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("a", meta)?;
+        collection.put_object("a", vec![], false)?;
+        for _ in 0..50 {
+            if collection.index_fwd.contains_key("color=mauve")? {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // A cancelled search reports it rather than passing off what it found as complete
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let mut req = SearchRequest::new("cancelled");
+        req.include(Label::new("color", "mauve"));
+        let res = backend.perform_search_cancellable(req, cancel).await?;
+        assert!(matches!(res.result, Err(SearchError::Cancelled)));
+        assert_eq!(res.total, 0);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_pagination() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.max_search_results = 3;
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("pages")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        for name in ["e", "c", "a", "d", "b"] {
+            let mut meta = Metadata::default();
+            meta.labels.insert(Label::new("color", "mauve"));
+            collection.put_object_metadata(name, meta)?;
+            collection.put_object(name, vec![], false)?;
+        }
+        let search = |limit: Option<usize>, offset| {
+            let backend = backend.clone();
+            async move {
+                let mut req = SearchRequest::new("pages");
+                req.include(Label::new("color", "mauve"));
+                if let Some(limit) = limit {
+                    req.limit(limit);
+                }
+                req.offset(offset);
+                let res = backend.perform_search(req).await?;
+                let names: Vec<_> = res
+                    .result
+                    .unwrap_or_default()
+                    .refs()
+                    .into_iter()
+                    .map(|object| object.name.clone())
+                    .collect();
+                anyhow::Ok((names, res.total))
+            }
+        };
+        // Wait for the indexer to catch up with every object
+        for _ in 0..50 {
+            if search(None, 0).await?.1 == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(search(Some(2), 0).await?, (vec!["a".into(), "b".into()], 5));
+        assert_eq!(search(Some(2), 2).await?, (vec!["c".into(), "d".into()], 5));
+        assert_eq!(search(Some(2), 4).await?, (vec!["e".into()], 5));
+        assert_eq!(search(Some(2), 6).await?, (vec![], 5));
+        // The server caps the page size
+        assert_eq!(search(Some(10), 0).await?.0, ["a", "b", "c"]);
+        assert_eq!(search(None, 1).await?.0, ["b", "c", "d"]);
+
+        // A names only search returns refs in place of the objects and their metadata
+        let mut req = SearchRequest::new("pages");
+        req.include(Label::new("color", "mauve"));
+        req.names_only(true);
+        req.limit(2);
+        let res = backend.perform_search(req).await?;
+        assert_eq!(res.total, 5);
+        match res.result {
+            Ok(SearchResults::Names(refs)) => assert_eq!(
+                refs,
+                [ObjectRef::new("pages", "a"), ObjectRef::new("pages", "b")]
+            ),
+            other => panic!("expected names, got {other:?}"),
+        }
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+
+    #[rocket::async_test]
+    async fn test_vanished_object() -> anyhow::Result<()> {
+        let config = test_config();
+        let backend = Backend::open(config.clone())?;
+        let collection = backend.get_collection("vanishing")?;
+        // Give the collection indexer time to start watching
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        collection.put_object_metadata("a", meta)?;
+        collection.put_object("a", vec![], false)?;
+        for _ in 0..50 {
+            if collection.index_fwd.contains_key("color=mauve")? {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // The index still lists an object deleted before the search fetched its metadata
+        let refs = ObjectRefs::new(vec![
+            ObjectRef::new("vanishing", "a"),
+            ObjectRef::new("vanishing", "gone"),
+        ]);
+        collection
+            .index_fwd
+            .insert("color=mauve", refs.to_index(false)?)?;
+
+        let mut req = SearchRequest::new("vanishing");
+        req.include(Label::new("color", "mauve"));
+        let res = backend.perform_search(req).await?;
+        assert_eq!(res.total, 1);
+        assert_eq!(
+            res.result.unwrap_or_default().refs(),
+            [&ObjectRef::new("vanishing", "a")]
+        );
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
     }
 }
@@ -0,0 +1,89 @@
+//! Content type sniffing
+//!
+//! Objects uploaded without a `Content-Type` can have one guessed from their first bytes
+//! when `sniff_content_type` is set. Only well-known magic numbers are matched, and
+//! anything else that reads as UTF-8 text is `text/plain`. Bodies with a content encoding
+//! aren't sniffed, as their bytes are the compressed form.
+
+/// Bytes of the body looked at
+const SNIFF_LEN: usize = 4096;
+
+/// Magic numbers at the start of a body, with the type they identify
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BM", "image/bmp"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"BZh", "application/x-bzip2"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x00asm", "application/wasm"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"fLaC", "audio/flac"),
+];
+
+/// Guess the content type of a body from its first bytes, or `None` if it isn't recognised
+pub fn sniff(body: &[u8]) -> Option<&'static str> {
+    let head = &body[..body.len().min(SNIFF_LEN)];
+    if head.is_empty() {
+        return None;
+    }
+    if let Some((_, content_type)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(content_type);
+    }
+    // RIFF containers name their format after the length
+    if head.starts_with(b"RIFF") && head.len() >= 12 {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => (),
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+    text(head)
+}
+
+/// `text/plain` for bytes that read as UTF-8 without control characters, allowing for a
+/// character cut off at the end of the sniffed bytes
+fn text(head: &[u8]) -> Option<&'static str> {
+    let valid = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return None,
+    };
+    let binary = valid
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c'));
+    (!binary).then_some("text/plain; charset=utf-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sniff;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\x00\x00\x00\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(sniff(b"hello\nworld"), Some("text/plain; charset=utf-8"));
+        // A multibyte character cut off by the sniff length is still text
+        let long = format!("a{}", "é".repeat(4096));
+        assert_eq!(sniff(long.as_bytes()), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(b"\x00\x01\x02\x03"), None);
+        assert_eq!(sniff(b""), None);
+    }
+}
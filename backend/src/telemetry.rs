@@ -0,0 +1,45 @@
+//! Telemetry
+//!
+//! Distributed tracing for HTTP handlers, collection operations and the indexer, exported
+//! as OpenTelemetry spans over OTLP. Only available with the `otel` feature.
+
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::{config::MauveConfig, errors::MauveError};
+
+/// Install a global tracing subscriber exporting spans to `mauve.otlp_endpoint`.
+///
+/// The subscriber also prints events to stdout and bridges `log` records into tracing, so
+/// it replaces any other `log` logger. Returns `None` when no endpoint is configured. The
+/// returned provider should be shut down on exit to flush pending spans.
+///
+/// Must be called from within a tokio runtime.
+pub fn init(config: &MauveConfig) -> Result<Option<TracerProvider>, MauveError> {
+    let endpoint = match &config.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| MauveError::Oops(format!("failed to build OTLP exporter: {e}")))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new("service.name", "mauve")]))
+        .build();
+
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("mauve")))
+        .with(tracing_subscriber::fmt::layer())
+        .try_init()
+        .map_err(|e| MauveError::Oops(format!("failed to install tracing subscriber: {e}")))?;
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    log::info!("Exporting traces over OTLP to {endpoint}");
+    Ok(Some(provider))
+}
@@ -0,0 +1,183 @@
+//! Tombstones
+//!
+//! A record of the objects deleted from a collection, for features that need to learn about
+//! deletes after the fact. Tombstones cost space, so they are only kept for the collections
+//! listed under `tombstones` in the config, in each collection's `mauve_tombstones::<name>`
+//! tree keyed by object name.
+//!
+//! A tombstone is written by every delete and dropped again if the object is written back.
+//! A reaper task prunes tombstones once they are older than `retention_secs`, taking the
+//! object's delete out of the changes feed with it, so a client that syncs less often than
+//! the retention window should start over from a full listing.
+//!
+//! The reaper also evicts expired upstream copies from the collections read through from an
+//! upstream, see `Collection::evict_expired`.
+
+use std::time::Duration;
+
+use macros::MauveObject;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::Backend,
+    changes::now_millis,
+    collection::Collection,
+    config::{default_reap_interval, TombstoneConfig},
+    errors::MauveError,
+    objects::ToFromMauve,
+};
+
+/// A deleted object
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, MauveObject)]
+pub struct Tombstone {
+    pub name: String,
+    /// Milliseconds since the epoch
+    pub deleted_at: u64,
+}
+
+impl Collection {
+    /// Record `ident` as deleted, if this collection keeps tombstones
+    pub(crate) fn bury(&self, ident: &str) -> Result<(), MauveError> {
+        if let Some(tombstones) = &self.tombstones {
+            let tombstone = Tombstone {
+                name: ident.to_string(),
+                deleted_at: now_millis()?,
+            };
+            tombstones.insert(ident, tombstone.to_object()?)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the tombstone of an object that has been written again
+    pub(crate) fn unbury(&self, ident: &str) -> Result<(), MauveError> {
+        if let Some(tombstones) = &self.tombstones {
+            tombstones.remove(ident)?;
+        }
+        Ok(())
+    }
+
+    /// List the tombstones of deleted objects. This is empty unless the collection keeps
+    /// tombstones.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn list_tombstones(&self) -> Result<Vec<Tombstone>, MauveError> {
+        let mut list = vec![];
+        if let Some(tombstones) = &self.tombstones {
+            for tombstone in tombstones.iter().values() {
+                list.push(Tombstone::from_object(tombstone?.to_vec())?);
+            }
+        }
+        Ok(list)
+    }
+
+    /// Prune tombstones for deletes made before `before` (milliseconds since the epoch),
+    /// along with their entries in the changes feed. Returns how many were pruned.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn prune_tombstones(&self, before: u64) -> Result<usize, MauveError> {
+        let Some(tombstones) = &self.tombstones else {
+            return Ok(0);
+        };
+        let mut pruned = 0;
+        for tombstone in self.list_tombstones()? {
+            if tombstone.deleted_at >= before {
+                continue;
+            }
+            self.forget_delete(&tombstone.name)?;
+            tombstones.remove(&tombstone.name)?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+}
+
+/// Start the reaper, pruning the configured collections' tombstones and evicting the expired
+/// copies of the `cached` collections every `interval_secs`
+pub(crate) fn start(backend: Backend, config: Option<TombstoneConfig>, cached: Vec<String>) {
+    tokio::task::spawn(async move {
+        let interval_secs = config
+            .as_ref()
+            .map_or_else(default_reap_interval, |config| config.interval_secs);
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            for name in &cached {
+                match backend
+                    .get_collection(name)
+                    .and_then(|collection| collection.evict_expired())
+                {
+                    Ok(0) => (),
+                    Ok(evicted) => {
+                        log::info!(collection = name; "Evicted {evicted} expired upstream copies")
+                    }
+                    Err(e) => log::error!(collection = name; "upstream cache reaper failed {e}"),
+                }
+            }
+            let Some(config) = &config else { continue };
+            let retention = config.retention_secs.saturating_mul(1000);
+            let before = match now_millis() {
+                Ok(now) => now.saturating_sub(retention),
+                Err(e) => {
+                    log::error!("tombstone reaper failed {e}");
+                    continue;
+                }
+            };
+            for name in &config.collections {
+                match backend
+                    .get_collection(name)
+                    .and_then(|collection| collection.prune_tombstones(before))
+                {
+                    Ok(0) => (),
+                    Ok(pruned) => log::info!(collection = name; "Pruned {pruned} tombstones"),
+                    Err(e) => log::error!(collection = name; "tombstone reaper failed {e}"),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{api::tests::test_config, backend::Backend, config::TombstoneConfig};
+
+    #[rocket::async_test]
+    async fn test_tombstones() -> anyhow::Result<()> {
+        let mut config = test_config();
+        config.mauve.tombstones = Some(TombstoneConfig {
+            collections: vec!["graves".to_string()],
+            retention_secs: 60,
+            interval_secs: 3600,
+        });
+        let backend = Backend::open(config.clone())?;
+        let graves = backend.get_collection("graves")?;
+        let other = backend.get_collection("other")?;
+        for collection in [&graves, &other] {
+            collection.put_object("a", b"a".to_vec(), false)?;
+            collection.put_object("b", b"b".to_vec(), false)?;
+            collection.delete_objects(&["a".to_string(), "b".to_string()])?;
+        }
+        assert!(other.list_tombstones()?.is_empty());
+        let mut names: Vec<_> = graves
+            .list_tombstones()?
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+
+        // Writing an object back removes its tombstone
+        graves.put_object("b", b"b".to_vec(), false)?;
+        let tombstones = graves.list_tombstones()?;
+        assert_eq!(tombstones.len(), 1);
+        assert_eq!(tombstones[0].name, "a");
+
+        assert_eq!(graves.prune_tombstones(tombstones[0].deleted_at)?, 0);
+        assert_eq!(graves.prune_tombstones(u64::MAX)?, 1);
+        assert!(graves.list_tombstones()?.is_empty());
+        let changes = graves.list_changes(0, None, 10)?.changes;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].key, "b");
+        assert!(!changes[0].deleted);
+
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
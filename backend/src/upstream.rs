@@ -0,0 +1,318 @@
+//! Upstream
+//!
+//! Read-through federation from another Mauve instance. When an object is missing from a
+//! federated collection it is fetched from the upstream's S3 API, stored locally with its
+//! metadata, and served as if it had always been here. Writes stay local.
+//!
+//! Cached copies expire after `cache_ttl` seconds. Expiry times live in the collection's
+//! `mauve_cache_ttl::<name>` tree, and an expired copy is refreshed from the upstream on its
+//! next read, or dropped if the upstream no longer has it. Copies that expire without being
+//! read again are evicted by the tombstone reaper, see `Collection::evict_expired`. Objects
+//! written locally have no expiry and are never replaced from the upstream.
+//!
+//! An expiry without a local copy is a negative entry. A miss upstream is remembered for
+//! `cache_ttl` too, so missing objects aren't asked for on every read, and a local delete is
+//! remembered until the object is written again, so it isn't read back from the upstream.
+
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+use url::Url;
+
+use crate::{
+    collection::Collection, config::UpstreamConfig, errors::MauveError, labels::Label,
+    meta::Metadata,
+};
+
+/// Connection to the upstream instance, shared by every federated collection
+#[derive(Clone)]
+pub(crate) struct Upstream {
+    client: reqwest::Client,
+    url: Url,
+    ttl: Duration,
+    pub(crate) collections: Vec<String>,
+}
+
+impl Upstream {
+    pub(crate) fn new(config: &UpstreamConfig) -> Result<Self, MauveError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .build()?;
+        Ok(Self {
+            client,
+            url: Url::parse(&config.url).map_err(|e| MauveError::ConfigError(e.to_string()))?,
+            ttl: Duration::from_secs(config.cache_ttl),
+            collections: config.collections.clone(),
+        })
+    }
+}
+
+/// A collection's view of the upstream, along with the expiry times of its cached objects
+#[derive(Clone)]
+pub(crate) struct UpstreamCache {
+    pub(crate) upstream: Upstream,
+    pub(crate) expiry: sled::Tree,
+}
+
+impl UpstreamCache {
+    /// Returns `Some(true)` if `ident` has an entry past its TTL, `Some(false)` if it has one
+    /// that is still fresh, and `None` if it has none
+    pub(crate) fn is_expired(&self, ident: &str) -> Result<Option<bool>, MauveError> {
+        Ok(match self.expiry.get(ident)? {
+            Some(expires) => {
+                let expires = u64::from_be_bytes(expires.as_ref().try_into().unwrap_or_default());
+                Some(expires <= now()?)
+            }
+            None => None,
+        })
+    }
+
+    /// Record `ident` as a cached copy expiring one TTL from now
+    pub(crate) fn cached(&self, ident: &str) -> Result<(), MauveError> {
+        let expires = now()? + self.upstream.ttl.as_secs();
+        self.expiry.insert(ident, &expires.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Record `ident` as missing upstream for one TTL from now. The same entry as a cached
+    /// copy, with no local copy to go with it.
+    pub(crate) fn missed(&self, ident: &str) -> Result<(), MauveError> {
+        self.cached(ident)
+    }
+
+    /// Record `ident` as deleted here, so it is not read from the upstream again until it is
+    /// written locally
+    pub(crate) fn deleted(&self, ident: &str) -> Result<(), MauveError> {
+        self.expiry.insert(ident, &u64::MAX.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Stop treating `ident` as a cached copy or as missing
+    pub(crate) fn forget(&self, ident: &str) -> Result<(), MauveError> {
+        self.expiry.remove(ident)?;
+        Ok(())
+    }
+
+    /// Every cached copy or miss past its TTL
+    fn expired(&self) -> Result<Vec<String>, MauveError> {
+        let now = now()?;
+        let mut expired = vec![];
+        for entry in self.expiry.iter() {
+            let (ident, expires) = entry?;
+            let expires = u64::from_be_bytes(expires.as_ref().try_into().unwrap_or_default());
+            if expires <= now {
+                expired.push(String::from_utf8(ident.to_vec())?);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Fetch an object and its metadata from the upstream, or `None` if it doesn't have it
+    pub(crate) fn fetch(
+        &self,
+        collection: &str,
+        ident: &str,
+    ) -> Result<Option<(Vec<u8>, Metadata)>, MauveError> {
+        let mut url = self.upstream.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| {
+                let url = &self.upstream.url;
+                MauveError::ConfigError(format!("upstream url {url} cannot be a base"))
+            })?
+            .pop_if_empty()
+            .extend(["s3", collection])
+            .extend(ident.split('/'));
+        let request = self.upstream.client.get(url).send();
+
+        block_on(async move {
+            let res = request.await?;
+            if res.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let res = res.error_for_status()?;
+
+            let mut meta = Metadata::default();
+            for (name, value) in res.headers() {
+                let Ok(value) = value.to_str() else { continue };
+                match name.as_str() {
+                    "content-type" => meta.content_type = value.to_string(),
+                    "content-encoding" => meta.content_encoding = value.to_string(),
+                    "content-language" => meta.content_language = value.to_string(),
+                    name => {
+                        // Case is folded with the rest of the metadata, see `read_through`
+                        if let Some(label) = name.strip_prefix("x-amz-meta-") {
+                            meta.labels.insert(Label::exact(label, value));
+                        }
+                    }
+                }
+            }
+            let object = res.bytes().await?.to_vec();
+            meta.size = object.len() as u64;
+            Ok(Some((object, meta)))
+        })?
+    }
+}
+
+impl Collection {
+    /// Drop the cached upstream copies and misses that are past their TTL, so entries that
+    /// are never read again don't stay forever. The next read of one asks the upstream again.
+    /// Returns how many copies were evicted.
+    #[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(collection = %self.name)))]
+    pub fn evict_expired(&self) -> Result<usize, MauveError> {
+        let Some(upstream) = &self.upstream else {
+            return Ok(0);
+        };
+        let mut evicted = 0;
+        for ident in upstream.expired()? {
+            if self.delete_object(&ident)?.is_some() {
+                evicted += 1;
+            }
+            // An eviction isn't a local delete, so the upstream may be read again
+            upstream.forget(&ident)?;
+        }
+        Ok(evicted)
+    }
+}
+
+/// Run an upstream request to completion from the synchronous `Collection` methods.
+///
+/// This needs a multi-threaded tokio runtime, such as Rocket's, to block on.
+fn block_on<F: Future>(f: F) -> Result<F::Output, MauveError> {
+    let handle = Handle::try_current().map_err(|e| MauveError::Oops(e.to_string()))?;
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(MauveError::Oops(
+            "upstream reads need a multi-threaded tokio runtime".to_string(),
+        ));
+    }
+    Ok(tokio::task::block_in_place(|| handle.block_on(f)))
+}
+
+fn now() -> Result<u64, MauveError> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| MauveError::Oops(e.to_string()))?
+        .as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::fairing::AdHoc;
+
+    use crate::{
+        api::{mauve_rocket, tests::test_config},
+        backend::Backend,
+        config::UpstreamConfig,
+        labels::Label,
+        meta::Metadata,
+    };
+
+    #[rocket::async_test]
+    async fn test_read_through() -> anyhow::Result<()> {
+        let mut upstream_config = test_config();
+        upstream_config.mauve.s3_enabled = true;
+        let upstream = Backend::open(upstream_config.clone())?;
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let rocket = mauve_rocket(upstream.clone(), upstream_config.clone())
+            .configure(rocket::Config {
+                port: 0,
+                ..rocket::Config::debug_default()
+            })
+            .attach(AdHoc::on_liftoff("port", |rocket| {
+                Box::pin(async move {
+                    let _ = tx.send(rocket.config().port);
+                })
+            }));
+        let shutdown = rocket.ignite().await?;
+        let handle = shutdown.shutdown();
+        tokio::spawn(shutdown.launch());
+        let port = rx.await?;
+
+        let central = upstream.get_collection("federated")?;
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::new("color", "mauve"));
+        central.put_object_metadata("a/b", meta)?;
+        central.put_object("a/b", b"hello".to_vec(), false)?;
+
+        // A zero TTL refreshes the cached copy on every read
+        let mut config = test_config();
+        config.mauve.upstream = Some(UpstreamConfig {
+            url: format!("http://127.0.0.1:{port}"),
+            collections: vec!["federated".to_string()],
+            cache_ttl: 0,
+            timeout_secs: 30,
+            connect_timeout_secs: 5,
+        });
+        let backend = Backend::open(config.clone())?;
+        let edge = backend.get_collection("federated")?;
+
+        assert!(!edge.data.contains_key("a/b")?);
+        assert_eq!(edge.get_object("a/b")?, b"hello");
+        assert!(edge.data.contains_key("a/b")?);
+        let meta = edge.get_object_metadata("a/b")?;
+        assert!(meta.labels.contains(&Label::new("color", "mauve")));
+        assert!(!edge.head_object("missing")?);
+
+        // Local writes are never replaced from the upstream
+        edge.put_object("local", b"mine".to_vec(), false)?;
+        central.put_object("local", b"theirs".to_vec(), false)?;
+        assert_eq!(edge.get_object("local")?, b"mine");
+
+        // The reaper evicts expired copies, and leaves local objects alone
+        assert_eq!(edge.evict_expired()?, 1);
+        assert!(!edge.data.contains_key("a/b")?);
+        assert!(edge.upstream.as_ref().unwrap().expiry.is_empty());
+        assert_eq!(edge.get_object("local")?, b"mine");
+        assert_eq!(edge.get_object("a/b")?, b"hello");
+
+        // Expired copies are dropped once the upstream no longer has them
+        central.delete_object("a/b")?;
+        assert!(!edge.head_object("a/b")?);
+        assert!(!edge.data.contains_key("a/b")?);
+
+        // A local delete isn't undone by reading the object from the upstream again
+        central.put_object("c", b"theirs".to_vec(), false)?;
+        assert_eq!(edge.get_object("c")?, b"theirs");
+        edge.delete_object("c")?;
+        assert!(!edge.head_object("c")?);
+        edge.evict_expired()?;
+        assert!(edge.get_object("c").is_err());
+        edge.put_object("c", b"mine".to_vec(), false)?;
+        assert_eq!(edge.get_object("c")?, b"mine");
+
+        // Misses are remembered for the TTL, and labels keep their case when the backend does
+        let mut cached_config = test_config();
+        cached_config.mauve.case_sensitive = true;
+        cached_config.mauve.upstream = Some(UpstreamConfig {
+            cache_ttl: 3600,
+            ..config.mauve.upstream.clone().unwrap()
+        });
+        let cached = Backend::open(cached_config.clone())?.get_collection("federated")?;
+        assert!(!cached.head_object("later")?);
+        central.put_object("later", b"late".to_vec(), false)?;
+        assert!(!cached.head_object("later")?);
+
+        let mut meta = Metadata::default();
+        meta.labels.insert(Label::exact("team", "Core"));
+        central.put_object_metadata("cased", meta)?;
+        central.put_object("cased", b"x".to_vec(), false)?;
+        for (collection, label) in [
+            (&cached, Label::exact("team", "Core")),
+            (&edge, Label::new("team", "core")),
+        ] {
+            collection.get_object("cased")?;
+            let labels = collection.get_object_metadata("cased")?.labels;
+            assert!(labels.contains(&label), "{labels:?}");
+        }
+
+        handle.notify();
+        std::fs::remove_dir_all(config.sled.path)?;
+        std::fs::remove_dir_all(upstream_config.sled.path)?;
+        std::fs::remove_dir_all(cached_config.sled.path)?;
+        Ok(())
+    }
+}
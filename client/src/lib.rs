@@ -0,0 +1,387 @@
+//! Mauve client
+//!
+//! A typed wrapper around the Mauve HTTP API, built on the same `Metadata` and `Label` types
+//! the server uses. Object metadata travels in the same headers the server reads and writes:
+//! `Content-Type`, `Content-Encoding`, `Content-Language`, `x-mauve-labels` and
+//! `x-mauve-offsets-inclusive`, with user-defined fields as `x-mauve-meta-<name>`, and reads
+//! also carry `x-mauve-created-at` and `x-mauve-updated-at`. A `content_hash` set on the metadata of a put is sent as
+//! `x-mauve-content-hash` for the server to verify, and the `content_hash` of metadata read
+//! back is taken from the object's `ETag`.
+
+use mc6_backend::{
+    api::errors::ErrorBody,
+    collection::CollectionStats,
+    labels::Label,
+    meta::Metadata,
+    search::{SearchRequest, SearchResponse},
+};
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH},
+    RequestBuilder, Response, StatusCode,
+};
+use thiserror::Error;
+use url::Url;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("HTTP client error {0}")]
+    HttpError(#[from] reqwest::Error),
+
+    #[error("Invalid url {0}")]
+    UrlError(String),
+
+    #[error("Invalid response {0}")]
+    InvalidResponse(String),
+
+    /// The server answered with a non-2xx status. `code` is the server's machine-readable
+    /// error name, e.g. `ObjectNotFound`, or empty if the body wasn't a Mauve error.
+    #[error("{status}: {message}")]
+    ApiError {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+}
+
+impl From<url::ParseError> for ClientError {
+    fn from(value: url::ParseError) -> Self {
+        ClientError::UrlError(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(value: serde_json::Error) -> Self {
+        ClientError::InvalidResponse(value.to_string())
+    }
+}
+
+/// Client for one Mauve server
+#[derive(Clone, Debug)]
+pub struct MauveClient {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl MauveClient {
+    /// Create a client for the server at `url`, e.g. `http://localhost:9000`
+    pub fn new(url: &str) -> Result<Self, ClientError> {
+        let url = Url::parse(url)?;
+        if url.cannot_be_a_base() {
+            return Err(ClientError::UrlError(format!("{url} cannot be a base")));
+        }
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url,
+        })
+    }
+
+    /// Store an object, replacing any object already stored under `name`
+    pub async fn put_object(
+        &self,
+        collection: &str,
+        name: &str,
+        object: Vec<u8>,
+        meta: &Metadata,
+    ) -> Result<(), ClientError> {
+        let request = self
+            .client
+            .put(self.object_url(collection, name))
+            .body(object);
+        send(with_metadata(request, meta)).await?;
+        Ok(())
+    }
+
+    /// Get an object and its metadata
+    pub async fn get_object(
+        &self,
+        collection: &str,
+        name: &str,
+    ) -> Result<(Vec<u8>, Metadata), ClientError> {
+        let res = send(self.client.get(self.object_url(collection, name))).await?;
+        let mut meta = metadata(res.headers())?;
+        let object = res.bytes().await?.to_vec();
+        meta.size = object.len() as u64;
+        Ok((object, meta))
+    }
+
+    /// Get an object's metadata without its body, or `None` if it doesn't exist
+    pub async fn head_object(
+        &self,
+        collection: &str,
+        name: &str,
+    ) -> Result<Option<Metadata>, ClientError> {
+        let res = self
+            .client
+            .head(self.object_url(collection, name))
+            .send()
+            .await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let res = check(res).await?;
+        let mut meta = metadata(res.headers())?;
+        // `Response::content_length` is the body's, which a HEAD response doesn't have
+        meta.size = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse().ok())
+            .unwrap_or_default();
+        Ok(Some(meta))
+    }
+
+    /// Delete an object
+    pub async fn delete_object(&self, collection: &str, name: &str) -> Result<(), ClientError> {
+        send(self.client.delete(self.object_url(collection, name))).await?;
+        Ok(())
+    }
+
+    /// List every collection on the server
+    pub async fn list_collections(&self) -> Result<Vec<String>, ClientError> {
+        let mut url = self.url_for(&["v1", "collections"]);
+        // The collection list is served from the mount point itself
+        url.path_segments_mut().expect("checked in new").push("");
+        let res = send(self.client.get(url)).await?;
+        Ok(serde_json::from_slice(&res.bytes().await?)?)
+    }
+
+    /// List the objects in a collection, optionally only those starting with `prefix`
+    pub async fn list_objects(
+        &self,
+        collection: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>, ClientError> {
+        let mut url = self.url_for(&["v1", "collections", collection]);
+        if let Some(prefix) = prefix {
+            url.query_pairs_mut().append_pair("prefix", prefix);
+        }
+        let res = send(self.client.get(url)).await?;
+        Ok(serde_json::from_slice(&res.bytes().await?)?)
+    }
+
+    /// Count a collection's objects, their total size and its distinct labels
+    pub async fn describe(&self, collection: &str) -> Result<CollectionStats, ClientError> {
+        let url = self.url_for(&["v1", "collections", collection, "stats"]);
+        let res = send(self.client.get(url)).await?;
+        Ok(serde_json::from_slice(&res.bytes().await?)?)
+    }
+
+    /// Run a search and return every result at once
+    pub async fn search(&self, req: &SearchRequest) -> Result<SearchResponse, ClientError> {
+        let mut url = self.url_for(&["v1", "search"]);
+        // Searches are posted to the mount point itself
+        url.path_segments_mut().expect("checked in new").push("");
+        let res = send(self.client.post(url).json(req)).await?;
+        Ok(serde_json::from_slice(&res.bytes().await?)?)
+    }
+
+    fn object_url(&self, collection: &str, name: &str) -> Url {
+        self.url_for(&["v1", "objects", collection, name])
+    }
+
+    /// The server url with `segments` appended, each percent-encoded as one path segment
+    fn url_for(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in new")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+}
+
+/// Send the object metadata as request headers
+fn with_metadata(mut request: RequestBuilder, meta: &Metadata) -> RequestBuilder {
+    for (header, value) in [
+        ("content-type", &meta.content_type),
+        ("content-encoding", &meta.content_encoding),
+        ("content-language", &meta.content_language),
+    ] {
+        if !value.is_empty() {
+            request = request.header(header, value);
+        }
+    }
+    if !meta.labels.is_empty() {
+        request = request.header("x-mauve-labels", meta.label_str());
+    }
+    match meta.offsets_str() {
+        Ok(offsets) if !offsets.is_empty() => {
+            request = request.header("x-mauve-offsets-inclusive", offsets);
+        }
+        _ => {}
+    }
+    if !meta.content_hash.is_empty() {
+        request = request.header("x-mauve-content-hash", format!("md5:{}", meta.content_hash));
+    }
+    for (name, value) in &meta.extra {
+        request = request.header(format!("x-mauve-meta-{name}"), value);
+    }
+    request
+}
+
+/// Read object metadata from response headers. The size is left for the caller.
+fn metadata(headers: &HeaderMap) -> Result<Metadata, ClientError> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut meta = Metadata {
+        content_type: header("content-type"),
+        content_encoding: header("content-encoding"),
+        content_language: header("content-language"),
+        ..Default::default()
+    };
+    for label in header("x-mauve-labels")
+        .split(',')
+        .filter(|l| !l.is_empty())
+    {
+        let label =
+            Label::decode(label).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+        meta.labels.insert(label);
+    }
+    meta.set_offsets(&header("x-mauve-offsets-inclusive"))
+        .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+    // A weak tag is for a compressed body, not the stored bytes the hash describes
+    if let Some(hash) = header("etag").strip_prefix('"') {
+        meta.content_hash = hash.trim_end_matches('"').to_string();
+    }
+    meta.created_at = header("x-mauve-created-at").parse().unwrap_or_default();
+    meta.updated_at = header("x-mauve-updated-at").parse().unwrap_or_default();
+    for (name, value) in headers {
+        if let Some(name) = name.as_str().strip_prefix("x-mauve-meta-") {
+            let value = value
+                .to_str()
+                .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+            meta.extra.insert(name.to_string(), value.to_string());
+        }
+    }
+    Ok(meta)
+}
+
+async fn send(request: RequestBuilder) -> Result<Response, ClientError> {
+    check(request.send().await?).await
+}
+
+/// Turn a non-2xx response into an `ApiError` carrying the server's code and message
+async fn check(res: Response) -> Result<Response, ClientError> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let text = res.text().await.unwrap_or_default();
+    let (code, message) = match serde_json::from_str::<ErrorBody>(&text) {
+        Ok(body) => (body.code, body.error),
+        Err(_) => (String::new(), text),
+    };
+    Err(ClientError::ApiError {
+        status,
+        code,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mc6_backend::{
+        api::mauve_rocket,
+        backend::Backend,
+        config::AppConfig,
+        labels::Label,
+        meta::{etag, Metadata},
+        search::{SearchRequest, SearchResults},
+    };
+    use reqwest::StatusCode;
+    use rocket::fairing::AdHoc;
+
+    use super::{ClientError, MauveClient};
+
+    #[rocket::async_test]
+    async fn test_client() -> anyhow::Result<()> {
+        let mut config = AppConfig::default();
+        config.sled.path =
+            std::env::temp_dir().join(format!("mauve-test-{}", rand::random::<u64>()));
+        let backend = Backend::open(config.clone())?;
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let rocket = mauve_rocket(backend.clone(), config.clone())
+            .configure(rocket::Config {
+                port: 0,
+                ..rocket::Config::debug_default()
+            })
+            .attach(AdHoc::on_liftoff("port", |rocket| {
+                Box::pin(async move {
+                    let _ = tx.send(rocket.config().port);
+                })
+            }));
+        let shutdown = rocket.ignite().await?;
+        let handle = shutdown.shutdown();
+        tokio::spawn(shutdown.launch());
+        let port = rx.await?;
+
+        // The indexer only sees writes made once it is watching the collection
+        backend.get_collection("things")?;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let client = MauveClient::new(&format!("http://127.0.0.1:{port}"))?;
+        let mut meta = Metadata {
+            content_type: "text/plain".to_string(),
+            segments: vec![(0, 1), (2, 4)],
+            offset_map: "tail=3-4".to_string(),
+            ..Default::default()
+        };
+        meta.labels.insert(Label::new("color", "mauve"));
+        meta.extra.insert("owner".to_string(), "mauve".to_string());
+        client
+            .put_object("things", "a/b", b"hello".to_vec(), &meta)
+            .await?;
+
+        let (object, got) = client.get_object("things", "a/b").await?;
+        assert_eq!(object, b"hello");
+        assert_eq!(got.content_type, "text/plain");
+        assert_eq!(got.labels, meta.labels);
+        assert_eq!(got.segments, meta.segments);
+        assert_eq!(got.offset_map, meta.offset_map);
+        assert_eq!(got.extra, meta.extra);
+        assert_eq!(got.size, 5);
+        assert!(got.created_at > 0);
+        assert_eq!(got.updated_at, got.created_at);
+        assert_eq!(got.content_hash, etag(b"hello"));
+
+        let head = client.head_object("things", "a/b").await?;
+        assert_eq!(
+            head.map(|meta| (meta.size, meta.content_hash)),
+            Some((5, etag(b"hello")))
+        );
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let mut req = SearchRequest::new("things");
+        req.include(Label::new("color", "mauve"));
+        match client.search(&req).await?.result {
+            Ok(SearchResults::Objects(found)) => assert_eq!(found.len(), 1),
+            other => panic!("expected one object, got {other:?}"),
+        }
+        let stats = client.describe("things").await?;
+        assert_eq!((stats.objects, stats.bytes), (1, 5));
+        assert_eq!(client.list_objects("things", Some("a/")).await?, ["a/b"]);
+        assert!(client
+            .list_collections()
+            .await?
+            .contains(&"things".to_string()));
+
+        client.delete_object("things", "a/b").await?;
+        assert!(client.head_object("things", "a/b").await?.is_none());
+        match client.get_object("things", "a/b").await {
+            Err(ClientError::ApiError { status, code, .. }) => {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(code, "ObjectNotFound");
+            }
+            other => panic!("expected a 404, got {other:?}"),
+        }
+
+        handle.notify();
+        std::fs::remove_dir_all(config.sled.path)?;
+        Ok(())
+    }
+}
@@ -1,34 +1,117 @@
 use proc_macro::TokenStream;
 use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, LitStr};
 
 /// Implements the necessary functions to store a `T` in Mauve.
 ///
-/// Requires: `Serialize + for<'de> Deserialize<'de>`
-#[proc_macro_derive(MauveObject)]
+/// Requires: `Serialize + for<'de> Deserialize<'de>`. Generic types keep their parameters
+/// and bounds, which have to be enough for that to hold.
+///
+/// Any struct or enum serde can derive for works, whether its fields are named, a tuple or
+/// absent, and enums may be `#[non_exhaustive]` or carry data. Unions are rejected, as
+/// serde has no derive for them.
+///
+/// Objects are stored as CBOR unless `#[mauve(format = "...")]` picks `json` or `bincode`.
+/// The crate deriving needs a dependency on the format's crate.
+#[proc_macro_derive(MauveObject, attributes(mauve))]
 pub fn mauve_object_derive(input: TokenStream) -> TokenStream {
-    let ast = syn::parse(input).unwrap();
-    impl_mauve_object(&ast)
+    let ast = parse_macro_input!(input as DeriveInput);
+    match impl_mauve_object(&ast) {
+        Ok(gen) => gen.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Serialization format of a `MauveObject`
+enum Format {
+    Cbor,
+    Json,
+    Bincode,
 }
 
-fn impl_mauve_object(ast: &syn::DeriveInput) -> TokenStream {
+impl Format {
+    /// The format named by the `mauve` attribute, or CBOR without one
+    fn parse(ast: &DeriveInput) -> syn::Result<Self> {
+        let mut format = Self::Cbor;
+        for attr in ast
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("mauve"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("format") {
+                    return Err(meta.error("unsupported mauve attribute, expected `format`"));
+                }
+                let value: LitStr = meta.value()?.parse()?;
+                format = match value.value().as_str() {
+                    "cbor" => Self::Cbor,
+                    "json" => Self::Json,
+                    "bincode" => Self::Bincode,
+                    other => {
+                        return Err(syn::Error::new(
+                            value.span(),
+                            format!("unknown format {other:?}, expected cbor, json or bincode"),
+                        ))
+                    }
+                };
+                Ok(())
+            })?;
+        }
+        Ok(format)
+    }
+}
+
+fn impl_mauve_object(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if let Data::Union(union) = &ast.data {
+        return Err(syn::Error::new(
+            union.union_token.span,
+            "MauveObject can't be derived for unions",
+        ));
+    }
     let name = &ast.ident;
-    let gen = quote! {
-        impl ToFromMauve for #name {
-            fn to_object(&self) -> Result<Vec<u8>, MauveError> {
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let (to_object, from_object) = match Format::parse(ast)? {
+        Format::Cbor => (
+            quote! {
                 let mut writer = vec![];
                 ciborium::into_writer(&self, &mut writer)
                     .map_err(|e| MauveError::CborError(e.to_string()))?;
                 Ok(writer)
-            }
-
-            fn from_object(b: Vec<u8>) -> Result<#name, MauveError> {
+            },
+            quote! {
                 use std::io::BufReader;
                 let reader = BufReader::new(&*b);
                 let res = ciborium::from_reader(reader)
                     .map_err(|e| MauveError::CborError(e.to_string()))?;
                 Ok(res)
+            },
+        ),
+        Format::Json => (
+            quote! {
+                serde_json::to_vec(&self).map_err(|e| MauveError::JsonError(e.to_string()))
+            },
+            quote! {
+                serde_json::from_slice(&b).map_err(|e| MauveError::JsonError(e.to_string()))
+            },
+        ),
+        Format::Bincode => (
+            quote! {
+                bincode::serialize(&self).map_err(|e| MauveError::BincodeError(e.to_string()))
+            },
+            quote! {
+                bincode::deserialize(&b).map_err(|e| MauveError::BincodeError(e.to_string()))
+            },
+        ),
+    };
+    Ok(quote! {
+        impl #impl_generics ToFromMauve for #name #ty_generics #where_clause {
+            fn to_object(&self) -> Result<Vec<u8>, MauveError> {
+                #to_object
+            }
+
+            fn from_object(b: Vec<u8>) -> Result<Self, MauveError> {
+                #from_object
             }
         }
-    };
-    gen.into()
+    })
 }